@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use tonic::codec::CompressionEncoding;
+
+use sf_auto_focus::sound_flow::DeviceDirection;
+
+/// SoundFlow feedback client: loops the server's capture back to its speaker, or controls its devices.
+///
+/// Options with an environment variable named next to them can be set through it as well.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Config {
+    /// Server to connect to, `https://` when it serves TLS.
+    #[arg(long, env = "SF_SERVER", default_value = "http://[::1]:50051")]
+    pub server: String,
+
+    /// Connect to a server started with --socket at this path instead of --server.
+    #[arg(long, env = "SF_SOCKET", value_name = "PATH", conflicts_with_all = ["server", "ca_cert"])]
+    pub socket: Option<PathBuf>,
+
+    /// PEM CA certificate to trust the server's certificate with.
+    #[arg(long, env = "SF_CA_CERT")]
+    pub ca_cert: Option<PathBuf>,
+
+    /// PEM certificate to authenticate with when the server requires mutual TLS, requires --client-key.
+    #[arg(long, env = "SF_CLIENT_CERT", requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key belonging to --client-cert.
+    #[arg(long, env = "SF_CLIENT_KEY", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Bearer token to present on every call, for a server started with --auth-token.
+    #[arg(long, env = "SF_AUTH_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// Longest wait in milliseconds between reconnection attempts, the wait doubles from 500 ms
+    /// up to this after each failure.
+    #[arg(long, env = "SF_MAX_BACKOFF_MS", default_value_t = 30_000)]
+    pub max_backoff_ms: u64,
+
+    /// Print the server's version, advertised address and what it supports, and exit. A quick way
+    /// to check it is reachable before streaming.
+    #[arg(long, conflicts_with_all = ["list_devices", "current_device", "set_device", "play_file", "record"])]
+    pub server_info: bool,
+
+    /// Print the server's devices and exit.
+    #[arg(long, conflicts_with_all = ["set_device", "play_file", "current_device"])]
+    pub list_devices: bool,
+
+    /// Print the server's current capture device with `--direction capture`, the playback device
+    /// otherwise, and exit.
+    #[arg(long, conflicts_with_all = ["set_device", "play_file"])]
+    pub current_device: bool,
+
+    /// Make the device with this id the server's default and exit.
+    #[arg(long, value_name = "ID", conflicts_with = "play_file")]
+    pub set_device: Option<u32>,
+
+    /// Which devices --list-devices prints and which kind --set-device and --current-device use.
+    #[arg(long, value_enum, default_value_t = Direction::All)]
+    pub direction: Direction,
+
+    /// Play this WAV file on the server's speaker at real-time speed instead of looping capture back.
+    #[arg(long, env = "SF_PLAY_FILE")]
+    pub play_file: Option<PathBuf>,
+
+    /// Play this WAV file on the server's speaker over and over at real-time speed until Ctrl-C,
+    /// logging the frames sent every second, to soak test the server. With --record, what the
+    /// server captures meanwhile is recorded too.
+    #[arg(long, env = "SF_LOOP_FILE", value_name = "FILE", conflicts_with_all = ["server_info", "list_devices", "current_device", "set_device", "play_file", "test_tone"])]
+    pub loop_file: Option<PathBuf>,
+
+    /// Play a one-second sine at this frequency on the server's speaker and exit, to check the
+    /// speaker works before streaming anything.
+    #[arg(long, value_name = "HZ", conflicts_with_all = ["list_devices", "current_device", "set_device", "play_file", "record"])]
+    pub test_tone: Option<f32>,
+
+    /// While looping capture back or --loop-file, also write the frames received to this 32-bit
+    /// float WAV file, to keep evidence of glitches. Finalized on Ctrl-C.
+    #[arg(long, env = "SF_RECORD", value_name = "FILE", conflicts_with_all = ["list_devices", "current_device", "set_device", "play_file"])]
+    pub record: Option<PathBuf>,
+
+    /// How frames sent to the server are compressed. The server must accept it, and by default
+    /// accepts gzip and zstd. Frames from the server are accepted in either.
+    #[arg(long, env = "SF_COMPRESSION", value_enum, default_value_t = Compression::None)]
+    pub compression: Compression,
+}
+
+/// gRPC message compression, see the server's --send-compression for the tradeoffs.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Compression {
+    /// Send uncompressed.
+    None,
+    /// Send gzip compressed.
+    Gzip,
+    /// Send zstd compressed.
+    Zstd,
+}
+
+impl Config {
+    /// What to call the server in messages, --socket or --server.
+    pub fn address(&self) -> String {
+        match &self.socket {
+            Some(path) => format!("unix:{}", path.display()),
+            None => self.server.clone(),
+        }
+    }
+}
+
+impl Compression {
+    pub fn encoding(self) -> Option<CompressionEncoding> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(CompressionEncoding::Gzip),
+            Compression::Zstd => Some(CompressionEncoding::Zstd),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Direction {
+    /// Playback and capture devices; --set-device treats the id as a playback device.
+    All,
+    /// Sinks, e.g. speakers and headphones.
+    Playback,
+    /// Sources, e.g. microphones.
+    Capture,
+}
+
+impl From<Direction> for DeviceDirection {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::All => DeviceDirection::All,
+            Direction::Playback => DeviceDirection::Playback,
+            Direction::Capture => DeviceDirection::Capture,
+        }
+    }
+}
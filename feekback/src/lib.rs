@@ -0,0 +1,58 @@
+//! The pieces of the SoundFlow client that other Rust apps can build on: the generated gRPC
+//! client, the interceptor that authenticates it, and adapters that read and write Flow streams
+//! as raw PCM through `tokio::io`.
+
+use tonic::{Request, Status};
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::metadata::errors::InvalidMetadataValue;
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+
+use crate::sound_flow::AudioFormat;
+use crate::sound_flow::sound_flow_client::SoundFlowClient;
+
+pub use crate::pcm::{FlowReader, FlowWriter};
+
+mod pcm;
+
+pub mod sound_flow {
+    tonic::include_proto!("sound_flow");
+}
+
+/// A client that sends a token with every request.
+pub type Client = SoundFlowClient<InterceptedService<Channel, Token>>;
+
+const SAMPLE_RATE_HEADER: &str = "sf-sample-rate"; // get_flow response metadata
+const CHANNELS_HEADER: &str = "sf-channels";
+
+/// Adds a bearer token to every request, if there is one.
+#[derive(Clone)]
+pub struct Token(Option<AsciiMetadataValue>);
+
+impl Token {
+    /// Fails if `token` isn't printable ASCII.
+    pub fn new(token: Option<&str>) -> Result<Self, InvalidMetadataValue> {
+        Ok(Token(token.map(|token| format!("Bearer {}", token).parse()).transpose()?))
+    }
+}
+
+impl Interceptor for Token {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// The sample rate and channels of a get_flow stream, from its response metadata, if the
+/// server sent them.
+pub fn flow_format(metadata: &MetadataMap) -> Option<AudioFormat> {
+    let number = |key: &str| metadata.get(key)?.to_str().ok()?.parse().ok();
+    Some(AudioFormat {
+        sample_rate: number(SAMPLE_RATE_HEADER)?,
+        channels: number(CHANNELS_HEADER)?,
+        ..Default::default()
+    })
+}
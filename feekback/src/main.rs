@@ -1,32 +1,364 @@
-use std::time::Duration;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use clap::Parser;
+use hound::{SampleFormat, WavReader, WavSpec};
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
 
-use crate::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_auto_focus::{flow_format, Client, Token};
+use sf_auto_focus::sound_flow::{AudioFormat, Codec, DeviceDirection, DeviceId, Direction, Flow, FlowRequest, SampleFormat as WireFormat, ServerInfo, TestTone};
+use sf_auto_focus::sound_flow::sound_flow_client::SoundFlowClient;
 
-pub mod sound_flow {
-    tonic::include_proto!("sound_flow");
-}
+use crate::config::Config;
+use crate::record::Recorder;
+
+mod config;
+mod record;
+
+const PACKAGE_SIZE: usize = 1000; // samples per Flow frame when playing a file, the server's default
+const PROTOCOL: u32 = 1; // the ServerInfo.protocol this client speaks
+const TEST_TONE_AMPLITUDE: f32 = 0.25; // -12 dBFS, audible without startling anyone
+const TEST_TONE_MS: u32 = 1000;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500); // first wait before reconnecting, doubled after each failure
+const LOOP_REPORT: Duration = Duration::from_secs(1); // between --loop-file's logs of the frames sent
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = SoundFlowClient::connect("http://[::1]:50051").await?;
-    let (tx, rx) = tokio::sync::mpsc::channel(128);
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config = Config::parse();
+    if !(config.server_info || config.list_devices || config.current_device || config.set_device.is_some() || config.play_file.is_some() || config.loop_file.is_some() || config.test_tone.is_some()) {
+        return feedback(&config).await;
+    }
+    let mut client = connect(&config).await?;
+    if config.server_info {
+        return server_info(&mut client).await;
+    }
+    handshake(&mut client, config.token.is_some()).await?;
+    if config.list_devices {
+        return list_devices(&mut client, config.direction.into()).await;
+    }
+    if config.current_device {
+        let device = client.get_current_device(Direction { direction: DeviceDirection::from(config.direction).into() }).await?.into_inner();
+        println!("{}  {}", device.id, device.name);
+        return Ok(());
+    }
+    if let Some(id) = config.set_device {
+        let direction: DeviceDirection = config.direction.into();
+        client.set_device(DeviceId { id, direction: direction.into() }).await?;
+        println!("default {} device is now {}", if direction == DeviceDirection::Capture { "capture" } else { "playback" }, id);
+        return Ok(());
+    }
+    if let Some(path) = &config.play_file {
+        return play_file(&mut client, path).await;
+    }
+    if let Some(path) = &config.loop_file {
+        return loop_file(&mut client, path, config.record.clone()).await;
+    }
+    if let Some(frequency_hz) = config.test_tone {
+        println!("playing a {} Hz test tone", frequency_hz);
+        client.play_test_tone(TestTone { frequency_hz, amplitude: TEST_TONE_AMPLITUDE, duration_ms: TEST_TONE_MS }).await?;
+        return Ok(());
+    }
+    Ok(())
+}
 
+/// Loops the server's capture back to its speaker until Ctrl-C, reconnecting with exponential
+/// backoff whenever the connection fails or the server ends the flow, and recording what it
+/// receives with --record.
+async fn feedback(config: &Config) -> Result<(), Box<dyn Error>> {
     println!("*** SIMPLE FEEDBACK ***");
-    let response = client
-        .get_flow(()).await?;
+    let mut recorder = config.record.clone().map(Recorder::new);
+    let max_backoff = Duration::from_millis(config.max_backoff_ms);
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let forwarded = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            forwarded = forward(config, recorder.as_mut()) => forwarded,
+        };
+        match forwarded {
+            Ok(true) => {
+                eprintln!("flow ended, reconnecting");
+                backoff = INITIAL_BACKOFF; // it worked for a while, so retry quickly
+            }
+            Ok(false) => eprintln!("server closed the flow right away, reconnecting in {:?}", backoff),
+            Err(e) if e.is::<Fatal>() => return Err(e),
+            Err(e) => eprintln!("connection to {} failed: {}, reconnecting in {:?}", config.address(), e, backoff),
+        }
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(max_backoff);
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
+    println!("feedback stopped");
+    Ok(())
+}
+
+/// Connects and forwards frames from get_flow to send_flow until either side ends, announcing
+/// the capture format get_flow reports so the server converts it for its speaker. Every frame
+/// also goes to `recorder`, if there is one. Returns whether any frame made it through.
+async fn forward(config: &Config, mut recorder: Option<&mut Recorder>) -> Result<bool, Box<dyn Error>> {
+    let mut client = connect(config).await?;
+    handshake(&mut client, config.token.is_some()).await?;
+    let response = client.get_flow(FlowRequest::default()).await?;
+    let format = flow_format(response.metadata());
+    if let Some(format) = &format {
+        client.negotiate_format(format.clone()).await?;
+    }
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.connected(format.as_ref());
+    }
     let mut flow = response.into_inner();
-    tokio::spawn(async move {
-        loop {
-            if let Some(Ok(value)) = flow.next().await {
-                tx.send(value).await.unwrap();
+    let (tx, rx) = tokio::sync::mpsc::channel(128);
+    // The server answers send_flow once the flow has ended, so it runs alongside the forwarding.
+    let sending = tokio::spawn(async move { client.send_flow(ReceiverStream::new(rx)).await });
+    eprintln!("connected to {}", config.address());
+    let mut forwarded = false;
+    while let Some(value) = flow.next().await {
+        let value = value?;
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.write(&value); // a buffered write, too quick to hold up forwarding
+        }
+        if tx.send(value).await.is_err() {
+            break; // the server stopped taking our flow
+        }
+        forwarded = true;
+    }
+    drop(tx);
+    sending.await??;
+    Ok(forwarded)
+}
+
+/// A problem reconnecting won't fix, so feedback stops instead of retrying.
+#[derive(Debug)]
+struct Fatal(String);
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Fatal {}
+
+/// Asks the server what it is and supports before anything else, failing with `Fatal` if it
+/// can't serve this client: an older server, another protocol version, no raw f32 frames, or a
+/// missing or wrong token (`with_token` says which).
+async fn handshake(client: &mut Client, with_token: bool) -> Result<ServerInfo, Box<dyn Error>> {
+    let info = match client.get_server_info(()).await {
+        Ok(info) => info.into_inner(),
+        Err(status) if status.code() == Code::Unimplemented => {
+            return Err(Fatal("the server is too old for this client, it doesn't answer GetServerInfo".to_string()).into());
+        }
+        Err(status) if status.code() == Code::Unauthenticated => {
+            let why = if with_token { "the server rejected --token" } else { "the server requires a token, pass --token" };
+            return Err(Fatal(why.to_string()).into());
+        }
+        Err(status) => return Err(status.into()),
+    };
+    if info.protocol != PROTOCOL {
+        return Err(Fatal(format!("the server (sf_core {}) speaks protocol {}, this client {}", info.version, info.protocol, PROTOCOL)).into());
+    }
+    if !info.codecs().any(|codec| codec == Codec::Raw) || !info.sample_formats().any(|format| format == WireFormat::F32) {
+        return Err(Fatal(format!("the server (sf_core {}) doesn't offer raw f32 frames", info.version)).into());
+    }
+    Ok(info)
+}
+
+/// Connects to --server, trusting --ca-cert and presenting --client-cert for an `https://` server,
+/// and authenticating with --token. The client sends in --compression and accepts whatever
+/// compression the server answers in.
+async fn connect(config: &Config) -> Result<Client, Box<dyn Error>> {
+    let channel = match &config.socket {
+        Some(path) => connect_socket(path.clone()).await?,
+        None => endpoint(config)?.connect().await?,
+    };
+    let token = Token::new(config.token.as_deref()).map_err(|_| "--token must be printable ASCII")?;
+    let mut client = SoundFlowClient::with_interceptor(channel, token)
+        .accept_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Zstd);
+    if let Some(encoding) = config.compression.encoding() {
+        client = client.send_compressed(encoding);
+    }
+    Ok(client)
+}
+
+/// --server, with TLS when there is a --ca-cert.
+fn endpoint(config: &Config) -> Result<Endpoint, Box<dyn Error>> {
+    let mut endpoint = Channel::from_shared(config.server.clone())?;
+    if let Some(ca) = &config.ca_cert {
+        let mut tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+        if let (Some(cert), Some(key)) = (&config.client_cert, &config.client_key) {
+            tls = tls.identity(Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?));
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    }
+    Ok(endpoint)
+}
+
+/// A channel over the Unix domain socket at `path`. The URI is only there to make an endpoint of,
+/// every connection goes to the socket.
+#[cfg(unix)]
+async fn connect_socket(path: PathBuf) -> Result<Channel, Box<dyn Error>> {
+    let connector = tower::service_fn(move |_: tonic::transport::Uri| tokio::net::UnixStream::connect(path.clone()));
+    Ok(Endpoint::from_static("http://[::]:50051").connect_with_connector(connector).await?)
+}
+
+#[cfg(not(unix))]
+async fn connect_socket(path: PathBuf) -> Result<Channel, Box<dyn Error>> {
+    Err(format!("can't connect to {}, this platform has no Unix domain sockets", path.display()).into())
+}
+
+/// Prints what GetServerInfo says about the server.
+async fn server_info(client: &mut Client) -> Result<(), Box<dyn Error>> {
+    let info = client.get_server_info(()).await?.into_inner();
+    let codecs: Vec<_> = info.codecs().map(|codec| if codec == Codec::Opus { "opus" } else { "raw" }).collect();
+    let sample_formats: Vec<_> = info.sample_formats().map(|format| format.as_str_name().to_lowercase()).collect();
+    println!("version: {}", info.version);
+    println!("address: {}", info.advertised_address);
+    println!("codecs: {}", codecs.join(", "));
+    println!("sample formats: {}", sample_formats.join(", "));
+    println!("accepts compression: {}", if info.accept_compression.is_empty() { "none".to_string() } else { info.accept_compression.join(", ") });
+    Ok(())
+}
+
+/// Prints the server's devices in `direction` as a numbered table.
+async fn list_devices(client: &mut Client, direction: DeviceDirection) -> Result<(), Box<dyn Error>> {
+    let devices = client.get_devices(Direction { direction: direction.into() }).await?.into_inner().devices;
+    println!("{:>3}  {:>6}  {:<9}  name", "#", "id", "direction");
+    for (number, device) in (1..).zip(&devices) {
+        let direction = match device.direction() {
+            DeviceDirection::Capture if device.monitor => "monitor",
+            DeviceDirection::Capture => "capture",
+            _ => "playback",
+        };
+        println!("{:>3}  {:>6}  {:<9}  {}", number, device.id, direction, device.name);
+    }
+    Ok(())
+}
+
+/// The samples of the WAV file at `path` as f32, with its format.
+fn read_wav(path: &Path) -> Result<(Vec<f32>, WavSpec), Box<dyn Error>> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|sample| sample.map(|sample| sample as f32 / scale)).collect::<Result<_, _>>()?
+        }
+    };
+    Ok((samples, spec))
+}
+
+/// Announces `spec` as the format of what is sent next, and returns the size of the frames to
+/// send it in, whole frames only, and how long each of them plays.
+async fn negotiate(client: &mut Client, spec: &WavSpec) -> Result<(usize, Duration), Box<dyn Error>> {
+    client.negotiate_format(AudioFormat {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels.into(),
+        ..Default::default()
+    }).await?;
+    let channels = spec.channels as usize;
+    let package_size = (PACKAGE_SIZE / channels).max(1) * channels;
+    Ok((package_size, Duration::from_secs_f64((package_size / channels) as f64 / spec.sample_rate as f64)))
+}
+
+/// Plays the WAV file at `path` on the server's speaker, sending it at real-time speed so it goes
+/// through the same jitter buffer and pacing as a live stream.
+async fn play_file(client: &mut Client, path: &Path) -> Result<(), Box<dyn Error>> {
+    let (samples, spec) = read_wav(path)?;
+    let (package_size, period) = negotiate(client, &spec).await?;
+    let channels = spec.channels as usize;
+    println!("playing {} ({} Hz, {} channel(s), {:.1} s)", path.display(), spec.sample_rate, channels,
+             (samples.len() / channels) as f64 / spec.sample_rate as f64);
+    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    let feeder = tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(period);
+        for (seq, chunk) in (1..).zip(samples.chunks(package_size)) {
+            ticks.tick().await;
+            if tx.send(Flow { flow: chunk.to_vec(), seq, ..Default::default() }).await.is_err() {
+                break;
             }
         }
     });
     client.send_flow(ReceiverStream::new(rx)).await?;
-    loop {
-        tokio::time::sleep(Duration::from_secs(10)).await
+    feeder.await?;
+    Ok(())
+}
+
+/// Plays the WAV file at `path` on the server's speaker over and over, at real-time speed, until
+/// Ctrl-C, logging the frames sent every LOOP_REPORT. With `record`, what the server captures is
+/// recorded there meanwhile, to check what went round came back intact.
+async fn loop_file(client: &mut Client, path: &Path, record: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let (samples, spec) = read_wav(path)?;
+    if samples.is_empty() {
+        return Err(format!("{} holds no samples to loop", path.display()).into());
+    }
+    let (package_size, period) = negotiate(client, &spec).await?;
+    let (stop_recording, mut stopped) = tokio::sync::oneshot::channel::<()>();
+    let recording = match record {
+        Some(record) => {
+            let response = client.get_flow(FlowRequest::default()).await?;
+            let mut recorder = Recorder::new(record);
+            recorder.connected(flow_format(response.metadata()).as_ref());
+            let mut flow = response.into_inner();
+            Some(tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = &mut stopped => break,
+                        value = flow.next() => match value {
+                            Some(Ok(value)) => recorder.write(&value),
+                            _ => break,
+                        },
+                    }
+                }
+                recorder
+            }))
+        }
+        None => None,
+    };
+    let seconds = (samples.len() / spec.channels as usize) as f64 / spec.sample_rate as f64;
+    println!("looping {} ({} Hz, {} channel(s), {:.1} s) until Ctrl-C", path.display(), spec.sample_rate, spec.channels, seconds);
+    let (tx, rx) = tokio::sync::mpsc::channel(8);
+    let feeder = tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(period);
+        let (mut reported, mut reported_at) = (0, Instant::now());
+        for seq in 1u64.. {
+            ticks.tick().await;
+            // Frames run on across the end of the file, which holds whole frames, so they stay in step.
+            let start = (seq - 1) as usize * package_size;
+            let frame = (start..start + package_size).map(|i| samples[i % samples.len()]).collect();
+            if tx.send(Flow { flow: frame, seq, ..Default::default() }).await.is_err() {
+                break;
+            }
+            let elapsed = reported_at.elapsed();
+            if elapsed >= LOOP_REPORT {
+                let round = start / samples.len() + 1;
+                eprintln!("{:.1} frames/s, {} sent, round {}", (seq - reported) as f64 / elapsed.as_secs_f64(), seq, round);
+                (reported, reported_at) = (seq, Instant::now());
+            }
+        }
+    });
+    // The server answers once it stops taking frames, so the loop runs on until then or Ctrl-C.
+    let sent = tokio::select! {
+        sent = client.send_flow(ReceiverStream::new(rx)) => sent.map(|_| ()),
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    feeder.abort();
+    if let Some(recording) = recording {
+        let _ = stop_recording.send(());
+        recording.await?.finish();
     }
-}
\ No newline at end of file
+    println!("loop stopped");
+    sent?;
+    Ok(())
+}
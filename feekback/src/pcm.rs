@@ -0,0 +1,142 @@
+//! Flow streams as raw PCM: interleaved little-endian f32 samples, with no header and nothing
+//! between frames, so they can go through `tokio::io::copy` and friends. The bytes carry no
+//! format: a get_flow stream is in the server's capture format, which `flow_format` reads from
+//! its response metadata, and what is written to send_flow should be in the format given to
+//! NegotiateFormat first.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+use tonic::Status;
+
+use crate::sound_flow::{Encoding, Flow};
+
+const SAMPLE: usize = std::mem::size_of::<f32>(); // bytes
+
+/// Reads the frames of a get_flow stream as PCM bytes, frame after frame, until the stream ends.
+/// Frames sent as raw f32, or tagged as packed i16 or u16, are read as f32; opus frames and ones
+/// packed without a tag can't be, so ask get_flow for the default raw f32.
+pub struct FlowReader<S> {
+    flows: S,
+    pending: Vec<u8>, // what is left of the last frame
+    read: usize, // bytes of `pending` read already
+}
+
+impl<S: Stream<Item = Result<Flow, Status>> + Unpin> FlowReader<S> {
+    /// Reads `flows`, such as the `Streaming<Flow>` a get_flow response holds.
+    pub fn new(flows: S) -> Self {
+        FlowReader { flows, pending: Vec::new(), read: 0 }
+    }
+}
+
+impl<S: Stream<Item = Result<Flow, Status>> + Unpin> AsyncRead for FlowReader<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        while self.read == self.pending.len() {
+            let Some(flow) = ready!(Pin::new(&mut self.flows).poll_next(cx)) else {
+                return Poll::Ready(Ok(())); // the end of the stream
+            };
+            let flow = flow.map_err(io::Error::other)?;
+            self.pending = pcm(flow)?;
+            self.read = 0;
+        }
+        let read = self.read;
+        let n = buf.remaining().min(self.pending.len() - read);
+        buf.put_slice(&self.pending[read..read + n]);
+        self.read += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The samples of `flow` as PCM bytes.
+fn pcm(flow: Flow) -> io::Result<Vec<u8>> {
+    if flow.payload.is_empty() {
+        return Ok(flow.flow.iter().flat_map(|sample| sample.to_le_bytes()).collect());
+    }
+    let packed = |scale: fn([u8; 2]) -> f32| flow.payload.chunks_exact(2).flat_map(|pair| scale([pair[0], pair[1]]).to_le_bytes()).collect();
+    match flow.encoding() {
+        Encoding::F32 => Ok(flow.payload),
+        Encoding::I16 => Ok(packed(|pair| i16::from_le_bytes(pair) as f32 / 32768.0)),
+        Encoding::U16 => Ok(packed(|pair| (u16::from_le_bytes(pair) as f32 - 32768.0) / 32768.0)),
+        encoding => Err(io::Error::new(io::ErrorKind::InvalidData, format!("a frame encoded as {} can't be read as PCM", encoding.as_str_name()))),
+    }
+}
+
+/// Writes PCM bytes as the frames of a send_flow stream, `package_size` samples each and
+/// numbered from 1. A frame is sent once it is full or on a flush, and the stream ends on
+/// shutdown. Nothing paces the frames, so write at real-time speed, as a capture would: the
+/// server drops what comes in faster than its --max-ingest-speed.
+pub struct FlowWriter {
+    flows: PollSender<Flow>,
+    package_size: usize, // samples, a whole number of frames of the negotiated channels
+    samples: Vec<f32>, // of the next frame
+    partial: Vec<u8>, // the bytes of a sample written only in part
+    seq: u64,
+}
+
+impl FlowWriter {
+    /// A writer and the stream to hand to send_flow. Up to `capacity` frames wait for the server
+    /// to take them before writes wait too.
+    pub fn new(package_size: usize, capacity: usize) -> (Self, ReceiverStream<Flow>) {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let package_size = package_size.max(1);
+        let writer = FlowWriter { flows: PollSender::new(tx), package_size, samples: Vec::with_capacity(package_size), partial: Vec::new(), seq: 0 };
+        (writer, ReceiverStream::new(rx))
+    }
+
+    /// Sends the samples written so far as a frame, once there is room for it.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.samples.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.flows.poll_reserve(cx)).map_err(|_| closed())?;
+        self.seq += 1;
+        let samples = std::mem::replace(&mut self.samples, Vec::with_capacity(self.package_size));
+        self.flows.send_item(Flow { flow: samples, seq: self.seq, ..Default::default() }).map_err(|_| closed())?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The server ended send_flow, or the stream handed to it was dropped.
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the flow is no longer being sent")
+}
+
+impl AsyncWrite for FlowWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.samples.len() == self.package_size {
+            ready!(self.poll_send(cx))?;
+        }
+        // Only what fits in the frame being filled, so a write never has to wait halfway.
+        let room = (self.package_size - self.samples.len()) * SAMPLE - self.partial.len();
+        let written = &buf[..buf.len().min(room)];
+        let this = &mut *self;
+        this.partial.extend_from_slice(written);
+        let whole = this.partial.len() / SAMPLE * SAMPLE;
+        this.samples.extend(this.partial[..whole].chunks_exact(SAMPLE).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])));
+        this.partial.drain(..whole);
+        Poll::Ready(Ok(written.len()))
+    }
+
+    /// Sends the samples written so far as a frame, shorter than `package_size` if it isn't
+    /// full yet.
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_send(cx)
+    }
+
+    /// Flushes and ends the stream, failing if bytes short of a whole sample are left over.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_send(cx))?;
+        self.flows.close();
+        if !self.partial.is_empty() {
+            let left = std::mem::take(&mut self.partial).len();
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} bytes left over, short of a whole sample", left))));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use sf_auto_focus::sound_flow::{AudioFormat, Flow};
+
+/// Writes the frames feedback receives to a 32-bit float WAV file, across reconnections, in the
+/// format the first connection reports. Problems stop the recording, never the forwarding.
+pub struct Recorder {
+    path: PathBuf,
+    writer: Option<WavWriter<BufWriter<File>>>,
+    spec: Option<WavSpec>,
+    stopped: bool,
+    seq: u64, // of the last frame on this connection
+    lost: u64, // frames missing from the seq numbers, across connections
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf) -> Self {
+        Recorder { path, writer: None, spec: None, stopped: false, seq: 0, lost: 0 }
+    }
+
+    /// A new connection sends frames in `format`, `None` if the server didn't say.
+    pub fn connected(&mut self, format: Option<&AudioFormat>) {
+        self.seq = 0; // every get_flow numbers its frames from 1
+        if self.stopped {
+            return;
+        }
+        let Some(format) = format else {
+            return self.stop("the server didn't report its capture format");
+        };
+        let spec = WavSpec {
+            channels: format.channels as u16,
+            sample_rate: format.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        match self.spec {
+            Some(recording) if recording != spec => self.stop(&format!(
+                "the capture format changed from {} Hz, {} channel(s) to {} Hz, {} channel(s)",
+                recording.sample_rate, recording.channels, spec.sample_rate, spec.channels)),
+            Some(_) => {}
+            None => match WavWriter::create(&self.path, spec) {
+                Ok(writer) => {
+                    eprintln!("recording to {}", self.path.display());
+                    self.writer = Some(writer);
+                    self.spec = Some(spec);
+                }
+                Err(e) => self.stop(&format!("failed to create it: {}", e)),
+            },
+        }
+    }
+
+    /// Appends the samples of `flow`.
+    pub fn write(&mut self, flow: &Flow) {
+        if flow.seq > self.seq + 1 && flow.seq != 0 {
+            self.lost += flow.seq - self.seq - 1;
+        }
+        self.seq = flow.seq;
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        if let Err(e) = flow.flow.iter().try_for_each(|&sample| writer.write_sample(sample)) {
+            self.stop(&format!("failed to write: {}", e));
+        }
+    }
+
+    /// Finalizes the file, so its header gives the right length.
+    pub fn finish(mut self) {
+        let Some(writer) = self.writer.take() else {
+            return;
+        };
+        let seconds = writer.duration() as f64 / self.spec.map_or(1, |spec| spec.sample_rate) as f64;
+        match writer.finalize() {
+            Ok(()) => eprintln!("recorded {:.1} s to {}, {} frame(s) lost on the way", seconds, self.path.display(), self.lost),
+            Err(e) => eprintln!("failed to finalize {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn stop(&mut self, why: &str) {
+        eprintln!("recording to {} stopped: {}", self.path.display(), why);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.finalize(); // keep what was recorded so far playable
+        }
+        self.stopped = true;
+    }
+}
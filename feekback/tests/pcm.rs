@@ -0,0 +1,62 @@
+//! Reads and writes Flow streams as PCM bytes and checks the samples survive whole, in order
+//! and split into the frames asked for.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+use tonic::{Code, Status};
+
+use sf_auto_focus::{FlowReader, FlowWriter};
+use sf_auto_focus::sound_flow::{Encoding, Flow};
+
+fn bytes(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+}
+
+#[tokio::test]
+async fn frames_read_as_one_run_of_samples() {
+    let flows = [
+        Flow { flow: vec![0.5, -0.5, 0.25], seq: 1, ..Default::default() },
+        Flow { payload: bytes(&[0.125]), encoding: Encoding::F32.into(), seq: 2, ..Default::default() },
+        Flow { payload: i16::MIN.to_le_bytes().to_vec(), encoding: Encoding::I16.into(), seq: 3, ..Default::default() },
+    ];
+    let mut reader = FlowReader::new(tokio_stream::iter(flows.map(Ok)));
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, bytes(&[0.5, -0.5, 0.25, 0.125, -1.0]));
+}
+
+#[tokio::test]
+async fn a_failed_stream_fails_the_read() {
+    let flows = [Ok(Flow { flow: vec![0.5], ..Default::default() }), Err(Status::unavailable("gone"))];
+    let mut reader = FlowReader::new(tokio_stream::iter(flows));
+    let mut read = Vec::new();
+    let error = reader.read_to_end(&mut read).await.unwrap_err();
+    let status = error.into_inner().unwrap().downcast::<Status>().unwrap();
+    assert_eq!(status.code(), Code::Unavailable);
+    assert_eq!(read, bytes(&[0.5]));
+}
+
+#[tokio::test]
+async fn writes_become_numbered_frames_of_the_package_size() {
+    let samples: Vec<f32> = (0..10).map(|i| i as f32).collect();
+    let (mut writer, flows) = FlowWriter::new(4, 8);
+    // Written in pieces that split samples, as a pipe would hand them over.
+    for chunk in bytes(&samples).chunks(3) {
+        writer.write_all(chunk).await.unwrap();
+    }
+    writer.shutdown().await.unwrap();
+    drop(writer);
+    let flows: Vec<Flow> = flows.collect().await;
+    let frames: Vec<&[f32]> = flows.iter().map(|flow| flow.flow.as_slice()).collect();
+    assert_eq!(frames, [&samples[0..4], &samples[4..8], &samples[8..10]]);
+    assert_eq!(flows.iter().map(|flow| flow.seq).collect::<Vec<_>>(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn a_dropped_stream_fails_the_write() {
+    let (mut writer, flows) = FlowWriter::new(1, 1);
+    drop(flows);
+    writer.write_all(&bytes(&[0.5])).await.unwrap();
+    let error = writer.write_all(&bytes(&[0.5])).await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,70 @@
+//! Compares the bytes on the wire for a 10 second clip sent as raw f32 frames and as opus packets.
+//!
+//! Run with `cargo bench --bench codec`.
+
+use std::f32::consts::TAU;
+use std::time::Instant;
+
+use prost::Message;
+
+use crate::codec::OpusEncoder;
+use crate::sound_flow::{AudioFormat, Codec, Flow, SampleFormat};
+
+#[path = "../src/codec.rs"]
+#[allow(dead_code)]
+mod codec;
+
+#[allow(dead_code)]
+mod sound_flow {
+    tonic::include_proto!("sound_flow");
+}
+
+const PACKAGE_SIZE: usize = 1000; // the service's default --package-size
+const CLIP_SECONDS: usize = 10;
+
+fn main() {
+    let format = AudioFormat {
+        sample_rate: 48000,
+        channels: 2,
+        sample_format: SampleFormat::F32.into(),
+        codec: Codec::Opus.into(),
+    };
+    let clip = clip(&format);
+
+    let raw: usize = clip.chunks(PACKAGE_SIZE)
+        .map(|chunk| Flow { flow: chunk.to_vec(), ..Default::default() }.encoded_len())
+        .sum();
+
+    let mut encoder = OpusEncoder::new(&format).expect("failed to create opus encoder");
+    let start = Instant::now();
+    let mut opus = 0;
+    for chunk in clip.chunks(PACKAGE_SIZE) {
+        for payload in encoder.encode(chunk).expect("failed to encode") {
+            opus += Flow { payload, ..Default::default() }.encoded_len();
+        }
+    }
+    let elapsed = start.elapsed();
+
+    println!("{} s of {} Hz, {} channel(s) audio", CLIP_SECONDS, format.sample_rate, format.channels);
+    println!("{:<6} {:>12} {:>10}", "codec", "bytes", "kbit/s");
+    for (name, bytes) in [("raw", raw), ("opus", opus)] {
+        println!("{:<6} {:>12} {:>10.1}", name, bytes, (bytes * 8) as f64 / CLIP_SECONDS as f64 / 1000.0);
+    }
+    println!("opus is {:.1}x smaller, encoding took {:?}", raw as f64 / opus as f64, elapsed);
+}
+
+/// A few seconds of chords with a little noise, closer to real audio than a pure tone.
+fn clip(format: &AudioFormat) -> Vec<f32> {
+    let rate = format.sample_rate as f32;
+    let frames = format.sample_rate as usize * CLIP_SECONDS;
+    let mut noise = 0x1234_5678u32;
+    (0..frames).flat_map(|i| {
+        let t = i as f32 / rate;
+        let tone = [220.0, 277.2, 329.6].iter().map(|hz| (TAU * hz * t).sin()).sum::<f32>() / 6.0;
+        noise ^= noise << 13;
+        noise ^= noise >> 17;
+        noise ^= noise << 5;
+        let hiss = (noise as f32 / u32::MAX as f32 - 0.5) * 0.02;
+        std::iter::repeat_n(tone + hiss, format.channels as usize)
+    }).collect()
+}
@@ -0,0 +1,155 @@
+//! Times what the audio callbacks and the streams around them do to every frame: chunking the
+//! capture into packages, converting samples between f32 and i16, resampling, mixing senders and
+//! applying the volume. Each iteration handles one frame of the default --package-size in 48 kHz
+//! stereo, so criterion's time is per frame and its throughput in samples per second. Adding a
+//! stage to the chain should leave these where they were.
+//!
+//! Run with `cargo bench --bench hot_path`, or `cargo bench --bench hot_path -- mix` for one group.
+
+use std::f32::consts::TAU;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use crate::framing::{capture_packages, Framer};
+use crate::jitter::Packet;
+use crate::mixer::Mixer;
+use crate::resample::Resampler;
+use crate::samples::{from_payload, to_payload, DeviceSample};
+use crate::sound_flow::SampleFormat;
+use crate::volume::Smoother;
+
+#[path = "../src/drift.rs"]
+#[allow(dead_code)]
+mod drift;
+
+#[path = "../src/framing.rs"]
+#[allow(dead_code)]
+mod framing;
+
+#[path = "../src/jitter.rs"]
+#[allow(dead_code)]
+mod jitter;
+
+#[path = "../src/mixer.rs"]
+#[allow(dead_code)]
+mod mixer;
+
+#[path = "../src/resample.rs"]
+#[allow(dead_code)]
+mod resample;
+
+#[path = "../src/samples.rs"]
+#[allow(dead_code)]
+mod samples;
+
+#[path = "../src/volume.rs"]
+#[allow(dead_code)]
+mod volume;
+
+#[allow(dead_code)]
+mod sound_flow {
+    tonic::include_proto!("sound_flow");
+}
+
+const SAMPLE_RATE: u32 = 48000;
+const CHANNELS: usize = 2;
+const PACKAGE_SIZE: usize = 1000; // the service's default --package-size
+const CALLBACK: usize = 4 * PACKAGE_SIZE; // a callback buffer several packages long, for chunking
+
+/// `len` interleaved samples of a 440 Hz tone, the same on every channel.
+fn tone(len: usize) -> Vec<f32> {
+    (0..len).map(|i| 0.5 * (TAU * 440.0 * (i / CHANNELS) as f32 / SAMPLE_RATE as f32).sin()).collect()
+}
+
+fn package_duration() -> Duration {
+    Duration::from_secs_f64((PACKAGE_SIZE / CHANNELS) as f64 / SAMPLE_RATE as f64)
+}
+
+fn chunking(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunk");
+    group.throughput(Throughput::Elements(PACKAGE_SIZE as u64));
+    let frame = tone(PACKAGE_SIZE);
+    let mut framer = Framer::fixed(PACKAGE_SIZE).timed(SAMPLE_RATE, CHANNELS);
+    group.bench_function("framer", |b| b.iter(|| framer.push(black_box(&frame), 0)));
+    // An iteration here splits a whole callback, CALLBACK samples.
+    let callback = tone(CALLBACK);
+    let (at, duration) = (Instant::now(), package_duration());
+    group.throughput(Throughput::Elements(CALLBACK as u64));
+    group.bench_function("capture_packages", |b| b.iter(|| {
+        capture_packages(black_box(&callback), PACKAGE_SIZE, at, duration).map(|(package, _)| package.len()).sum::<usize>()
+    }));
+    group.finish();
+}
+
+fn conversion(c: &mut Criterion) {
+    let mut group = c.benchmark_group("convert");
+    group.throughput(Throughput::Elements(PACKAGE_SIZE as u64));
+    let frame = tone(PACKAGE_SIZE);
+    let device: Vec<i16> = frame.iter().map(|&sample| i16::from_f32(sample)).collect();
+    let payload = to_payload(&frame, SampleFormat::I16);
+    group.bench_function("f32_to_i16_device", |b| b.iter(|| {
+        black_box(&frame).iter().map(|&sample| i16::from_f32(sample)).collect::<Vec<_>>()
+    }));
+    group.bench_function("i16_to_f32_device", |b| b.iter(|| {
+        black_box(&device).iter().map(|&sample| sample.to_f32()).collect::<Vec<_>>()
+    }));
+    group.bench_function("f32_to_i16_payload", |b| b.iter(|| to_payload(black_box(&frame), SampleFormat::I16)));
+    group.bench_function("i16_to_f32_payload", |b| b.iter(|| from_payload(black_box(&payload), SampleFormat::I16).unwrap()));
+    group.finish();
+}
+
+fn resampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resample");
+    group.throughput(Throughput::Elements(PACKAGE_SIZE as u64));
+    let frame = tone(PACKAGE_SIZE);
+    for from in [44100, 16000] {
+        let mut resampler = Resampler::new(from, SAMPLE_RATE, CHANNELS, PACKAGE_SIZE).unwrap();
+        group.bench_function(format!("{}_to_{}", from, SAMPLE_RATE), |b| b.iter(|| resampler.process(black_box(&frame)).unwrap()));
+    }
+    group.finish();
+}
+
+fn mixing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mix");
+    group.throughput(Throughput::Elements(PACKAGE_SIZE as u64));
+    let frame = tone(PACKAGE_SIZE);
+    let period = package_duration();
+    for senders in [1, 4, 16] {
+        let mut mixer = Mixer::new(1, 1, 8, true, true, 0);
+        let mut seq = 0;
+        let mut slot = Instant::now();
+        // Each sender's package arrives just before the callback pops the mix.
+        group.bench_function(format!("{}_senders", senders), |b| b.iter_batched(
+            || vec![frame.clone(); senders],
+            |packages| {
+                seq += 1;
+                for (stream, samples) in (1..).zip(packages) {
+                    mixer.push(Packet { stream, seq, samples, captured: None, due: None, end: false });
+                }
+                slot += period;
+                mixer.pop(PACKAGE_SIZE, &(slot..slot + period))
+            },
+            BatchSize::SmallInput,
+        ));
+    }
+    group.finish();
+}
+
+fn gain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gain");
+    group.throughput(Throughput::Elements(PACKAGE_SIZE as u64));
+    let frame = tone(PACKAGE_SIZE);
+    // A fresh frame every time, as turning the same one down over and over ends in denormals.
+    let mut smoother = Smoother::new(0.5, CHANNELS);
+    group.bench_function("steady", |b| b.iter_batched_ref(|| frame.clone(), |frame| smoother.apply(black_box(0.5), frame), BatchSize::SmallInput));
+    // Alternating between two volumes keeps it ramping the whole frame.
+    let mut smoother = Smoother::new(0.25, CHANNELS);
+    let mut targets = [0.25, 0.75].into_iter().cycle();
+    group.bench_function("ramping", |b| b.iter_batched_ref(|| frame.clone(), |frame| smoother.apply(targets.next().unwrap(), frame), BatchSize::SmallInput));
+    group.finish();
+}
+
+criterion_group!(benches, chunking, conversion, resampling, mixing, gain);
+criterion_main!(benches);
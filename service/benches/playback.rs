@@ -0,0 +1,176 @@
+//! Has senders push packages into the playback ring while a stand-in output callback drains it
+//! every millisecond, once through the shared mutex ring the service used to have and once
+//! through a lane per sender, and compares how often the callback had to skip a turn and how
+//! long draining and pushing took.
+//!
+//! Run with `cargo bench --bench playback`.
+
+use std::sync::{Arc, Barrier, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ringbuf::{HeapRb, Rb};
+
+use crate::config::Overflow;
+use crate::jitter::Packet;
+use crate::playback::{Lane, PlaybackDrain, PlaybackRing};
+
+#[path = "../src/jitter.rs"]
+#[allow(dead_code)]
+mod jitter;
+
+#[path = "../src/playback.rs"]
+#[allow(dead_code)]
+mod playback;
+
+mod config {
+    /// The service's, which comes with its whole command line.
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Overflow {
+        DropNewest,
+        DropOldest,
+        Block,
+    }
+}
+
+const CAPACITY: usize = 1024; // packages, enough that nothing overflows
+const PACKAGE_SIZE: usize = 480; // 10 ms of mono at 48 kHz
+const PUSH_INTERVAL: Duration = Duration::from_micros(200); // far more often than a real sender, to provoke contention
+const CALLBACK_INTERVAL: Duration = Duration::from_millis(1);
+const RUN: Duration = Duration::from_secs(2);
+
+fn main() {
+    println!("{:<8} {:<6} {:>9} {:>14} {:>14} {:>13}", "senders", "ring", "skipped", "drain p99 µs", "drain max µs", "push p99 µs");
+    for senders in [1, 4, 16] {
+        for (name, ring) in [("mutex", Ring::mutex()), ("lanes", Ring::lanes())] {
+            let run = run(ring, senders);
+            println!(
+                "{:<8} {:<6} {:>8.2}% {:>14.1} {:>14.1} {:>13.1}",
+                senders, name, 100.0 * run.skipped as f64 / run.turns as f64,
+                percentile(&run.drains, 0.99), percentile(&run.drains, 1.0), percentile(&run.pushes, 0.99),
+            );
+        }
+    }
+}
+
+/// The design before lanes: one ring for every sender, locked for each push, which the callback
+/// only tries to lock and skips its turn when it can't.
+type Shared = Arc<Mutex<HeapRb<Packet>>>;
+
+enum Ring {
+    Mutex(Shared),
+    Lanes(PlaybackRing, PlaybackDrain),
+}
+
+impl Ring {
+    fn mutex() -> Self {
+        Ring::Mutex(Arc::new(Mutex::new(HeapRb::new(CAPACITY))))
+    }
+
+    fn lanes() -> Self {
+        let (ring, drain) = PlaybackRing::new(CAPACITY);
+        Ring::Lanes(ring, drain)
+    }
+}
+
+enum Producer {
+    Mutex(Shared),
+    Lane(Lane),
+}
+
+impl Producer {
+    fn push(&mut self, packet: Packet, runtime: &tokio::runtime::Runtime) {
+        match self {
+            Producer::Mutex(ring) => {
+                ring.lock().unwrap().push_overwrite(packet);
+            }
+            Producer::Lane(lane) => {
+                runtime.block_on(lane.push(packet, Overflow::DropOldest));
+            }
+        }
+    }
+}
+
+struct Run {
+    turns: usize,
+    skipped: usize,
+    drains: Vec<f64>, // µs per callback turn
+    pushes: Vec<f64>, // µs per push, over every sender
+}
+
+fn run(ring: Ring, senders: usize) -> Run {
+    let stop = Arc::new(AtomicBool::new(false));
+    let start = Arc::new(Barrier::new(senders + 1));
+    let producers: Vec<Producer> = (0..senders).map(|_| match &ring {
+        Ring::Mutex(shared) => Producer::Mutex(shared.clone()),
+        Ring::Lanes(ring, _) => Producer::Lane(ring.open()),
+    }).collect();
+    let sending: Vec<_> = (1..).zip(producers).map(|(stream, mut producer)| {
+        let (stop, start) = (stop.clone(), start.clone());
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+            let mut pushes = Vec::new();
+            start.wait();
+            for seq in 1.. {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let packet = Packet { stream, seq, samples: vec![0.0; PACKAGE_SIZE], captured: None, due: None, end: false };
+                let pushed = Instant::now();
+                producer.push(packet, &runtime);
+                pushes.push(pushed.elapsed().as_secs_f64() * 1e6);
+                spin_until(pushed + PUSH_INTERVAL);
+            }
+            pushes
+        })
+    }).collect();
+
+    let mut run = Run { turns: 0, skipped: 0, drains: Vec::new(), pushes: Vec::new() };
+    let mut drained = 0;
+    let mut drain = match ring {
+        Ring::Mutex(shared) => Box::new(move || match shared.try_lock() {
+            Ok(mut ring) => {
+                ring.pop_iter().for_each(|packet| drained += packet.samples.len());
+                true
+            }
+            Err(_) => false,
+        }) as Box<dyn FnMut() -> bool>,
+        Ring::Lanes(_, mut lanes) => Box::new(move || {
+            lanes.drain(|packet| drained += packet.samples.len());
+            true
+        }),
+    };
+    start.wait();
+    let started = Instant::now();
+    let mut next = started;
+    while started.elapsed() < RUN {
+        next += CALLBACK_INTERVAL;
+        spin_until(next);
+        let turn = Instant::now();
+        if !drain() {
+            run.skipped += 1;
+        }
+        run.drains.push(turn.elapsed().as_secs_f64() * 1e6);
+        run.turns += 1;
+    }
+    stop.store(true, Ordering::Relaxed);
+    for sender in sending {
+        run.pushes.extend(sender.join().unwrap());
+    }
+    run
+}
+
+/// Waits like a real-time thread would, without handing the core to the scheduler.
+fn spin_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
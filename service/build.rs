@@ -1,4 +1,22 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+const PROTO: &str = "../proto/sound_flow.proto";
+const INCLUDES: &str = "../proto";
+
+/// Generates the SoundFlow messages and service, plus the descriptor set gRPC reflection serves,
+/// from the proto shared with the client. With the `serde` feature the messages derive
+/// Serialize and Deserialize.
 fn main() {
-    tonic_build::compile_protos("../proto/sound_flow.proto")
-        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
-}
\ No newline at end of file
+    if !Path::new(PROTO).is_file() {
+        panic!("{} is missing: the service is built from a checkout of the whole SoundFlow repository", PROTO);
+    }
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut builder = tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("sound_flow_descriptor.bin"));
+    if env::var_os("CARGO_FEATURE_SERDE").is_some() {
+        builder = builder.type_attribute(".sound_flow", "#[derive(serde::Serialize, serde::Deserialize)]");
+    }
+    builder.compile(&[PROTO], &[INCLUDES])
+        .unwrap_or_else(|e| panic!("{} doesn't compile:\n{}", PROTO, e));
+}
@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use ringbuf::{HeapRb, Rb};
+use tracing::warn;
+
+use crate::dsp::Processor;
+use crate::sound_flow::AudioFormat;
+
+const STEP: f32 = 0.25; // NLMS step size, larger adapts faster but settles less deep
+const REGULARIZATION: f32 = 1e-6; // keeps the update finite while the speaker is silent
+const REFERENCE_CAPACITY: usize = 192_000; // played samples kept for the canceller, a second at the highest common rate
+
+/// An acoustic echo canceller: a normalized least mean squares filter per capture channel that
+/// learns how what the speaker plays (the reference) reaches the microphone, over `taps`
+/// samples, and subtracts its estimate of that echo from the capture.
+pub struct EchoCanceller {
+    taps: usize,
+    history: Vec<f32>, // the last `taps` reference samples twice over, so they are always one slice
+    at: usize, // where the newest reference sample went
+    energy: f32, // sum of squares over the last `taps` reference samples
+    weights: Vec<Vec<f32>>, // per channel
+}
+
+impl EchoCanceller {
+    pub fn new(taps: usize, channels: usize) -> Self {
+        let taps = taps.max(1);
+        EchoCanceller { taps, history: vec![0.0; 2 * taps], at: 0, energy: 0.0, weights: vec![vec![0.0; taps]; channels.max(1)] }
+    }
+
+    /// Removes the echo of `reference`, mono and one sample per frame, from the interleaved
+    /// `capture`, which is as many frames long.
+    pub fn process(&mut self, capture: &mut [f32], reference: &[f32]) {
+        let channels = self.weights.len();
+        for (frame, &played) in capture.chunks_mut(channels).zip(reference) {
+            self.at = (self.at + 1) % self.taps;
+            let oldest = self.history[self.at];
+            self.energy = (self.energy + played * played - oldest * oldest).max(0.0);
+            self.history[self.at] = played;
+            self.history[self.at + self.taps] = played;
+            let window = &self.history[self.at + 1..=self.at + self.taps]; // oldest first
+            for (sample, weights) in frame.iter_mut().zip(&mut self.weights) {
+                let estimate: f32 = weights.iter().zip(window).map(|(weight, x)| weight * x).sum();
+                let error = *sample - estimate;
+                let step = STEP * error / (self.energy + REGULARIZATION);
+                weights.iter_mut().zip(window).for_each(|(weight, x)| *weight += step * x);
+                *sample = error;
+            }
+        }
+    }
+}
+
+/// The canceller as a capture Processor, fed with what `reference` says the speaker played. It
+/// only works while both devices run at the same rate, and leaves the capture alone otherwise.
+pub struct EchoCancellation {
+    reference: EchoReference,
+    canceller: EchoCanceller,
+    mismatch_warned: bool,
+}
+
+impl EchoCancellation {
+    pub fn new(reference: EchoReference, canceller: EchoCanceller) -> Self {
+        EchoCancellation { reference, canceller, mismatch_warned: false }
+    }
+}
+
+impl Processor for EchoCancellation {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        let played = self.reference.sample_rate();
+        if played == format.sample_rate {
+            let reference = self.reference.take(frame.len() / (format.channels as usize).max(1));
+            self.canceller.process(frame, &reference);
+        } else if played != 0 && !self.mismatch_warned {
+            self.mismatch_warned = true;
+            warn!(capture = format.sample_rate, playback = played, "echo cancellation is off while the devices run at different sample rates");
+        }
+    }
+}
+
+/// What the speaker played, downmixed to mono, on its way from the output callback to the
+/// canceller in the input callback. Both only ever try to lock it, so neither callback waits on
+/// the other; a missed turn just leaves a gap in the reference.
+#[derive(Clone)]
+pub struct EchoReference {
+    played: Arc<Mutex<HeapRb<f32>>>,
+    sample_rate: Arc<AtomicU32>, // of the current output stream, 0 before there is one
+}
+
+impl Default for EchoReference {
+    fn default() -> Self {
+        EchoReference {
+            played: Arc::new(Mutex::new(HeapRb::new(REFERENCE_CAPACITY))),
+            sample_rate: Arc::new(AtomicU32::new(0)),
+        }
+    }
+}
+
+impl EchoReference {
+    /// Called by a new output stream with its sample rate.
+    pub fn started(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the interleaved `samples` the speaker is about to play, downmixed to mono.
+    pub fn played(&self, samples: &[f32], channels: usize) {
+        let Ok(mut played) = self.played.try_lock() else {
+            return;
+        };
+        for frame in samples.chunks(channels.max(1)) {
+            played.push_overwrite(frame.iter().sum::<f32>() / frame.len() as f32);
+        }
+    }
+
+    /// The next `frames` samples played, padded with silence if the speaker is behind.
+    pub fn take(&self, frames: usize) -> Vec<f32> {
+        let mut reference = vec![0.0; frames];
+        if let Ok(mut played) = self.played.try_lock() {
+            for (sample, played) in reference.iter_mut().zip(played.pop_iter()) {
+                *sample = played;
+            }
+        }
+        reference
+    }
+}
+
+/// On the playback path, keeps every frame for the canceller and passes it on unchanged.
+impl Processor for EchoReference {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        self.played(frame, format.channels as usize);
+    }
+}
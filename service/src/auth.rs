@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use tonic::{Request, Status};
+use tracing::warn;
+
+pub const AUTHORIZATION: &str = "authorization"; // request metadata carrying `Bearer <token>`
+
+/// An interceptor that lets through only requests presenting `token` as a bearer token, or every
+/// request if there is no token.
+pub fn require_token(token: Option<String>) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    let token: Option<Arc<[u8]>> = token.map(|token| token.into_bytes().into());
+    move |request: Request<()>| {
+        let Some(token) = &token else {
+            return Ok(request);
+        };
+        let presented = request.metadata().get(AUTHORIZATION)
+            .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "));
+        match presented {
+            Some(presented) if same(presented, token) => Ok(request),
+            _ => {
+                warn!(peer = ?request.remote_addr(), "rejected a request without a valid token");
+                Err(Status::unauthenticated("missing or wrong bearer token"))
+            }
+        }
+    }
+}
+
+/// Compares in time that depends only on the lengths, so a wrong token doesn't reveal how much of
+/// it was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
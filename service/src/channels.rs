@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+/// Converts interleaved `samples` with `from` channels into `to` channels. Fewer input channels
+/// are repeated across the outputs, so mono is duplicated into both sides of a stereo output.
+/// Extra input channels are averaged into the output they wrap onto, so stereo downmixes to mono
+/// as the average of left and right.
+pub fn remap(samples: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let mut remapped = Vec::with_capacity(samples.len() / from * to);
+    for frame in samples.chunks_exact(from) {
+        if from < to {
+            remapped.extend((0..to).map(|channel| frame[channel % from]));
+        } else {
+            remapped.extend((0..to).map(|channel| {
+                let folded = frame.iter().skip(channel).step_by(to);
+                folded.clone().sum::<f32>() / folded.count() as f32
+            }));
+        }
+    }
+    remapped
+}
+
+/// A fixed routing from a stream's channels to the speaker's, for --channel-map: for every output
+/// channel, the weight each input channel is mixed into it with. Written as one row per output
+/// and one weight per input, rows separated by `;`, so `0,1;1,0` swaps left and right and `1;0`
+/// plays a mono stream on the left only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMap {
+    weights: Vec<Vec<f32>>, // by output channel, then input channel
+}
+
+impl ChannelMap {
+    pub fn inputs(&self) -> usize {
+        self.weights[0].len()
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Routes interleaved `samples` with `inputs()` channels into `outputs()` channels.
+    pub fn apply(&self, samples: &[f32]) -> Vec<f32> {
+        let mut mapped = Vec::with_capacity(samples.len() / self.inputs() * self.outputs());
+        for frame in samples.chunks_exact(self.inputs()) {
+            mapped.extend(self.weights.iter().map(|row| row.iter().zip(frame).map(|(weight, sample)| weight * sample).sum::<f32>()));
+        }
+        mapped
+    }
+}
+
+impl FromStr for ChannelMap {
+    type Err = String;
+
+    fn from_str(map: &str) -> Result<Self, String> {
+        let weights = map.split(';').map(|row| {
+            row.split(',').map(|weight| match weight.trim().parse::<f32>() {
+                Ok(weight) if weight.is_finite() => Ok(weight),
+                _ => Err(format!("{:?} isn't a weight", weight.trim())),
+            }).collect::<Result<Vec<_>, _>>()
+        }).collect::<Result<Vec<_>, _>>()?;
+        if weights.iter().any(|row| row.len() != weights[0].len()) {
+            return Err("every output channel needs a weight for each input channel".to_string());
+        }
+        Ok(ChannelMap { weights })
+    }
+}
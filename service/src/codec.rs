@@ -0,0 +1,81 @@
+use opus::{Application, Channels, Decoder, Encoder};
+
+use crate::sound_flow::AudioFormat;
+
+const FRAME_DURATION_MS: usize = 20; // opus only takes 2.5, 5, 10, 20, 40 or 60 ms frames, PACKAGE_SIZE chunks are re-framed to this
+const MAX_FRAME_DURATION_MS: usize = 120; // the longest frame a single opus packet can decode to
+const MAX_PACKET_SIZE: usize = 4000; // recommended by libopus, a packet never exceeds this
+const SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// Whether opus can encode audio in `format` without resampling.
+pub fn opus_supports(format: &AudioFormat) -> bool {
+    SAMPLE_RATES.contains(&format.sample_rate) && (1..=2).contains(&format.channels)
+}
+
+fn channels(format: &AudioFormat) -> Channels {
+    if format.channels == 1 { Channels::Mono } else { Channels::Stereo }
+}
+
+/// Encodes interleaved f32 samples of any chunk size into opus packets of FRAME_DURATION_MS each.
+pub struct OpusEncoder {
+    encoder: Encoder,
+    frame_len: usize, // interleaved samples per opus frame
+    pending: Vec<f32>, // samples waiting for a complete frame
+}
+
+impl OpusEncoder {
+    pub fn new(format: &AudioFormat) -> Result<Self, opus::Error> {
+        Ok(OpusEncoder {
+            encoder: Encoder::new(format.sample_rate, channels(format), Application::Audio)?,
+            frame_len: format.sample_rate as usize * format.channels as usize * FRAME_DURATION_MS / 1000,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Buffers `samples` and returns one packet for every complete frame, possibly none.
+    pub fn encode(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>, opus::Error> {
+        self.pending.extend_from_slice(samples);
+        let mut packets = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= self.frame_len {
+            let frame = &self.pending[start..start + self.frame_len];
+            packets.push(self.encoder.encode_vec_float(frame, MAX_PACKET_SIZE)?);
+            start += self.frame_len;
+        }
+        self.pending.drain(..start);
+        Ok(packets)
+    }
+
+    /// A last packet of what is still buffered, padded with silence to a whole frame, if anything is.
+    pub fn flush(&mut self) -> Result<Option<Vec<u8>>, opus::Error> {
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        self.pending.resize(self.frame_len, 0.0);
+        let packet = self.encoder.encode_vec_float(&self.pending, MAX_PACKET_SIZE)?;
+        self.pending.clear();
+        Ok(Some(packet))
+    }
+}
+
+/// Decodes opus packets back into interleaved f32 samples.
+pub struct OpusDecoder {
+    decoder: Decoder,
+    channels: usize,
+    buffer: Vec<f32>, // large enough for the longest frame
+}
+
+impl OpusDecoder {
+    pub fn new(format: &AudioFormat) -> Result<Self, opus::Error> {
+        Ok(OpusDecoder {
+            decoder: Decoder::new(format.sample_rate, channels(format))?,
+            channels: format.channels as usize,
+            buffer: vec![0.0; format.sample_rate as usize * format.channels as usize * MAX_FRAME_DURATION_MS / 1000],
+        })
+    }
+
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>, opus::Error> {
+        let frames = self.decoder.decode_float(packet, &mut self.buffer, false)?;
+        Ok(self.buffer[..frames * self.channels].to_vec())
+    }
+}
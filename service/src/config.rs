@@ -0,0 +1,729 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::{ArgMatches, CommandFactory, Parser, ValueEnum};
+use clap::parser::ValueSource;
+use figment::Figment;
+use figment::providers::{Format, Toml};
+use serde::Deserialize;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tracing::warn;
+
+use crate::aec::{EchoCanceller, EchoReference};
+use crate::channels::ChannelMap;
+use crate::framing::Framer;
+use crate::idle::CaptureIdle;
+use crate::latency::Playout;
+use crate::listen::ipv4_addr;
+use crate::mixer::Mixer;
+use crate::sound_flow::AudioFormat;
+use crate::throughput::Throughput;
+use crate::vad::Vad;
+
+const MIN_RING_CAPACITY: usize = 4; // below this a single late wakeup is enough to drop frames
+const BROADCAST_WINDOW: Duration = Duration::from_secs(1); // default --broadcast-capacity, in time
+const LISTENER_WINDOW: Duration = Duration::from_millis(200); // default --listener-queue, in time
+const FRAME_LIMIT: usize = 10; // default --max-frame-samples, in packages
+
+/// SoundFlow core service, streams audio between this machine's devices and remote clients.
+///
+/// Every option can also be set through the environment variable named next to it, or in the
+/// --config file. The command line takes precedence over the environment, and both over the file.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+pub struct Config {
+    /// TOML file setting any of the options below by their long name, e.g.
+    /// `package-size = 480` or `accept-compression = ["zstd"]`.
+    #[arg(long, env = "SF_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to serve gRPC on, e.g. `0.0.0.0:50051` for IPv4 or `[::]:50051` for IPv6 and
+    /// IPv4 both. The default only accepts connections from this machine, over IPv6.
+    #[arg(long, env = "SF_LISTEN", default_value = "[::1]:50051")]
+    pub listen: SocketAddr,
+
+    /// Listen on the IPv4 counterpart of --listen and --metrics-listen, `0.0.0.0` for `[::]` and
+    /// `127.0.0.1` for `[::1]`, for hosts without IPv6 or clients that only reach IPv4.
+    #[arg(long, env = "SF_IPV4_ONLY")]
+    pub ipv4_only: bool,
+
+    /// Serve on a Unix domain socket at this path instead of --listen, for local apps only and
+    /// without opening a port. Served without TLS, the socket's permissions keep others out.
+    #[arg(long, env = "SF_SOCKET", value_name = "PATH", conflicts_with_all = ["tls_cert", "ipv4_only"])]
+    pub socket: Option<PathBuf>,
+
+    /// Permissions of the --socket file, in octal: 600 lets only this user connect, 660 its
+    /// group as well.
+    #[arg(long, env = "SF_SOCKET_MODE", value_name = "MODE", default_value = "600", value_parser = octal)]
+    pub socket_mode: u32,
+
+    /// Address clients should reach the server at, when that isn't --listen, e.g. behind NAT
+    /// or a port forward. GetServerInfo returns it so clients can check they got here the way
+    /// others will; it changes nothing about what is served.
+    #[arg(long, value_name = "ADDR", env = "SF_ADVERTISE")]
+    pub advertise: Option<String>,
+
+    /// Audio backend to capture and play through, by name and any case: e.g. `alsa` or `jack` on
+    /// Linux, `wasapi` or `asio` on Windows, `coreaudio` on macOS, as far as this build includes
+    /// them. Defaults to the platform's default backend.
+    #[arg(long, env = "SF_HOST")]
+    pub host: Option<String>,
+
+    /// Attempts at rebuilding a stream whose device went away, e.g. a USB interface unplugged
+    /// for a moment, backing off from 250 ms to 8 s between them. After that the stream waits
+    /// for the devices to change. 0 leaves it to that from the start.
+    #[arg(long, env = "SF_DEVICE_RETRIES", default_value_t = 10)]
+    pub device_retries: u32,
+
+    /// Set the package size, hardware buffer, ring capacity, jitter buffer and compression
+    /// together for low latency, the defaults, or riding out a poor network. Any of those set on
+    /// its own, here or in the --config file, still wins over the preset.
+    #[arg(long, env = "SF_PRESET", value_enum)]
+    pub preset: Option<Preset>,
+
+    /// Frames per hardware buffer to ask the input and output devices for, smaller for less
+    /// latency at a higher risk of underruns. A device that doesn't support the size keeps its
+    /// own, as it does by default.
+    #[arg(long, env = "SF_BUFFER_FRAMES", value_name = "FRAMES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub buffer_frames: Option<u32>,
+
+    /// Samples per Flow frame, interleaved across channels. Smaller frames reach the other
+    /// side sooner but cost more per-frame overhead; 1000 samples are ~10 ms of 48 kHz stereo.
+    #[arg(long, env = "SF_PACKAGE_SIZE", default_value_t = 1000, value_parser = positive)]
+    pub package_size: usize,
+
+    /// Let the RAW frames sent to GetFlow and Duplex listeners grow, up to --max-package-size,
+    /// while a listener falls behind or its Duplex stream reports underruns, and shrink back to
+    /// --package-size once the link has been fine for a while. Fewer, bigger frames cost less
+    /// per frame but arrive later.
+    #[arg(long, env = "SF_ADAPTIVE_PACKAGE_SIZE")]
+    pub adaptive_package_size: bool,
+
+    /// The largest frame, in samples, --adaptive-package-size grows to.
+    #[arg(long, env = "SF_MAX_PACKAGE_SIZE", default_value_t = 8000, value_parser = positive)]
+    pub max_package_size: usize,
+
+    /// Frames the capture ring buffer, and each sender's playback lane, can hold. A full buffer
+    /// adds up to this many frames of latency, a small one drops frames under jitter.
+    #[arg(long, env = "SF_RING_CAPACITY", default_value_t = 128, value_parser = positive)]
+    pub ring_capacity: usize,
+
+    /// Frames the capture broadcast, and each room's, keeps for listeners that fall behind before
+    /// they lose some. Defaults to a second's worth at the capture format: with --package-size
+    /// samples per frame that is sample rate × channels / package size, 96 at 48 kHz stereo.
+    #[arg(long, env = "SF_BROADCAST_CAPACITY", value_parser = positive)]
+    pub broadcast_capacity: Option<usize>,
+
+    /// Frames queued for each listener between its broadcast and the network. Defaults to
+    /// 200 ms worth at the capture format, like --broadcast-capacity. A short queue drops frames
+    /// sooner when the network stalls, a long one lets a stalled listener's latency grow that far.
+    #[arg(long, env = "SF_LISTENER_QUEUE", value_parser = positive)]
+    pub listener_queue: Option<usize>,
+
+    /// Most GetFlow listeners served at once, later ones are refused with RESOURCE_EXHAUSTED.
+    /// Each listener costs a copy of every captured frame, and an opus encoder if it asked for one.
+    #[arg(long, env = "SF_MAX_LISTENERS", default_value_t = 16, value_parser = positive)]
+    pub max_listeners: usize,
+
+    /// Most SendFlow and Duplex senders played at once, later ones are refused with RESOURCE_EXHAUSTED.
+    #[arg(long, env = "SF_MAX_SENDERS", default_value_t = 16, value_parser = positive)]
+    pub max_senders: usize,
+
+    /// Fastest a sender may send, as a multiple of real time at its negotiated format. Frames
+    /// beyond it are dropped so a runaway sender can't flood the mix. A second's worth of burst
+    /// is allowed, enough to catch up after a network stall.
+    #[arg(long, env = "SF_MAX_INGEST_SPEED", default_value_t = 2.0, value_parser = at_least_real_time)]
+    pub max_ingest_speed: f64,
+
+    /// Most samples a sender's frame may decode to, 10 × --package-size by default. A bigger
+    /// frame is refused with INVALID_ARGUMENT and ends the sender's flow, so a buggy or hostile
+    /// sender can't push megabytes into a lane at once.
+    #[arg(long, env = "SF_MAX_FRAME_SAMPLES", value_parser = positive)]
+    pub max_frame_samples: Option<usize>,
+
+    /// Packages to buffer before playback starts, and again after the speaker runs dry. Each
+    /// package adds ~10 ms of latency at the default --package-size but absorbs that much jitter.
+    #[arg(long, env = "SF_JITTER_DEPTH", default_value_t = 3)]
+    pub jitter_depth: usize,
+
+    /// Fewest packages the jitter buffer shrinks to after a long run without underruns.
+    #[arg(long, env = "SF_JITTER_MIN", default_value_t = 1, value_parser = positive)]
+    pub jitter_min: usize,
+
+    /// Most packages the jitter buffer grows to when the speaker keeps running dry; anything
+    /// queued beyond it is dropped to catch up.
+    #[arg(long, env = "SF_JITTER_MAX", default_value_t = 16, value_parser = positive)]
+    pub jitter_max: usize,
+
+    /// Play every frame that carries a capture time this long after it was captured, instead of
+    /// when the jitter buffer fills: early frames wait and late ones are dropped, so the latency
+    /// stays put however the network jitters. Frames from another server's clock are timed from
+    /// the quickest one to arrive. Raise --jitter-max to hold that many milliseconds of packages.
+    #[arg(long, env = "SF_PLAYOUT_DELAY_MS", value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub playout_delay_ms: Option<u64>,
+
+    /// Play a stand-in for frames lost on the way, so later frames keep their timing instead of
+    /// being pulled forward.
+    #[arg(long, env = "SF_FILL_GAPS")]
+    pub fill_gaps: bool,
+
+    /// Play silence for missing frames instead of fading out a repeat of the last frame played.
+    #[arg(long, env = "SF_NO_CONCEALMENT")]
+    pub no_concealment: bool,
+
+    /// Cut a send_flow stream off after its last frame instead of fading it out over --fade-ms.
+    #[arg(long, env = "SF_NO_END_FADE")]
+    pub no_end_fade: bool,
+
+    /// Fade playback in over this many milliseconds when it starts from silence, and each
+    /// send_flow stream's end out over as many, so neither pops. 0 turns both off.
+    #[arg(long, env = "SF_FADE_MS", value_name = "MS", default_value_t = 10)]
+    pub fade_ms: u64,
+
+    /// Let each sender's jitter buffer fill or drain as its clock drifts from the speaker's,
+    /// instead of stretching or squeezing its frames by a sample now and then to keep it centred.
+    /// --playout-delay-ms times frames by their capture time instead, so it turns this off too.
+    #[arg(long, env = "SF_NO_DRIFT_COMPENSATION")]
+    pub no_drift_compensation: bool,
+
+    /// What to do with a received package when the playback ring is full.
+    #[arg(long, env = "SF_OVERFLOW", value_enum, default_value_t = Overflow::DropOldest)]
+    pub overflow: Overflow,
+
+    /// Routes what is played to the speaker's channels by weight instead of duplicating or
+    /// averaging them: one row per speaker channel of one weight per stream channel, rows
+    /// separated by `;`. `0,1;1,0` swaps left and right, `1;0` plays a mono stream on the left
+    /// only. Streams with another channel count are refused.
+    #[arg(long, env = "SF_CHANNEL_MAP", value_name = "MAP")]
+    pub channel_map: Option<ChannelMap>,
+
+    /// How many times a second Meter streams capture levels, each over the audio since the last.
+    #[arg(long, env = "SF_METER_HZ", default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub meter_hz: u32,
+
+    /// Directory StartRecording writes WAV files into. Recording is refused while unset, since
+    /// it lets clients create files on this machine.
+    #[arg(long, env = "SF_RECORDINGS_DIR")]
+    pub recordings_dir: Option<PathBuf>,
+
+    /// Send listeners every captured frame, even silence. Music and ambient streams need this,
+    /// since voice activity detection otherwise stops sending during quiet passages. Recordings
+    /// always get every frame.
+    #[arg(long, env = "SF_NO_VAD")]
+    pub no_vad: bool,
+
+    /// Captured frames quieter than this RMS level in dBFS count as silence and aren't sent.
+    #[arg(long, env = "SF_VAD_THRESHOLD_DB", default_value_t = -50.0, allow_negative_numbers = true)]
+    pub vad_threshold_db: f32,
+
+    /// Milliseconds to keep sending after the last frame above the threshold, so word endings
+    /// and short pauses aren't cut off.
+    #[arg(long, env = "SF_VAD_HANGOVER_MS", default_value_t = 300)]
+    pub vad_hangover_ms: u64,
+
+    /// Subtract what the speaker plays from the capture, so Duplex and room participants don't
+    /// hear themselves back through this machine's microphone. Costs CPU in the input callback
+    /// and only works while both devices run at the same sample rate.
+    #[arg(long, env = "SF_ECHO_CANCELLATION")]
+    pub echo_cancellation: bool,
+
+    /// How many samples of echo path --echo-cancellation models, per capture channel. Longer
+    /// covers more room reverb and speaker-to-microphone delay, at more CPU per sample.
+    #[arg(long, env = "SF_ECHO_TAPS", default_value_t = 512, value_parser = positive)]
+    pub echo_taps: usize,
+
+    /// Suppress steady background noise in the capture with RNNoise, for voice. Adds 20 ms of
+    /// capture latency and CPU in the input callback, and only works on 48 kHz capture. Leave it
+    /// off for music, which it would treat as noise.
+    #[arg(long, env = "SF_NOISE_SUPPRESSION")]
+    pub noise_suppression: bool,
+
+    /// Normalize the capture towards this RMS level in dBFS, e.g. -20, for microphones that are
+    /// far too quiet or too loud. A slow automatic gain control gets it there and a limiter keeps
+    /// the peaks below -1 dBFS. Off unless set, and separate from the playback volume.
+    #[arg(long, env = "SF_NORMALIZE_DBFS", value_name = "DBFS", allow_negative_numbers = true)]
+    pub normalize_dbfs: Option<f32>,
+
+    /// Most --normalize-dbfs boosts a quiet microphone by, in dB. Past it, noise is all that
+    /// gets louder.
+    #[arg(long, env = "SF_NORMALIZE_MAX_GAIN_DB", default_value_t = 30.0)]
+    pub normalize_max_gain_db: f32,
+
+    /// Milliseconds a room stays open with nobody in it before it is closed. Frames sent to a
+    /// closed room's listeners are gone, a new room with the same id starts empty.
+    #[arg(long, env = "SF_ROOM_IDLE_TIMEOUT_MS", default_value_t = 60_000)]
+    pub room_idle_timeout_ms: u64,
+
+    /// Milliseconds to keep serving after Ctrl-C or SIGTERM so frames already in flight reach
+    /// listeners and the speaker before the streams are closed.
+    #[arg(long, env = "SF_SHUTDOWN_GRACE_MS", default_value_t = 500)]
+    pub shutdown_grace_ms: u64,
+
+    /// Milliseconds the device calls (GetDevices, SetDevice and the like) may wait on the audio
+    /// system before failing with DEADLINE_EXCEEDED, less if the client's deadline is sooner.
+    #[arg(long, env = "SF_RPC_TIMEOUT_MS", default_value_t = 5000, value_parser = positive)]
+    pub rpc_timeout_ms: usize,
+
+    /// Milliseconds without a GetFlow or Duplex listener, a Monitor or a recording after which
+    /// the microphone is paused, and its light goes off, until one comes. Off unless set.
+    #[arg(long, env = "SF_CAPTURE_IDLE_MS", value_parser = positive)]
+    pub capture_idle_ms: Option<usize>,
+
+    /// PEM certificate chain the server presents to clients, requires --tls-key.
+    #[arg(long, env = "SF_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key belonging to --tls-cert.
+    #[arg(long, env = "SF_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA certificate that client certificates must be signed by. Enables mutual TLS:
+    /// clients without a valid certificate are rejected.
+    #[arg(long, env = "SF_TLS_CLIENT_CA", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Serve without TLS. Only meant for trusted networks such as loopback, since anyone
+    /// on the path can listen to the microphone otherwise.
+    #[arg(long, env = "SF_PLAINTEXT", conflicts_with = "tls_cert")]
+    pub plaintext: bool,
+
+    /// Shared secret clients must present as `authorization: Bearer <token>` metadata on every
+    /// SoundFlow call, rejected with UNAUTHENTICATED otherwise. Prefer the environment variable,
+    /// command lines are visible to other users. Health checks and reflection stay open.
+    #[arg(long, env = "SF_AUTH_TOKEN", hide_env_values = true)]
+    pub auth_token: Option<String>,
+
+    /// Serve gRPC reflection so tools like grpcurl can list the methods and messages.
+    /// Leave it off in production to avoid advertising the API.
+    #[arg(long, env = "SF_REFLECTION")]
+    pub reflection: bool,
+
+    /// Address to serve Prometheus metrics on at `/metrics`, e.g. `127.0.0.1:9150`. Off unless set.
+    #[arg(long, env = "SF_METRICS_LISTEN")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Milliseconds between the info lines giving frames per second and kbps, of the capture and
+    /// of every stream, for seeing at a glance whether audio flows.
+    #[arg(long, env = "SF_THROUGHPUT_INTERVAL_MS", default_value_t = 1000, value_parser = positive)]
+    pub throughput_interval_ms: usize,
+
+    /// Don't log the throughput lines.
+    #[arg(long, env = "SF_NO_THROUGHPUT_LOG")]
+    pub no_throughput_log: bool,
+
+    /// OTLP/gRPC collector to export every RPC as a span and the counters as metrics to, e.g.
+    /// `http://localhost:4317`. Off unless set, and needs a build with the `otel` feature.
+    #[arg(long, env = "SF_OTLP_ENDPOINT", value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
+    /// The `service.name` exported spans and metrics carry.
+    #[arg(long, env = "SF_OTEL_SERVICE_NAME", default_value = "sf_core")]
+    pub otel_service_name: String,
+
+    /// Browser origins allowed to call the server over grpc-web, comma separated, e.g.
+    /// `http://localhost:8080`, or `*` for any. Off unless set, and needs a build with the `web`
+    /// feature.
+    #[arg(long, env = "SF_WEB_ORIGIN", value_name = "ORIGIN", value_delimiter = ',')]
+    pub web_origin: Vec<String>,
+
+    /// Play the microphone straight back on the speaker instead of starting the server, logging
+    /// the measured latency, to check the devices work before involving the network. Use
+    /// headphones, a speaker next to the microphone will howl.
+    #[arg(long, env = "SF_LOOPBACK")]
+    pub loopback: bool,
+
+    /// Print the audio hosts and devices with the configurations they support, then test the
+    /// default input and output for a second each and exit, without starting the server. The
+    /// output test plays a quiet tone. Paste the report into bug reports about audio.
+    #[arg(long, conflicts_with = "loopback")]
+    pub diagnose: bool,
+
+    /// Print the default input and output devices with the sample rate, channels, sample format
+    /// and buffer sizes they prefer, then exit without opening them or starting the server.
+    #[arg(long, conflicts_with_all = ["loopback", "diagnose"])]
+    pub probe: bool,
+
+    /// How frames sent to clients are compressed, for clients that accept it.
+    #[arg(long, env = "SF_SEND_COMPRESSION", value_enum, default_value_t = Compression::Gzip)]
+    pub send_compression: Compression,
+
+    /// Compressions clients may send frames in, comma separated. `none` refuses compressed
+    /// requests altogether.
+    #[arg(long, env = "SF_ACCEPT_COMPRESSION", value_enum, value_delimiter = ',', default_value = "gzip,zstd")]
+    pub accept_compression: Vec<Compression>,
+
+    /// How log lines are written to stderr; verbosity comes from SF_LOG or RUST_LOG.
+    #[arg(long, env = "SF_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+/// How a full playback ring makes room. Whichever package is dropped counts as an output overrun.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Drop the package that didn't fit and keep what is already queued.
+    DropNewest,
+    /// Drop the oldest queued package instead, stale audio is useless for live voice.
+    DropOldest,
+    /// Hold the sender for up to 20 ms waiting for room, then drop the package that didn't fit.
+    Block,
+}
+
+/// Buffering settings that go together. See `Preset::values` for what each one sets.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+    /// 5 ms packages, a two package jitter buffer and small hardware buffers, uncompressed: a
+    /// round trip of a few tens of milliseconds on a quiet LAN, with more dropouts on a busy one.
+    LowLatency,
+    /// The defaults: 10 ms packages and a three package jitter buffer.
+    Balanced,
+    /// 20 ms packages and a five package jitter buffer that may grow to half a second, for
+    /// Wi-Fi and the internet, where getting every frame matters more than getting it soon.
+    HighQuality,
+}
+
+impl Preset {
+    /// The options the preset sets, by long name, and their values.
+    pub fn values(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Preset::LowLatency => &[
+                ("package-size", "480"),
+                ("buffer-frames", "128"),
+                ("ring-capacity", "32"),
+                ("jitter-depth", "2"),
+                ("jitter-min", "1"),
+                ("jitter-max", "8"),
+                ("send-compression", "none"),
+            ],
+            Preset::Balanced => &[],
+            Preset::HighQuality => &[
+                ("package-size", "1920"),
+                ("buffer-frames", "1024"),
+                ("ring-capacity", "128"),
+                ("jitter-depth", "5"),
+                ("jitter-min", "3"),
+                ("jitter-max", "25"),
+            ],
+        }
+    }
+}
+
+/// gRPC message compression. Audio barely compresses, raw f32 frames shrink by less than a
+/// tenth and i16 or opus frames by even less, so it mostly costs CPU.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// Nothing, the least CPU and the most bandwidth.
+    None,
+    /// Widely supported, but the slowest for what little it saves.
+    Gzip,
+    /// Saves about as much as gzip for a fraction of the CPU.
+    Zstd,
+}
+
+impl Compression {
+    pub fn encoding(self) -> Option<CompressionEncoding> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(CompressionEncoding::Gzip),
+            Compression::Zstd => Some(CompressionEncoding::Zstd),
+        }
+    }
+
+    /// The encoding's name in the grpc-encoding header.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, for log aggregation.
+    Json,
+}
+
+impl Config {
+    /// The options from the command line, the environment and the --config file, exiting with
+    /// clap's usage message if they are invalid.
+    pub fn load() -> anyhow::Result<Config> {
+        Config::load_from(std::env::args_os())
+    }
+
+    /// Like `load`, with `args` for the command line.
+    pub fn load_from<I, T>(args: I) -> anyhow::Result<Config>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let matches = Config::command().get_matches_from(args.clone());
+        if let Some(path) = matches.get_one::<PathBuf>("config") {
+            Config::add_file(path, &matches, &mut args)?;
+        }
+        // The preset's values come last, for whatever neither the command line, the
+        // environment nor the file set.
+        let matches = Config::command().get_matches_from(args.clone());
+        if let Some(preset) = matches.get_one::<Preset>("preset") {
+            for (long, value) in preset.values() {
+                if matches!(matches.value_source(&long.replace('-', "_")), None | Some(ValueSource::DefaultValue)) {
+                    args.push(format!("--{}={}", long, value).into());
+                }
+            }
+        }
+        Ok(Config::parse_from(args))
+    }
+
+    /// Appends the options the file at `path` sets to `args`.
+    fn add_file(path: &Path, matches: &ArgMatches, args: &mut Vec<OsString>) -> anyhow::Result<()> {
+        // The file's values go through clap like the rest, so they are validated the same way,
+        // and only for options that aren't set on the command line or in the environment.
+        let file: BTreeMap<String, FileValue> = Figment::from(Toml::file_exact(path)).extract()
+            .with_context(|| format!("failed to read the config file {}", path.display()))?;
+        let command = Config::command();
+        for (key, value) in file {
+            let id = key.replace('-', "_");
+            let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some()) else {
+                bail!("{}: unknown option `{}`", path.display(), key);
+            };
+            if id == "config" {
+                bail!("{}: a config file can't name another one", path.display());
+            }
+            if !matches!(matches.value_source(&id), None | Some(ValueSource::DefaultValue)) {
+                continue;
+            }
+            value.push_args(arg.get_long().unwrap(), args);
+        }
+        Ok(())
+    }
+
+    /// A copy with the secrets blanked out, for logging.
+    pub fn redacted(&self) -> Config {
+        Config { auth_token: self.auth_token.as_ref().map(|_| "<redacted>".to_string()), ..self.clone() }
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_millis(self.shutdown_grace_ms)
+    }
+
+    pub fn capture_idle(&self) -> Option<CaptureIdle> {
+        self.capture_idle_ms.map(|ms| CaptureIdle::new(Duration::from_millis(ms as u64)))
+    }
+
+    pub fn rpc_timeout(&self) -> Duration {
+        Duration::from_millis(self.rpc_timeout_ms as u64)
+    }
+
+    pub fn room_idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.room_idle_timeout_ms)
+    }
+
+    /// Counts frames going one way, logging them as `what` every --throughput-interval-ms unless
+    /// --no-throughput-log.
+    pub fn throughput(&self, what: &'static str) -> Throughput {
+        let interval = (!self.no_throughput_log).then(|| Duration::from_millis(self.throughput_interval_ms as u64));
+        Throughput::new(what, interval)
+    }
+
+    /// Voice activity detection for capture in `format`, unless it was turned off with --no-vad.
+    pub fn vad(&self, format: &AudioFormat) -> Option<Vad> {
+        (!self.no_vad).then(|| Vad::new(self.vad_threshold_db, self.vad_hangover_ms, format))
+    }
+
+    /// Where the speaker leaves what it played for the echo canceller, if --echo-cancellation
+    /// is set.
+    pub fn echo_reference(&self) -> Option<EchoReference> {
+        self.echo_cancellation.then(EchoReference::default)
+    }
+
+    /// An echo canceller for capture with `channels` interleaved channels.
+    pub fn echo_canceller(&self, channels: usize) -> EchoCanceller {
+        EchoCanceller::new(self.echo_taps, channels)
+    }
+
+    /// --broadcast-capacity, or BROADCAST_WINDOW of frames at `format`.
+    pub fn broadcast_capacity(&self, format: &AudioFormat) -> usize {
+        self.broadcast_capacity.unwrap_or_else(|| self.frames_in(BROADCAST_WINDOW, format))
+    }
+
+    /// --listener-queue, or LISTENER_WINDOW of frames at `format`.
+    pub fn listener_queue(&self, format: &AudioFormat) -> usize {
+        self.listener_queue.unwrap_or_else(|| self.frames_in(LISTENER_WINDOW, format))
+    }
+
+    /// --max-frame-samples, or FRAME_LIMIT packages of --package-size.
+    pub fn max_frame_samples(&self) -> usize {
+        self.max_frame_samples.unwrap_or(FRAME_LIMIT * self.package_size)
+    }
+
+    /// Roughly what buffering adds between capture and playback at `format`: the sender's
+    /// package and the jitter buffer's, plus both hardware buffers with --buffer-frames. The
+    /// devices' own buffers without it, and the network, come on top.
+    pub fn estimated_latency(&self, format: &AudioFormat) -> Duration {
+        let channels = (format.channels as usize).max(1);
+        let frames = (1 + self.jitter_depth) * self.package_samples(channels) / channels + 2 * self.buffer_frames.unwrap_or(0) as usize;
+        Duration::from_secs_f64(frames as f64 / format.sample_rate.max(1) as f64)
+    }
+
+    /// --package-size rounded down to whole frames of `channels` interleaved samples, at least
+    /// one, so no package splits a frame between its channels.
+    pub fn package_samples(&self, channels: usize) -> usize {
+        let channels = channels.max(1);
+        (self.package_size / channels).max(1) * channels
+    }
+
+    /// The framing for a listener to capture in `format`, if --adaptive-package-size is set.
+    pub fn framer(&self, format: &AudioFormat) -> Option<Framer> {
+        let min = self.package_samples(format.channels as usize);
+        self.adaptive_package_size.then(|| Framer::new(min, self.max_package_size / min))
+    }
+
+    /// How many --package-size frames `format` fills in `window`, at least MIN_RING_CAPACITY.
+    fn frames_in(&self, window: Duration, format: &AudioFormat) -> usize {
+        let samples = window.as_secs_f64() * format.sample_rate as f64 * format.channels as f64;
+        ((samples / self.package_size as f64).ceil() as usize).max(MIN_RING_CAPACITY)
+    }
+
+    pub fn fade(&self) -> Duration {
+        Duration::from_millis(self.fade_ms)
+    }
+
+    /// A mixer for `format` whose streams each get a jitter buffer set up by the --jitter-*
+    /// options, kept centred unless --no-drift-compensation or --playout-delay-ms is set.
+    pub fn mixer(&self, format: &AudioFormat) -> Mixer {
+        let channels = (format.channels as usize).max(1);
+        let fade_frames = if self.no_end_fade { 0 } else { (self.fade().as_secs_f64() * format.sample_rate as f64).round() as usize };
+        let mixer = Mixer::new(self.jitter_depth, self.jitter_min, self.jitter_max, self.fill_gaps, !self.no_concealment, fade_frames * channels);
+        if self.no_drift_compensation || self.playout_delay_ms.is_some() {
+            return mixer;
+        }
+        mixer.compensating_drift(channels)
+    }
+
+    /// The schedule for a send_flow stream, if --playout-delay-ms is set.
+    pub fn playout(&self) -> Option<Playout> {
+        self.playout_delay_ms.map(|ms| Playout::new(Duration::from_millis(ms)))
+    }
+
+    /// --advertise, or else --listen, or `unix:` and the path with --socket.
+    pub fn advertised_address(&self) -> String {
+        self.advertise.clone().unwrap_or_else(|| self.serving_on())
+    }
+
+    /// Where the server listens, the path of --socket or the address of --listen.
+    pub fn serving_on(&self) -> String {
+        match &self.socket {
+            Some(path) => format!("unix:{}", path.display()),
+            None => self.bound(self.listen).unwrap_or(self.listen).to_string(),
+        }
+    }
+
+    /// The address to listen on for `addr`, which is --listen or --metrics-listen, in IPv4 with
+    /// --ipv4-only.
+    pub fn bound(&self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        if self.ipv4_only { ipv4_addr(addr) } else { Ok(addr) }
+    }
+
+    /// The TLS setup from --tls-cert, --tls-key and --tls-client-ca, or `None` with --plaintext
+    /// or --socket.
+    pub fn server_tls(&self) -> anyhow::Result<Option<ServerTlsConfig>> {
+        let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+            if self.plaintext || self.socket.is_some() {
+                return Ok(None);
+            }
+            bail!("no --tls-cert and --tls-key given, pass --plaintext to serve without TLS");
+        };
+        let identity = Identity::from_pem(read(cert)?, read(key)?);
+        let mut tls = ServerTlsConfig::new().identity(identity);
+        if let Some(ca) = &self.tls_client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(read(ca)?));
+        }
+        Ok(Some(tls))
+    }
+
+    /// Logs options that are valid but likely to cause trouble.
+    pub fn warn_suspicious(&self) {
+        if self.ring_capacity < MIN_RING_CAPACITY {
+            warn!(
+                "ring capacity {} holds fewer than {} frames, expect dropouts",
+                self.ring_capacity, MIN_RING_CAPACITY,
+            );
+        }
+        if !(self.jitter_min..=self.jitter_max).contains(&self.jitter_depth) {
+            warn!(
+                "jitter depth {} is outside {}..={}, clamping it",
+                self.jitter_depth, self.jitter_min, self.jitter_max,
+            );
+        }
+        if self.auth_token.is_none() && self.socket.is_none() && !self.listen.ip().is_loopback() {
+            warn!("listening on {} without --auth-token, anyone who can reach it can use the microphone and speaker", self.listen);
+        }
+        if self.jitter_max > self.ring_capacity {
+            warn!(
+                "jitter max {} exceeds the ring capacity {}, the buffer can't grow that deep",
+                self.jitter_max, self.ring_capacity,
+            );
+        }
+    }
+}
+
+/// A value in the --config file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileValue {
+    Flag(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<FileValue>),
+}
+
+impl FileValue {
+    /// Appends the command line arguments that set `--long` to this value.
+    fn push_args(self, long: &str, args: &mut Vec<OsString>) {
+        match self {
+            FileValue::Flag(true) => args.push(format!("--{}", long).into()),
+            FileValue::Flag(false) => {} // the flags default to off
+            FileValue::Integer(value) => args.push(format!("--{}={}", long, value).into()),
+            FileValue::Float(value) => args.push(format!("--{}={}", long, value).into()),
+            FileValue::Text(value) => args.push(format!("--{}={}", long, value).into()),
+            FileValue::List(values) => values.into_iter().for_each(|value| value.push_args(long, args)),
+        }
+    }
+}
+
+fn positive(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be greater than 0".to_string()),
+        Ok(value) => Ok(value),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn octal(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        Ok(_) => Err("must be at most 777".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn at_least_real_time(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed >= 1.0 => Ok(speed),
+        Ok(_) => Err("must be at least 1, or no sender could keep up".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn read(path: &PathBuf) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+}
@@ -0,0 +1,55 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tonic::Status;
+use tonic::metadata::MetadataMap;
+
+const GRPC_TIMEOUT: &str = "grpc-timeout"; // request metadata a client's deadline arrives in
+const MAX_DIGITS: usize = 8; // the most a grpc-timeout value may have, per the gRPC spec
+
+/// How long a unary call may take: `default` (--rpc-timeout-ms), or less if the client's own
+/// deadline in its grpc-timeout metadata comes first. A malformed grpc-timeout is ignored.
+pub fn call_deadline(metadata: &MetadataMap, default: Duration) -> Duration {
+    metadata.get(GRPC_TIMEOUT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(grpc_timeout)
+        .map_or(default, |requested| requested.min(default))
+}
+
+/// A grpc-timeout value, e.g. `100m`: up to MAX_DIGITS digits and a unit.
+fn grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > MAX_DIGITS {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Awaits `call`, or fails with DEADLINE_EXCEEDED naming `what` once `deadline` passes.
+pub async fn within<T>(deadline: Instant, what: &str, call: impl Future<Output = Result<T, Status>>) -> Result<T, Status> {
+    tokio::time::timeout_at(deadline, call).await
+        .unwrap_or_else(|_| Err(Status::deadline_exceeded(format!("{} didn't finish in time", what))))
+}
+
+/// Runs `call`, which may block on the audio system, on a blocking thread instead of a worker,
+/// and gives up on it at `deadline`. A call given up on is left to finish on its thread.
+pub async fn run_blocking<T, F>(deadline: Instant, what: &str, call: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Status> + Send + 'static,
+{
+    within(deadline, what, async {
+        tokio::task::spawn_blocking(call).await
+            .unwrap_or_else(|e| Err(Status::internal(format!("{} panicked: {}", what, e))))
+    }).await
+}
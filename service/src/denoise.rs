@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use nnnoiseless::DenoiseState;
+
+use crate::dsp::Processor;
+use crate::sound_flow::AudioFormat;
+
+pub const SAMPLE_RATE: u32 = 48000; // the only rate RNNoise's model works at
+const FRAME: usize = DenoiseState::FRAME_SIZE; // 10 ms
+const SCALE: f32 = 32768.0; // RNNoise takes samples in the i16 range
+
+/// Noise suppression with RNNoise, one model per capture channel. RNNoise works on whole 10 ms
+/// frames, so the output runs a frame behind the input, plus the frame its overlapping windows
+/// hold back, see `delay`.
+pub struct NoiseSuppressor {
+    states: Vec<Box<DenoiseState<'static>>>,
+    pending: Vec<Vec<f32>>, // per channel, input not yet a whole frame
+    suppressed: VecDeque<f32>, // interleaved output waiting to be handed out
+    scratch: Vec<f32>,
+}
+
+impl NoiseSuppressor {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels.max(1);
+        NoiseSuppressor {
+            states: (0..channels).map(|_| DenoiseState::new()).collect(),
+            pending: vec![Vec::with_capacity(FRAME); channels],
+            // A frame of silence up front, so there is always output for however much input came.
+            suppressed: std::iter::repeat_n(0.0, FRAME * channels).collect(),
+            scratch: vec![0.0; FRAME],
+        }
+    }
+
+    /// How much later the suppressed audio comes out than it went in.
+    pub fn delay() -> Duration {
+        Duration::from_secs_f64(2.0 * FRAME as f64 / SAMPLE_RATE as f64)
+    }
+
+    /// Replaces the interleaved `samples` with as many suppressed ones.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.states.len();
+        for frame in samples.chunks(channels) {
+            for (pending, sample) in self.pending.iter_mut().zip(frame) {
+                pending.push(sample * SCALE);
+            }
+            if self.pending[0].len() == FRAME {
+                self.suppress();
+            }
+        }
+        for sample in samples.iter_mut() {
+            *sample = self.suppressed.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    fn suppress(&mut self) {
+        let channels = self.states.len();
+        let start = self.suppressed.len();
+        self.suppressed.resize(start + FRAME * channels, 0.0);
+        for (channel, (state, pending)) in self.states.iter_mut().zip(&mut self.pending).enumerate() {
+            state.process_frame(&mut self.scratch, pending);
+            for (i, sample) in self.scratch.iter().enumerate() {
+                self.suppressed[start + i * channels + channel] = sample / SCALE;
+            }
+            pending.clear();
+        }
+    }
+}
+
+/// On 48 kHz capture, see SAMPLE_RATE.
+impl Processor for NoiseSuppressor {
+    fn process(&mut self, frame: &mut Vec<f32>, _format: &AudioFormat) {
+        NoiseSuppressor::process(self, frame);
+    }
+
+    fn delay(&self) -> Duration {
+        NoiseSuppressor::delay()
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+use tokio::sync::watch;
+use tonic::Status;
+use tracing::{info, warn};
+
+use crate::sound_flow::{Device, DeviceDirection, DeviceId};
+
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1); // how often the controller is asked for its devices
+
+/// The system's audio devices as the device RPCs see them. Ids are only meaningful to the
+/// controller that handed them out, and within one direction.
+pub trait DeviceController: Send + Sync {
+    /// The playback devices, the capture devices or both, depending on `direction`.
+    fn list(&self, direction: DeviceDirection) -> Result<Vec<Device>, Status>;
+
+    /// Makes `device` the system's default, for new streams as well as the service's own.
+    fn set_default(&self, device: &DeviceId) -> Result<(), Status>;
+
+    /// The default capture device for CAPTURE, or the default playback device for anything else.
+    fn current(&self, direction: DeviceDirection) -> Result<Device, Status>;
+
+    /// How the audio host opens playback device `id`, to play on it besides the default one.
+    fn output(&self, id: u32) -> Result<OutputDevice, Status>;
+
+    /// The system volume of `device`, 1.0 for 100%.
+    fn volume(&self, _device: &DeviceId) -> Result<f32, Status> {
+        Err(Status::unimplemented("this platform's devices have no volume control here"))
+    }
+
+    /// Sets the system volume of `device` and returns what it ended up at.
+    fn set_volume(&self, _device: &DeviceId, _level: f32) -> Result<f32, Status> {
+        Err(Status::unimplemented("this platform's devices have no volume control here"))
+    }
+}
+
+/// A playback device as the audio host opens it, which needn't be the name it is listed under.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputDevice {
+    /// The host's device of this name.
+    Host(String),
+    /// The PulseAudio sink of this name, which the ALSA host plays on through its pulse device.
+    Pulse(String),
+}
+
+impl std::fmt::Display for OutputDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputDevice::Host(name) => write!(f, "\"{}\"", name),
+            OutputDevice::Pulse(sink) => write!(f, "PulseAudio sink \"{}\"", sink),
+        }
+    }
+}
+
+/// PulseAudio where it runs, otherwise what the cpal `host` enumerates.
+pub fn controller(host: cpal::HostId) -> Arc<dyn DeviceController> {
+    #[cfg(target_os = "linux")]
+    match crate::pulse::Pulse::connect() {
+        Ok(pulse) => return Arc::new(pulse),
+        Err(e) => warn!("{}, listing devices through {} instead", e.message(), host.name()),
+    }
+    info!(host = host.name(), "devices can be listed but not switched");
+    Arc::new(Cpal { host })
+}
+
+/// Devices as a cpal host enumerates them, numbered in that order. cpal can't change the
+/// system's defaults, so switching is refused.
+pub struct Cpal {
+    host: cpal::HostId,
+}
+
+impl Cpal {
+    fn devices(&self, direction: DeviceDirection) -> Result<Vec<cpal::Device>, Status> {
+        let host = cpal::host_from_id(self.host).map_err(|e| Status::unavailable(format!("failed to open {}: {}", self.host.name(), e)))?;
+        let devices = match direction {
+            DeviceDirection::Capture => host.input_devices().map(|devices| devices.collect()),
+            _ => host.output_devices().map(|devices| devices.collect()),
+        };
+        devices.map_err(|e| Status::internal(format!("failed to list devices: {}", e)))
+    }
+
+    fn described(&self, direction: DeviceDirection) -> Result<Vec<Device>, Status> {
+        Ok((0..).zip(self.devices(direction)?).map(|(id, device)| Device {
+            id,
+            name: device.name().unwrap_or_else(|_| "Unknown".to_string()),
+            direction: direction.into(),
+            monitor: false, // cpal doesn't tell them apart
+        }).collect())
+    }
+}
+
+impl DeviceController for Cpal {
+    fn list(&self, direction: DeviceDirection) -> Result<Vec<Device>, Status> {
+        let mut devices = Vec::new();
+        if direction != DeviceDirection::Capture {
+            devices.extend(self.described(DeviceDirection::Playback)?);
+        }
+        if direction != DeviceDirection::Playback {
+            devices.extend(self.described(DeviceDirection::Capture)?);
+        }
+        Ok(devices)
+    }
+
+    fn set_default(&self, _device: &DeviceId) -> Result<(), Status> {
+        Err(Status::unimplemented(format!("{} can't change the system's default device", self.host.name())))
+    }
+
+    fn current(&self, direction: DeviceDirection) -> Result<Device, Status> {
+        let direction = if direction == DeviceDirection::Capture { direction } else { DeviceDirection::Playback };
+        let host = cpal::host_from_id(self.host).map_err(|e| Status::unavailable(format!("failed to open {}: {}", self.host.name(), e)))?;
+        let default = match direction {
+            DeviceDirection::Capture => host.default_input_device(),
+            _ => host.default_output_device(),
+        };
+        let name = default.and_then(|device| device.name().ok()).ok_or_else(|| Status::not_found("no default device is set"))?;
+        self.described(direction)?.into_iter().find(|device| device.name == name)
+            .ok_or_else(|| Status::not_found(format!("the default device {} is gone", name)))
+    }
+
+    fn output(&self, id: u32) -> Result<OutputDevice, Status> {
+        let device = self.described(DeviceDirection::Playback)?.into_iter().find(|device| device.id == id)
+            .ok_or_else(|| Status::not_found(format!("no playback device {}", id)))?;
+        Ok(OutputDevice::Host(device.name))
+    }
+}
+
+/// Polls `controller` for its devices, since neither backend offers change notifications here,
+/// and publishes every change to `changes`. Runs until the last receiver is gone. The controllers
+/// block, so each poll runs on a blocking thread.
+pub async fn watch(controller: Arc<dyn DeviceController>, changes: watch::Sender<Vec<Device>>) {
+    let mut reported = false; // only log the first of a run of failures
+    while !changes.is_closed() {
+        let polled = controller.clone();
+        let listed = tokio::task::spawn_blocking(move || polled.list(DeviceDirection::All)).await
+            .unwrap_or_else(|e| Err(Status::internal(format!("listing the devices panicked: {}", e))));
+        match listed {
+            Ok(devices) => {
+                reported = false;
+                changes.send_if_modified(|current| {
+                    if *current == devices {
+                        return false;
+                    }
+                    info!(count = devices.len(), "audio devices changed");
+                    *current = devices;
+                    true
+                });
+            }
+            Err(e) if !reported => {
+                warn!("failed to watch audio devices: {}", e.message());
+                reported = true;
+            }
+            Err(_) => {}
+        }
+        tokio::time::sleep(DEVICE_POLL_INTERVAL).await;
+    }
+}
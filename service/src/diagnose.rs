@@ -0,0 +1,201 @@
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use cpal::{SupportedBufferSize, SupportedStreamConfigRange};
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::config::Config;
+use crate::jitter::Packet;
+use crate::meter::Meter;
+use crate::mirror::Mirrors;
+use crate::sound_flow::AudioFormat;
+use crate::stats::Counters;
+use crate::volume::Gain;
+use crate::setup::SetupError;
+use crate::{audio_host, default_input, default_output, open_input, open_output, StreamHealth};
+
+const TEST_DURATION: Duration = Duration::from_secs(1); // how long the capture and playback tests run
+const TONE_HZ: f32 = 440.0;
+const TONE_LEVEL: f32 = 0.1; // -20 dBFS, audible without being startling
+
+/// Prints a report of the audio hosts, their devices and what they support, then records from
+/// the default input and plays a short tone on the default output, without starting the server.
+/// Failures are part of the report rather than errors, so it always runs to the end.
+pub async fn run(config: &Config) {
+    let mut report = String::new();
+    let _ = writeln!(report, "sf_core {} on {} {}", env!("CARGO_PKG_VERSION"), std::env::consts::OS, std::env::consts::ARCH);
+    let hosts: Vec<_> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+    let _ = writeln!(report, "available hosts: {}", hosts.join(", "));
+    match audio_host(config) {
+        Ok(host) => {
+            let _ = writeln!(report, "selected host: {}", host.id().name());
+            describe_devices(&host, &mut report);
+        }
+        Err(e) => {
+            let _ = writeln!(report, "selected host: failed: {:#}", e);
+        }
+    }
+    print!("{}", report);
+
+    println!("\ncapture test, {:?} from the default input:", TEST_DURATION);
+    match capture_test(config).await {
+        Ok(result) => println!("  ok: {}", result),
+        Err(e) => println!("  failed: {:#}", e),
+    }
+    println!("playback test, a {} Hz tone for {:?} on the default output:", TONE_HZ, TEST_DURATION);
+    match playback_test(config).await {
+        Ok(result) => println!("  ok: {}", result),
+        Err(e) => println!("  failed: {:#}", e),
+    }
+}
+
+/// Prints a table of the default input and output devices with the configs they prefer, without
+/// opening them. Fails only if --host can't be opened, a device that can't be probed is a row
+/// saying why.
+pub fn probe(config: &Config) -> anyhow::Result<()> {
+    let host = audio_host(config)?;
+    println!("host: {}", host.id().name());
+    let rows: Vec<[String; 6]> = [("input", default_input(&host)), ("output", default_output(&host))]
+        .into_iter()
+        .map(|(direction, probed)| match probed {
+            Ok((device, supported)) => [
+                direction.to_string(),
+                device.name().unwrap_or_else(|_| "Unknown".to_string()),
+                format!("{} Hz", supported.sample_rate().0),
+                supported.channels().to_string(),
+                supported.sample_format().to_string(),
+                describe_buffer(supported.buffer_size()),
+            ],
+            Err(SetupError::NoDevice(_)) => [direction.to_string(), "none".to_string(), String::new(), String::new(), String::new(), String::new()],
+            Err(e) => [direction.to_string(), e.to_string(), String::new(), String::new(), String::new(), String::new()],
+        })
+        .collect();
+    let header = ["direction", "device", "sample rate", "channels", "format", "buffer size"].map(str::to_string);
+    let mut widths = [0; 6];
+    for row in std::iter::once(&header).chain(&rows) {
+        widths.iter_mut().zip(row).for_each(|(width, cell)| *width = (*width).max(cell.chars().count()));
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+fn describe_buffer(size: &SupportedBufferSize) -> String {
+    match size {
+        SupportedBufferSize::Range { min, max } => format!("{}-{} frames", min, max),
+        SupportedBufferSize::Unknown => "unknown".to_string(),
+    }
+}
+
+fn describe_devices(host: &cpal::Host, report: &mut String) {
+    let name = |device: &cpal::Device| device.name().unwrap_or_else(|_| "Unknown".to_string());
+    let _ = writeln!(report, "default input: {}", host.default_input_device().map_or("none".to_string(), |device| name(&device)));
+    let _ = writeln!(report, "default output: {}", host.default_output_device().map_or("none".to_string(), |device| name(&device)));
+    for (kind, devices) in [("input", host.input_devices()), ("output", host.output_devices())] {
+        let _ = writeln!(report, "\n{} devices:", kind);
+        let devices = match devices {
+            Ok(devices) => devices,
+            Err(e) => {
+                let _ = writeln!(report, "  failed to list: {}", e);
+                continue;
+            }
+        };
+        let mut listed = 0;
+        for device in devices {
+            listed += 1;
+            let _ = writeln!(report, "  {}", name(&device));
+            let (default, supported) = if kind == "input" {
+                (device.default_input_config().map_err(|e| e.to_string()), device.supported_input_configs().map(|configs| configs.collect::<Vec<_>>()).map_err(|e| e.to_string()))
+            } else {
+                (device.default_output_config().map_err(|e| e.to_string()), device.supported_output_configs().map(|configs| configs.collect::<Vec<_>>()).map_err(|e| e.to_string()))
+            };
+            match default {
+                Ok(default) => {
+                    let _ = writeln!(report, "    default: {} Hz, {} channel(s), {}", default.sample_rate().0, default.channels(), default.sample_format());
+                }
+                Err(e) => {
+                    let _ = writeln!(report, "    default: failed: {}", e);
+                }
+            }
+            match supported {
+                Ok(supported) => supported.iter().for_each(|range| {
+                    let _ = writeln!(report, "    supports: {}", describe_range(range));
+                }),
+                Err(e) => {
+                    let _ = writeln!(report, "    supports: failed: {}", e);
+                }
+            }
+        }
+        if listed == 0 {
+            let _ = writeln!(report, "  none");
+        }
+    }
+}
+
+fn describe_range(range: &SupportedStreamConfigRange) -> String {
+    let (min, max) = (range.min_sample_rate().0, range.max_sample_rate().0);
+    let rates = if min == max { format!("{} Hz", min) } else { format!("{}-{} Hz", min, max) };
+    format!("{}, {} channel(s), {}", rates, range.channels(), range.sample_format())
+}
+
+/// Captures for TEST_DURATION and sums up how much arrived and how loud it was.
+async fn capture_test(config: &Config) -> anyhow::Result<String> {
+    let health = StreamHealth::new(None);
+    let counters = Arc::new(Counters::default());
+    let (mut captured, stream, format) = open_input(config, &health, &Arc::new(AtomicBool::new(false)), &counters, &None)?;
+    let mut meter = Meter::new(&format, 1);
+    let mut samples = 0;
+    let mut levels = None;
+    let deadline = Instant::now() + TEST_DURATION;
+    while Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        while let Some(frame) = captured.pop() {
+            samples += frame.samples.len();
+            levels = meter.feed(&frame.samples).or(levels);
+        }
+    }
+    drop(stream);
+    let expected = expected_samples(&format);
+    let mut result = format!("{} of {} samples expected at {} Hz, {} channel(s)", samples, expected, format.sample_rate, format.channels);
+    if let Some(levels) = levels {
+        for (channel, level) in levels.channels.iter().enumerate() {
+            let _ = write!(result, "; channel {} rms {:.1} dBFS, peak {:.1} dBFS{}", channel, level.rms_dbfs, level.peak_dbfs, if level.clipped { ", clipped" } else { "" });
+        }
+    }
+    anyhow::ensure!(samples > 0, "the input stream started but delivered no audio");
+    Ok(result)
+}
+
+/// Plays a quiet tone for TEST_DURATION, failing if the output stream reports an error.
+async fn playback_test(config: &Config) -> anyhow::Result<String> {
+    let health = StreamHealth::new(None);
+    let counters = Arc::new(Counters::default());
+    let (ring, stream, format) = open_output(config, &health, &Gain::new(1.0), &Arc::new(AtomicBool::new(false)), &counters, &None, &Mirrors::default())?;
+    let channels = format.channels as usize;
+    let tone: Vec<f32> = (0..expected_samples(&format) / channels.max(1))
+        .flat_map(|frame| {
+            let sample = TONE_LEVEL * (2.0 * std::f32::consts::PI * TONE_HZ * frame as f32 / format.sample_rate as f32).sin();
+            std::iter::repeat_n(sample, channels)
+        })
+        .collect();
+    let package_size = config.package_samples(channels);
+    let period = Duration::from_secs_f64((package_size / channels.max(1)) as f64 / format.sample_rate as f64);
+    let mut ticks = tokio::time::interval(period);
+    let mut lane = ring.open();
+    for (seq, samples) in (1..).zip(tone.chunks(package_size)) {
+        ticks.tick().await;
+        lane.push(Packet { stream: 1, seq, samples: samples.to_vec(), captured: None, due: None, end: false }, config.overflow).await;
+    }
+    tokio::time::sleep(period * config.jitter_depth as u32 + period).await; // let the jitter buffer drain
+    drop(stream);
+    anyhow::ensure!(health.ok(), "the output stream reported an error");
+    Ok(format!("{} Hz, {} channel(s)", format.sample_rate, format.channels))
+}
+
+fn expected_samples(format: &AudioFormat) -> usize {
+    (TEST_DURATION.as_secs_f64() * format.sample_rate as f64 * format.channels as f64) as usize
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const WINDOW: Duration = Duration::from_secs(10); // of playback the buffer level is averaged over
+const HISTORY: usize = 60; // windows the trend is fitted to, 10 minutes
+const MIN_HISTORY: usize = 6; // windows needed before the trend is trusted
+const SETTLE_SECS: f64 = 60.0; // over which a level off the centre is brought back, on top of the drift
+const MAX_PPM: f64 = 1000.0; // the most drift compensated, far beyond any sound card's clock
+
+/// Keeps a stream's buffer level centred when its sender's clock runs at a slightly different
+/// rate from the one playing it, which over hours would fill or drain the buffer. The level is
+/// averaged over WINDOW, the drift estimated from the trend of those averages over HISTORY, and
+/// packages are stretched or squeezed by a frame as often as the drift and the level's distance
+/// from the centre call for. The level only moves by whole packages as the sender's packages
+/// slip past the speaker's, so the trend takes minutes to tell drift from that.
+pub struct DriftCompensator {
+    channels: usize,
+    played: f64, // seconds played since the trend was last restarted
+    elapsed: f64, // seconds played in the current window
+    level: f64, // frames buffered, times the seconds they were buffered for, over the current window
+    drifted: f64, // the same as if nothing had been corrected
+    history: VecDeque<(f64, f64)>, // when each window ended and its average level as if nothing had been corrected
+    corrected: f64, // frames dropped less frames inserted
+    error: f64, // frames the last window's average level was off the centre by, beyond half a package
+    owed: f64, // frames to drop, or insert if negative, that don't add up to a whole one yet
+    pub ppm: f64, // how much faster the sender's clock runs than ours
+    pub corrections: u64, // frames dropped or inserted
+}
+
+impl DriftCompensator {
+    pub fn new(channels: usize) -> Self {
+        DriftCompensator {
+            channels: channels.max(1),
+            played: 0.0,
+            elapsed: 0.0,
+            level: 0.0,
+            drifted: 0.0,
+            history: VecDeque::new(),
+            corrected: 0.0,
+            error: 0.0,
+            owed: 0.0,
+            ppm: 0.0,
+            corrections: 0,
+        }
+    }
+
+    /// Forgets the level's trend after it jumped, such as when the stream ran dry.
+    pub fn restart(&mut self) {
+        self.elapsed = 0.0;
+        self.level = 0.0;
+        self.drifted = 0.0;
+        self.history.clear();
+    }
+
+    /// `package`, popped with `level` samples buffered against a centre of `centre` and lasting
+    /// `duration`, a frame shorter or longer if one is due.
+    pub fn correct(&mut self, package: Vec<f32>, level: usize, centre: usize, duration: Duration) -> Vec<f32> {
+        let frames = package.len() / self.channels;
+        let secs = duration.as_secs_f64();
+        if frames < 3 || secs <= 0.0 {
+            return package;
+        }
+        let rate = frames as f64 / secs;
+        let level = (level / self.channels) as f64;
+        self.level += level * secs;
+        self.drifted += (level + self.corrected) * secs;
+        self.elapsed += secs;
+        self.played += secs;
+        if self.elapsed >= WINDOW.as_secs_f64() {
+            self.estimate(rate, (centre / self.channels) as f64, frames as f64);
+        }
+        self.owed = (self.owed + (self.ppm * 1e-6 * rate + self.error / SETTLE_SECS) * secs).clamp(-2.0, 2.0);
+        if self.owed >= 1.0 {
+            self.owed -= 1.0;
+            self.corrected += 1.0;
+            self.corrections += 1;
+            stretch(&package, self.channels, frames - 1)
+        } else if self.owed <= -1.0 {
+            self.owed += 1.0;
+            self.corrected -= 1.0;
+            self.corrections += 1;
+            stretch(&package, self.channels, frames + 1)
+        } else {
+            package
+        }
+    }
+
+    /// Closes the window, at `rate` frames a second, updating the drift from the trend and the
+    /// error from how far the window was off `centre`, a deadband of half a package around it.
+    fn estimate(&mut self, rate: f64, centre: f64, frames: f64) {
+        let off = self.level / self.elapsed - centre;
+        self.error = off.signum() * (off.abs() - frames / 2.0).max(0.0);
+        self.history.push_back((self.played, self.drifted / self.elapsed));
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        if self.history.len() >= MIN_HISTORY {
+            self.ppm = (slope(&self.history) / rate * 1e6).clamp(-MAX_PPM, MAX_PPM);
+        }
+        self.elapsed = 0.0;
+        self.level = 0.0;
+        self.drifted = 0.0;
+    }
+}
+
+/// The least-squares slope of `points`.
+fn slope(points: &VecDeque<(f64, f64)>) -> f64 {
+    let n = points.len() as f64;
+    let (x, y) = points.iter().fold((0.0, 0.0), |(x, y), &(px, py)| (x + px / n, y + py / n));
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), &(px, py)| (c + (px - x) * (py - y), v + (px - x) * (px - x)));
+    if variance > 0.0 { covariance / variance } else { 0.0 }
+}
+
+/// `package` resampled by linear interpolation to `frames` frames, keeping its first and last, so
+/// a frame more or less spread over a whole package is a pitch change far too small to hear.
+fn stretch(package: &[f32], channels: usize, frames: usize) -> Vec<f32> {
+    let from = package.len() / channels;
+    let step = (from - 1) as f64 / (frames - 1) as f64;
+    (0..frames)
+        .flat_map(|i| {
+            let position = i as f64 * step;
+            let j = (position as usize).min(from - 1);
+            let k = (j + 1).min(from - 1);
+            let t = (position - j as f64) as f32;
+            (0..channels).map(move |c| package[j * channels + c] * (1.0 - t) + package[k * channels + c] * t)
+        })
+        .collect()
+}
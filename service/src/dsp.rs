@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::sound_flow::AudioFormat;
+use crate::stats::Counters;
+use crate::volume::{Gain, Smoother};
+
+/// A transform applied to every frame of interleaved samples in `format` on the capture or
+/// playback path. A processor may change the frame's length, and one that empties it drops the
+/// frame: nothing after it in a Chain sees it.
+pub trait Processor: Send {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat);
+
+    /// How much later the processed audio comes out than it went in.
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Leaves every frame as it is.
+pub struct Passthrough;
+
+impl Processor for Passthrough {
+    fn process(&mut self, _frame: &mut Vec<f32>, _format: &AudioFormat) {}
+}
+
+/// Scales frames by a shared `volume`, or to silence while `muted`, ramping between levels so
+/// changes don't click.
+pub struct GainProcessor {
+    volume: Gain,
+    muted: Arc<AtomicBool>,
+    smoother: Option<Smoother>, // made with the first frame, which says how many channels there are
+}
+
+impl GainProcessor {
+    pub fn new(volume: Gain, muted: Arc<AtomicBool>) -> Self {
+        GainProcessor { volume, muted, smoother: None }
+    }
+
+    fn target(&self) -> f32 {
+        if self.muted.load(Ordering::Relaxed) { 0.0 } else { self.volume.get() }
+    }
+}
+
+impl Processor for GainProcessor {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        let target = self.target();
+        let smoother = self.smoother.get_or_insert_with(|| Smoother::new(target, (format.channels as usize).max(1)));
+        smoother.apply(target, frame);
+    }
+}
+
+/// Ramps playback up over `duration` whenever it starts from silence, so sound that starts at
+/// full level doesn't pop. Silence is a frame of nothing but zeros, such as the speaker plays
+/// while nothing is mixed.
+pub struct FadeIn {
+    duration: Duration,
+    gain: f32, // of the last frame played
+}
+
+impl FadeIn {
+    pub fn new(duration: Duration) -> Self {
+        FadeIn { duration, gain: 0.0 }
+    }
+}
+
+impl Processor for FadeIn {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        if frame.iter().all(|&sample| sample == 0.0) {
+            self.gain = 0.0;
+            return;
+        }
+        let frames = self.duration.as_secs_f32() * format.sample_rate as f32;
+        if self.gain >= 1.0 || frames < 1.0 {
+            self.gain = 1.0;
+            return;
+        }
+        for samples in frame.chunks_mut((format.channels as usize).max(1)) {
+            self.gain = (self.gain + 1.0 / frames).min(1.0);
+            samples.iter_mut().for_each(|sample| *sample *= self.gain);
+        }
+    }
+}
+
+/// Runs `processor` and keeps how long it took on the latest frame, in microseconds, in the
+/// counter `counter` picks.
+pub struct Timed<P> {
+    processor: P,
+    counters: Arc<Counters>,
+    counter: fn(&Counters) -> &AtomicU64,
+}
+
+impl<P: Processor> Timed<P> {
+    pub fn new(processor: P, counters: Arc<Counters>, counter: fn(&Counters) -> &AtomicU64) -> Self {
+        Timed { processor, counters, counter }
+    }
+}
+
+impl<P: Processor> Processor for Timed<P> {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        let started = Instant::now();
+        self.processor.process(frame, format);
+        (self.counter)(&self.counters).store(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn delay(&self) -> Duration {
+        self.processor.delay()
+    }
+}
+
+/// Processors applied one after the other, in the order they were pushed.
+#[derive(Default)]
+pub struct Chain {
+    processors: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+    pub fn push(&mut self, processor: impl Processor + 'static) {
+        self.processors.push(Box::new(processor));
+    }
+}
+
+impl Processor for Chain {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        for processor in &mut self.processors {
+            if frame.is_empty() {
+                return;
+            }
+            processor.process(frame, format);
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        self.processors.iter().map(|processor| processor.delay()).sum()
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::sound_flow::Flow;
+
+const SHRINK_AFTER: u32 = 500; // frames sent without trouble before the frame size halves, ~5 s of default frames
+
+/// Regroups interleaved samples into frames of `min` times a factor of up to `max_factor`. The
+/// factor doubles when the link struggles and halves again after SHRINK_AFTER calm frames, and
+/// the first frame of every new size announces it in `package_size`. It changes at most once per
+/// frame sent, so a burst of trouble doesn't jump straight to the largest size. Whole frames of
+/// `min` in, whole frames out, so channels stay aligned.
+pub struct Framer {
+    min: usize,
+    max_factor: usize,
+    factor: usize,
+    pending: Vec<f32>,
+    captured_ns: u64, // of the first pending sample
+    sample_ns: f64, // per interleaved sample, to time what is left over by, 0 if unknown
+    announced: usize, // the size the receiver was last told about
+    calm: u32, // frames since the last sign of trouble
+    settled: bool, // a frame of the current size went out since it last changed
+    peer_underruns: Option<u64>, // last total a Duplex peer reported
+}
+
+impl Framer {
+    pub fn new(min: usize, max_factor: usize) -> Self {
+        Framer {
+            min,
+            max_factor: max_factor.max(1),
+            factor: 1,
+            pending: Vec::new(),
+            captured_ns: 0,
+            sample_ns: 0.0,
+            announced: 0,
+            calm: 0,
+            settled: true,
+            peer_underruns: None,
+        }
+    }
+
+    /// Frames of exactly `size` samples, never adapted.
+    pub fn fixed(size: usize) -> Self {
+        Framer::new(size, 1)
+    }
+
+    /// Times what is left over after a frame from the samples before it, at `sample_rate` and
+    /// `channels`, rather than giving it the capture time of the samples it was pushed with.
+    pub fn timed(mut self, sample_rate: u32, channels: usize) -> Self {
+        self.sample_ns = 1e9 / (sample_rate.max(1) as f64 * channels.max(1) as f64);
+        self
+    }
+
+    pub fn size(&self) -> usize {
+        self.min * self.factor
+    }
+
+    /// Adds `samples`, captured at `captured_ns`, and returns the frames they complete.
+    pub fn push(&mut self, samples: &[f32], captured_ns: u64) -> Vec<Flow> {
+        if self.pending.is_empty() {
+            self.captured_ns = captured_ns;
+        }
+        self.pending.extend_from_slice(samples);
+        let mut frames = Vec::new();
+        while self.pending.len() >= self.size() {
+            let rest = self.pending.split_off(self.size());
+            let flow = std::mem::replace(&mut self.pending, rest);
+            let package_size = if self.size() == self.announced { 0 } else { self.size() as u32 };
+            self.announced = self.size();
+            let len = flow.len();
+            frames.push(Flow { flow, captured_ns: self.captured_ns, package_size, ..Default::default() });
+            self.captured_ns = match self.captured_ns {
+                0 => captured_ns,
+                _ if self.sample_ns == 0.0 => captured_ns, // close enough for what is left over
+                first => first + (len as f64 * self.sample_ns) as u64,
+            };
+            self.settled = true;
+            self.calm += 1;
+            if self.calm >= SHRINK_AFTER && self.factor > 1 {
+                self.resize(self.factor / 2);
+            }
+        }
+        frames
+    }
+
+    /// What is left over, as a last frame shorter than the rest, if anything is.
+    pub fn flush(&mut self) -> Option<Flow> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(Flow { flow: std::mem::take(&mut self.pending), captured_ns: self.captured_ns, ..Default::default() })
+    }
+
+    /// Drops what is left over without sending it, when the samples that follow aren't the ones
+    /// that came after it, so no frame splices audio across a gap.
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    /// The link is struggling, the receiver's queue backed up for instance: send bigger frames.
+    pub fn congested(&mut self) {
+        if self.settled && self.factor < self.max_factor {
+            self.resize(self.factor * 2);
+        }
+        self.calm = 0;
+    }
+
+    /// Takes in the underrun total a Duplex peer reports, counting any new ones as congestion.
+    pub fn peer_underruns(&mut self, underruns: u64) {
+        if self.peer_underruns.is_some_and(|seen| underruns > seen) {
+            self.congested();
+        }
+        self.peer_underruns = Some(underruns);
+    }
+
+    fn resize(&mut self, factor: usize) {
+        self.factor = factor.clamp(1, self.max_factor);
+        self.calm = 0;
+        self.settled = false;
+        debug!(package_size = self.size(), "frame size changed");
+    }
+}
+
+/// Splits an input callback's interleaved `samples` into packages of `package_size`, timed from
+/// `at` on by `package_duration` each. The last one is shorter when the callback doesn't fill
+/// it; a callback is whole frames, so with `package_size` a whole number of frames as well, see
+/// `Config::package_samples`, no package splits a frame between its channels.
+pub fn capture_packages(samples: &[f32], package_size: usize, at: Instant, package_duration: Duration) -> impl Iterator<Item = (&[f32], Instant)> {
+    (0..).zip(samples.chunks(package_size)).map(move |(n, package)| (package, at + package_duration * n))
+}
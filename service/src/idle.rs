@@ -0,0 +1,23 @@
+use std::time::{Duration, Instant};
+
+/// When to turn the microphone off for --capture-idle-ms: once nothing has used the capture, no
+/// listener, monitor or recording, for the whole timeout, and back on as soon as something does.
+pub struct CaptureIdle {
+    timeout: Duration,
+    idle_since: Option<Instant>, // when the capture was last in use, `None` while it is
+}
+
+impl CaptureIdle {
+    pub fn new(timeout: Duration) -> Self {
+        CaptureIdle { timeout, idle_since: None }
+    }
+
+    /// Whether the capture should be paused at `now`, given whether anything uses it.
+    pub fn paused(&mut self, in_use: bool, now: Instant) -> bool {
+        if in_use {
+            self.idle_since = None;
+            return false;
+        }
+        now - *self.idle_since.get_or_insert(now) >= self.timeout
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Instant;
+
+use tracing::{debug, info, warn};
+
+const ADAPT_WINDOW: u64 = 500; // packages played without an underrun before the target depth shrinks by one
+const CONCEAL_FRAMES: usize = 3; // longest run of missing packages concealed, longer dropouts fade to silence
+const MATCH: usize = 32; // samples at the end of the last package compared when looking for its period
+const MIN_PERIOD: usize = 32; // shortest stretch repeated, shorter ones match any smooth signal
+const CROSSFADE: usize = 64; // samples over which real audio takes back over from a stand-in
+
+/// One decoded frame on its way from a send_flow stream to the speaker.
+pub struct Packet {
+    pub stream: u64, // which send_flow stream it came from, each is mixed in through its own jitter buffer
+    pub seq: u64,
+    pub samples: Vec<f32>,
+    pub captured: Option<Instant>, // when it was captured, if on this machine, for measuring latency
+    pub due: Option<Instant>, // when it should be heard, with --playout-delay-ms
+    pub end: bool, // marks the end of the stream instead of carrying samples, numbered after its last package
+}
+
+impl Packet {
+    /// The marker a send_flow stream's last package, `seq - 1`, is followed by.
+    pub fn end(stream: u64, seq: u64) -> Self {
+        Packet { stream, seq, samples: Vec::new(), captured: None, due: None, end: true }
+    }
+}
+
+/// Holds back the packages of one stream until `target` of them are buffered, then plays them in `seq`
+/// order. Each underrun grows the target by one up to `max`, and a long enough run without one
+/// shrinks it again down to `min`. Missing packages are concealed by repeating the last period of
+/// the last one played while fading it out over CONCEAL_FRAMES packages, which clicks far less
+/// than silence. Once the end of the stream is marked, what is buffered plays out, the last
+/// `fade_out` samples faded out, or all of the last package if it is shorter, without any
+/// concealment after it.
+///
+/// A package that is `due` at a set time plays then instead, to within a package and whatever
+/// the depth: it waits if it is early and is dropped if it is late.
+pub struct JitterBuffer {
+    frames: BTreeMap<u64, Packet>,
+    next: Option<u64>, // the seq to play next, unknown until playback starts
+    buffering: bool, // waiting for `target` packages before (re)starting playback
+    target: usize,
+    min: usize,
+    max: usize,
+    fill_gaps: bool,
+    conceal: bool,
+    fade_out: usize, // samples at the end of the stream faded out
+    end: Option<u64>, // seq of the end marker, once it arrived
+    last: Vec<f32>, // the last package played, repeated to conceal missing ones
+    concealed: usize, // packages concealed since `last` was played
+    period: usize, // samples at the end of `last` being repeated
+    offset: usize, // samples of the repetition played so far
+    since_underrun: u64,
+    pub underruns: u64,
+    pub late: u64, // packages dropped for arriving after they were due
+    pub captured: Option<Instant>, // of the package pop last returned, left for the caller to take
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize, min: usize, max: usize, fill_gaps: bool, conceal: bool, fade_out: usize) -> Self {
+        JitterBuffer {
+            frames: BTreeMap::new(),
+            next: None,
+            buffering: true,
+            target: depth.max(min).min(max),
+            min,
+            max,
+            fill_gaps,
+            conceal,
+            fade_out,
+            end: None,
+            last: Vec::new(),
+            concealed: 0,
+            period: 0,
+            offset: 0,
+            since_underrun: 0,
+            underruns: 0,
+            late: 0,
+            captured: None,
+        }
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        if self.next.is_some_and(|next| packet.seq < next) {
+            debug!(seq = packet.seq, "frame arrived too late to be played");
+            return;
+        }
+        if packet.end {
+            self.end = Some(packet.seq);
+            return;
+        }
+        self.frames.insert(packet.seq, packet);
+        while self.frames.len() > self.max {
+            // More than the deepest allowed buffer piled up, catch up rather than lag further behind.
+            if let Some((seq, _)) = self.frames.pop_first() {
+                self.next = Some(seq + 1);
+            }
+        }
+    }
+
+    /// Whether the stream ended and everything it sent has been played.
+    pub fn finished(&self) -> bool {
+        self.end.is_some() && self.frames.is_empty()
+    }
+
+    /// Packages buffered right now.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Packages it buffers before playing, which is where drift compensation keeps it.
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    /// The next package to play, to be heard during `slot`, or `None` while buffering with
+    /// nothing left to conceal.
+    pub fn pop(&mut self, slot: &Range<Instant>) -> Option<Vec<f32>> {
+        let len = slot.end - slot.start;
+        let mut scheduled = false;
+        while let Some(due) = self.frames.first_key_value().and_then(|(_, packet)| packet.due) {
+            // Up to a whole slot late still plays, so a package due right between two slots
+            // doesn't flip between waiting and being dropped as the speaker's timing wobbles.
+            if due + len < slot.start {
+                if let Some((seq, _)) = self.frames.pop_first() {
+                    debug!(seq, late = ?slot.start - due, "frame missed its playout time");
+                    self.next = Some(seq + 1);
+                }
+                self.late += 1;
+                continue;
+            }
+            if due >= slot.start + len / 2 {
+                return self.stand_in(); // early, it waits for its slot
+            }
+            scheduled = true;
+            break;
+        }
+        if scheduled {
+            self.buffering = false;
+        }
+        if self.buffering {
+            // A stream that ended won't send enough to fill the buffer, so play what there is.
+            if self.frames.len() < self.target && self.end.is_none() {
+                return self.stand_in();
+            }
+            self.buffering = false;
+            debug!(depth = self.frames.len(), target = self.target, "jitter buffer filled, playing");
+        }
+        let Some((&seq, frame)) = self.frames.first_key_value() else {
+            if self.end.is_some() {
+                return None; // played out, not an underrun
+            }
+            self.underrun();
+            return self.stand_in();
+        };
+        let next = self.next.unwrap_or(seq);
+        let missing = seq - next;
+        let len = frame.samples.len();
+        // A package played when it is due keeps the timing without a stand-in for what went missing.
+        let fill = !scheduled && self.fill_gaps && missing > 0 && missing <= self.max as u64;
+        self.since_underrun += 1;
+        if self.since_underrun >= ADAPT_WINDOW && self.target > self.min {
+            self.since_underrun = 0;
+            self.target -= 1;
+            info!(target = self.target, depth = self.frames.len(), "jitter buffer shrunk");
+        }
+        if fill {
+            // Play something where the lost frame would have been so later frames keep their timing.
+            self.next = Some(next + 1);
+            return Some(self.stand_in().unwrap_or_else(|| vec![0.0; len]));
+        }
+        self.next = Some(seq + 1);
+        let packet = self.frames.remove(&seq)?;
+        self.captured = packet.captured;
+        let mut frame = packet.samples;
+        if self.concealed > 0 {
+            // Crossfade from where the stand-in left off, so resuming doesn't click either.
+            let n = CROSSFADE.min(frame.len());
+            for (i, sample) in frame.iter_mut().take(n).enumerate() {
+                let t = i as f32 / n as f32;
+                *sample = *sample * t + self.repeated(i) * (1.0 - t);
+            }
+        }
+        if self.end.is_some() && self.frames.is_empty() {
+            let n = self.fade_out.min(frame.len());
+            let start = frame.len() - n;
+            frame[start..].iter_mut().enumerate().for_each(|(i, sample)| *sample *= 1.0 - i as f32 / n as f32);
+            self.last.clear(); // nothing to conceal after the end
+        } else {
+            self.last.clone_from(&frame);
+        }
+        self.concealed = 0;
+        Some(frame)
+    }
+
+    /// The last period of the last package played, repeated and faded further towards silence
+    /// with each package, or `None` once it has been repeated for CONCEAL_FRAMES packages.
+    fn stand_in(&mut self) -> Option<Vec<f32>> {
+        if !self.conceal || self.last.is_empty() || self.concealed >= CONCEAL_FRAMES {
+            return None;
+        }
+        if self.concealed == 0 {
+            self.period = period(&self.last);
+            self.offset = 0;
+        }
+        let frame = (0..self.last.len()).map(|i| self.repeated(i)).collect();
+        self.offset += self.last.len();
+        self.concealed += 1;
+        Some(frame)
+    }
+
+    /// Sample `i` of the next stand-in, faded by how far into the concealment it falls.
+    fn repeated(&self, i: usize) -> f32 {
+        let len = self.last.len();
+        let fade = 1.0 - (self.concealed as f32 + i as f32 / len as f32) / CONCEAL_FRAMES as f32;
+        self.last[len - self.period + (self.offset + i) % self.period] * fade.max(0.0)
+    }
+
+    fn underrun(&mut self) {
+        self.buffering = true;
+        self.since_underrun = 0;
+        self.underruns += 1;
+        if self.target < self.max {
+            self.target += 1;
+        }
+        warn!(target = self.target, "output buffer ran dry, rebuffering: try increasing latency");
+    }
+}
+
+/// The length of the stretch at the end of `samples` that best continues where they end, found
+/// by comparing their last MATCH samples with earlier ones. The whole package if it is too short.
+fn period(samples: &[f32]) -> usize {
+    let len = samples.len();
+    if len < MIN_PERIOD + MATCH {
+        return len;
+    }
+    let tail = &samples[len - MATCH..];
+    let energy = |window: &[f32]| window.iter().map(|x| x * x).sum::<f32>();
+    (MIN_PERIOD..=len - MATCH)
+        .map(|lag| {
+            let earlier = &samples[len - MATCH - lag..len - lag];
+            let dot: f32 = tail.iter().zip(earlier).map(|(a, b)| a * b).sum();
+            (lag, dot / (energy(tail) * energy(earlier)).sqrt().max(f32::EPSILON))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(len, |(lag, _)| lag)
+}
@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const WINDOW: usize = 512; // latencies the averages are taken over, about 5 s of 10 ms packages
+const MAX_LATENCY: Duration = Duration::from_secs(10); // older capture times can't be ours and are ignored
+
+/// Where Flow.captured_ns counts from. Only this process can turn them back into instants, so
+/// latency is only measured on frames that were captured here and came back. It lies a little
+/// before first use, so frames captured just before that still get a timestamp.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(|| Instant::now().checked_sub(MAX_LATENCY).unwrap_or_else(Instant::now))
+}
+
+/// `at` as a Flow.captured_ns, never 0 since that means unknown.
+pub fn to_ns(at: Instant) -> u64 {
+    (at.saturating_duration_since(epoch()).as_nanos() as u64).max(1)
+}
+
+/// The instant a Flow.captured_ns stands for, or `None` if it is unset or can't have come from
+/// this process's clock, e.g. because another server captured the frame.
+pub fn from_ns(ns: u64) -> Option<Instant> {
+    if ns == 0 {
+        return None;
+    }
+    let at = epoch().checked_add(Duration::from_nanos(ns))?;
+    let age = Instant::now().checked_duration_since(at)?;
+    (age <= MAX_LATENCY).then_some(at)
+}
+
+/// When the packages of one send_flow stream are due: `delay` after they were captured. A
+/// capture time on another server's clock can't be compared with ours, so those are timed from
+/// the package that took the least time to arrive so far, as if it had come at once.
+pub struct Playout {
+    delay: Duration,
+    quickest: Option<(Instant, u64)>, // arrival and captured_ns of that package
+}
+
+impl Playout {
+    pub fn new(delay: Duration) -> Self {
+        Playout { delay, quickest: None }
+    }
+
+    /// When a package captured at `captured_ns` that arrived just now is due, if it says when.
+    pub fn due(&mut self, captured_ns: u64) -> Option<Instant> {
+        if captured_ns == 0 {
+            return None;
+        }
+        if let Some(captured) = from_ns(captured_ns) {
+            return Some(captured + self.delay);
+        }
+        let now = Instant::now();
+        let (arrived, ns) = match self.quickest {
+            // Arrived sooner after its capture than the quickest one did, so it is the quickest now.
+            Some((arrived, ns)) if (now.duration_since(arrived).as_nanos() as i128) < captured_ns as i128 - ns as i128 => (now, captured_ns),
+            Some(quickest) => quickest,
+            None => (now, captured_ns),
+        };
+        self.quickest = Some((arrived, ns));
+        let at = match captured_ns.checked_sub(ns) {
+            Some(after) => arrived + Duration::from_nanos(after),
+            None => arrived.checked_sub(Duration::from_nanos(ns - captured_ns))?,
+        };
+        Some(at + self.delay)
+    }
+}
+
+/// The last WINDOW capture-to-playback latencies, recorded by the output callback without locking.
+pub struct Latencies {
+    micros: Box<[AtomicU64]>,
+    recorded: AtomicUsize, // latencies recorded in total, the next one goes at this index modulo WINDOW
+}
+
+pub struct Summary {
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Latencies { micros: (0..WINDOW).map(|_| AtomicU64::new(0)).collect(), recorded: AtomicUsize::new(0) }
+    }
+}
+
+impl Latencies {
+    pub fn record(&self, latency: Duration) {
+        let index = self.recorded.fetch_add(1, Ordering::Relaxed) % WINDOW;
+        self.micros[index].store(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Average and percentiles over the window, `None` until a latency has been recorded.
+    pub fn summary(&self) -> Option<Summary> {
+        let recorded = self.recorded.load(Ordering::Relaxed).min(WINDOW);
+        if recorded == 0 {
+            return None;
+        }
+        let mut micros: Vec<u64> = self.micros[..recorded].iter().map(|m| m.load(Ordering::Relaxed)).collect();
+        micros.sort_unstable();
+        let percentile = |p: usize| Duration::from_micros(micros[(recorded * p / 100).min(recorded - 1)]);
+        Some(Summary {
+            mean: Duration::from_micros(micros.iter().sum::<u64>() / recorded as u64),
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        })
+    }
+}
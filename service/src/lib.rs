@@ -0,0 +1,2121 @@
+#![allow(clippy::result_large_err)] // tonic::Status is large, but it is what every handler helper returns
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use prost::Message;
+use cpal::{BuildStreamError, Stream};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapConsumer, HeapRb};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio::sync::broadcast::{channel, error::RecvError, Sender};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status, Streaming};
+use tonic::server::NamedService;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Server, ServerTlsConfig};
+use tonic::transport::server::Router;
+use tonic_health::ServingStatus;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tracing::{debug, error, info, instrument, warn, Instrument};
+use tracing_subscriber::{EnvFilter, Layer};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::aec::EchoCancellation;
+use crate::auth::require_token;
+use crate::codec::{opus_supports, OpusDecoder, OpusEncoder};
+use crate::config::LogFormat;
+use crate::deadline::within;
+use crate::framing::Framer;
+use crate::devices::{DeviceController, OutputDevice};
+use crate::dsp::Timed;
+use crate::limit::RateLimit;
+use crate::playback::PlaybackSender;
+use crate::recording::{recording_path, Recording};
+use crate::resample::Resampler;
+use crate::rooms::{requested_room, Room, Rooms};
+use crate::samples::encoding;
+use crate::sequence::{Arrival, SequenceTracker};
+use crate::switch::{Switchboard, LISTENER_HEADER};
+use crate::tasks::Tasks;
+use crate::throughput::Throughput;
+use crate::sound_flow::{AudioFormat, BufferHealth, BufferLevels, BufferWatch, Codec, CodecSwitch, CodecSwitched, Device, DeviceDirection, DeviceId, DeviceVolume, Devices, Direction, Encoding, Flow, FlowRequest, Levels, Mute, MuteState, OutputDevices, RecordingRequest, RecordingSummary, SampleFormat, ServerInfo, Stats, StreamConfig, TestTone, Volume};
+use crate::sound_flow::sound_flow_server::{SoundFlow, SoundFlowServer};
+
+mod aec;
+mod auth;
+mod channels;
+mod codec;
+mod config;
+mod deadline;
+mod denoise;
+mod devices;
+mod diagnose;
+mod drift;
+mod dsp;
+mod framing;
+mod idle;
+mod jitter;
+mod latency;
+mod limit;
+mod listen;
+mod meter;
+mod metrics;
+mod mirror;
+mod mixer;
+mod normalize;
+mod playback;
+#[cfg(target_os = "linux")]
+mod pulse;
+mod recording;
+mod resample;
+mod rooms;
+mod samples;
+mod sequence;
+mod setup;
+mod stats;
+mod switch;
+mod tasks;
+mod telemetry;
+mod throughput;
+mod tone;
+mod vad;
+mod virtual_device;
+mod volume;
+mod web;
+
+pub use crate::aec::{EchoCanceller, EchoReference};
+pub use crate::channels::{remap, ChannelMap};
+pub use crate::config::{Compression, Config, Preset};
+pub use crate::deadline::{call_deadline, run_blocking};
+pub use crate::denoise::NoiseSuppressor;
+pub use crate::drift::DriftCompensator;
+pub use crate::dsp::{Chain, FadeIn, GainProcessor, Passthrough, Processor};
+pub use crate::framing::capture_packages;
+pub use crate::idle::CaptureIdle;
+pub use crate::jitter::{JitterBuffer, Packet};
+pub use crate::listen::{bind_listener, bind_socket, ipv4_addr, Bound};
+pub use crate::mirror::{Mirror, Mirrored, Mirrors};
+pub use crate::mixer::Mixer;
+pub use crate::normalize::Normalizer;
+pub use crate::playback::{Accumulator, Lane, PlaybackRing};
+pub use crate::samples::{from_payload, to_payload, DeviceSample};
+pub use crate::setup::SetupError;
+pub use crate::stats::Counters;
+pub use crate::virtual_device::serve_loopback;
+pub use crate::volume::Gain;
+
+pub mod sound_flow {
+    tonic::include_proto!("sound_flow");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("sound_flow_descriptor");
+}
+
+/// The SoundFlow gRPC service, as `run_with` and `serve_loopback` build it around their devices.
+pub struct SoundFlowService {
+    config: Arc<Config>,
+    consumer: Sender<Result<Flow, ()>>,
+    playback_ring: Arc<Mutex<PlaybackRing>>, // replaced along with the output stream
+    flows: AtomicU64, // numbers send_flow streams and test tones for the jitter buffer
+    counters: Arc<Counters>,
+    capture_format: Arc<Mutex<AudioFormat>>, // the format the current input stream was built with
+    playback_format: Arc<Mutex<AudioFormat>>, // the format the current output stream was built with
+    capture_open: Arc<AtomicBool>, // false while there is no input device, until one appears
+    playback_open: Arc<AtomicBool>,
+    negotiated_format: Arc<Mutex<Option<AudioFormat>>>, // what senders agreed to send, if any did
+    audio: mpsc::Sender<AudioCommand>,
+    controller: Arc<dyn DeviceController>,
+    devices: watch::Receiver<Vec<Device>>, // the latest snapshot from devices::watch
+    levels: watch::Receiver<Levels>, // the latest capture levels from meter::run
+    recording: Arc<Mutex<Option<Recording>>>,
+    rooms: Arc<Rooms>,
+    volume: Gain, // gain the output callback applies to everything it plays
+    capture_muted: Arc<AtomicBool>, // checked by the input callback, muted capture streams silence
+    playback_muted: Arc<AtomicBool>,
+    switchboard: Arc<Switchboard>, // the listeners SetCodec can reach
+    tls: bool, // whether connections are served with TLS, never over --socket
+}
+
+/// What the service shares with the audio side, which `run_with` and `serve_loopback` each set
+/// up around their own devices.
+struct AudioSide {
+    counters: Arc<Counters>,
+    playback_ring: Arc<Mutex<PlaybackRing>>,
+    capture_format: Arc<Mutex<AudioFormat>>,
+    playback_format: Arc<Mutex<AudioFormat>>,
+    capture_open: Arc<AtomicBool>,
+    playback_open: Arc<AtomicBool>,
+    commands: mpsc::Sender<AudioCommand>,
+    controller: Arc<dyn DeviceController>,
+    devices: watch::Receiver<Vec<Device>>,
+    volume: Gain,
+    capture_muted: Arc<AtomicBool>,
+    playback_muted: Arc<AtomicBool>,
+}
+
+type FlowStream = ReceiverStream<Result<Flow, Status>>; // frames on their way to a listener
+
+/// A package from the input callback on its way out of the capture ring.
+pub struct Captured {
+    pub samples: Vec<f32>,
+    pub at: Instant, // when its first sample reached the microphone, as far as the device reports
+}
+
+/// Whether a device stream works, shared by its cpal callbacks and `run`: `ok` is cleared by any
+/// stream error and set again by the data callback. Losing the device altogether also tells
+/// `lost`, where `run` rebuilds the stream.
+#[derive(Clone)]
+pub struct StreamHealth {
+    ok: Arc<AtomicBool>,
+    lost: Option<mpsc::UnboundedSender<()>>, // `None` where nothing rebuilds the stream
+}
+
+impl StreamHealth {
+    pub fn new(lost: Option<mpsc::UnboundedSender<()>>) -> Self {
+        StreamHealth { ok: Arc::new(AtomicBool::new(true)), lost }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.ok.load(Ordering::Relaxed)
+    }
+}
+
+/// Rebuilding a stream whose device went away: the attempts that failed so far and when the
+/// next one is due, which backs off from RECOVERY_BACKOFF up to RECOVERY_MAX_BACKOFF.
+struct Recovery {
+    attempts: u32,
+    backoff: Duration,
+    next: tokio::time::Instant,
+}
+
+impl Recovery {
+    fn new() -> Self {
+        Recovery { attempts: 0, backoff: RECOVERY_BACKOFF, next: tokio::time::Instant::now() + RECOVERY_BACKOFF }
+    }
+
+    /// Counts a failed attempt, and returns the recovery still under way, or `None` once
+    /// `max_attempts` have failed.
+    fn failed(mut self, max_attempts: u32) -> Option<Self> {
+        self.attempts += 1;
+        if self.attempts >= max_attempts {
+            return None;
+        }
+        self.backoff = (self.backoff * 2).min(RECOVERY_MAX_BACKOFF);
+        self.next = tokio::time::Instant::now() + self.backoff;
+        Some(self)
+    }
+}
+
+/// Requests for the task in `main` that owns the cpal streams.
+enum AudioCommand {
+    /// Rebuild the input stream on the current default input device.
+    ReopenInput(oneshot::Sender<anyhow::Result<()>>),
+    /// Play on these output devices too, in place of those played on so far.
+    SetOutputs(Vec<OutputDevice>, oneshot::Sender<anyhow::Result<()>>),
+}
+const PROTOCOL: u32 = 1; // ServerInfo.protocol, bump on changes that break older clients
+const SAMPLE_RATE_HEADER: &str = "sf-sample-rate"; // response metadata of GetFlow and Duplex, the rate of the frames
+const CHANNELS_HEADER: &str = "sf-channels"; // response metadata of GetFlow and Duplex, how many channels the frames interleave
+const RETRY_INTERVAL: Duration = Duration::from_secs(1); // how long to wait before retrying a failed audio device setup
+const RECOVERY_BACKOFF: Duration = Duration::from_millis(250); // wait before rebuilding a stream that lost its device, doubled after each failure
+const RECOVERY_MAX_BACKOFF: Duration = Duration::from_secs(8);
+// Stands in for a device's format until one opens. Never sent to clients, the calls that would
+// need it fail with UNAVAILABLE instead; rooms carry it while there is no input device.
+const PLACEHOLDER_FORMAT: AudioFormat = AudioFormat { sample_rate: 48000, channels: 2, sample_format: SampleFormat::F32 as i32, codec: Codec::Raw as i32 };
+const MAX_DEVICE_LEVEL: f32 = 1.5; // most SetDeviceVolume allows, PulseAudio's own limit for sliders is about 1.53
+const ROOM_SWEEP_INTERVAL: Duration = Duration::from_secs(5); // how often empty rooms are looked for
+const LOOPBACK_REPORT_INTERVAL: Duration = Duration::from_secs(1); // how often --loopback logs the latency
+const OVERRUN_LOG_INTERVAL: Duration = Duration::from_secs(5); // most often dropped capture is logged
+const PULSE_DEVICE: &str = "pulse"; // the ALSA host's device that plays through PulseAudio
+const PULSE_SINK_VAR: &str = "PULSE_SINK"; // the sink PulseAudio plays a new stream on
+const WATCH_INTERVAL_MS: u32 = 100; // between WatchBuffers levels when the request sets none
+const WATCH_INTERVALS_MS: std::ops::RangeInclusive<u32> = 10..=10000; // what WatchBuffers accepts
+
+impl SoundFlowService {
+    /// The service on `audio`, which reports serving with `tls` or without. Sets up the rest the
+    /// same for every device: the capture broadcast, as big as the capture format needs, the
+    /// meter reading it and the rooms, both running as `tasks`, and no recording yet.
+    fn new(config: Arc<Config>, audio: AudioSide, tasks: &Tasks, tls: bool) -> Self {
+        let (consumer, _) = channel(config.broadcast_capacity(&audio.capture_format.lock().unwrap()));
+        let (measured, levels) = watch::channel(Levels::default());
+        tasks.spawn("meter", meter::run(consumer.subscribe(), audio.capture_format.clone(), config.meter_hz, measured));
+        let rooms = Arc::new(Rooms::new(&config, tasks.clone()));
+        SoundFlowService {
+            config,
+            consumer,
+            playback_ring: audio.playback_ring,
+            flows: AtomicU64::new(0),
+            counters: audio.counters,
+            capture_format: audio.capture_format,
+            playback_format: audio.playback_format,
+            capture_open: audio.capture_open,
+            playback_open: audio.playback_open,
+            negotiated_format: Arc::new(Mutex::new(None)),
+            audio: audio.commands,
+            controller: audio.controller,
+            devices: audio.devices,
+            levels,
+            recording: Arc::new(Mutex::new(None)),
+            rooms,
+            volume: audio.volume,
+            capture_muted: audio.capture_muted,
+            playback_muted: audio.playback_muted,
+            switchboard: Arc::default(),
+            tls,
+        }
+    }
+
+    /// Starts playing `stream` on the speaker, or mixing it into what `room` broadcasts,
+    /// through the decoder, channel remapping and resampler its negotiated format needs, and
+    /// regrouped into packages of the device's size if the sender frames differently. Underruns
+    /// a Duplex peer reports are stored in `peer`. Frames without samples are skipped, and one
+    /// past --max-frame-samples is refused: the task stops reading and ends with
+    /// INVALID_ARGUMENT. Otherwise the task ends with the stream.
+    fn play(&self, mut stream: Streaming<Flow>, room: Option<Arc<Room>>, peer: Option<Arc<AtomicU64>>) -> Result<JoinHandle<Result<(), Status>>, Status> {
+        if room.is_none() {
+            self.require_playback()?;
+        }
+        let mut playback = PlaybackSender::new(self.playback_ring.clone());
+        let overflow = self.config.overflow;
+        let counters = self.counters.clone();
+        // A room's listeners expect the same format as the device's, so that is what rooms carry.
+        let target = match room {
+            None => self.playback_format.lock().unwrap().clone(),
+            Some(_) => self.capture_format.lock().unwrap().clone(),
+        };
+        let format = self.negotiated_format.lock().unwrap().clone().unwrap_or_else(|| target.clone());
+        let stream_id = self.flows.fetch_add(1, Ordering::Relaxed) + 1;
+        let target_rate = target.sample_rate;
+        let (channels, target_channels) = (format.channels as usize, target.channels as usize);
+        let package_size = self.config.package_samples(target_channels);
+        let package_ns = (package_size / target_channels.max(1)) as u64 * 1_000_000_000 / target_rate.max(1) as u64;
+        let mut resampler = if format.sample_rate == target_rate {
+            None
+        } else {
+            Some(Resampler::new(format.sample_rate, target_rate, target_channels, self.config.package_size)
+                .map_err(|e| Status::invalid_argument(format!("can't resample {} Hz to {} Hz: {}", format.sample_rate, target_rate, e)))?)
+        };
+        let max_senders = self.config.max_senders;
+        if counters.senders.fetch_add(1, Ordering::Relaxed) >= max_senders {
+            counters.senders.fetch_sub(1, Ordering::Relaxed);
+            warn!(max_senders, "refused a sender, --max-senders reached");
+            return Err(Status::resource_exhausted(format!("already playing {} senders", max_senders)));
+        }
+        let sending = Sending { counters: counters.clone() };
+        let mut playout = self.config.playout();
+        let mut limit = RateLimit::new(self.config.max_ingest_speed * format.sample_rate as f64 * target_channels as f64);
+        let max_frame_samples = self.config.max_frame_samples();
+        // --channel-map routes what the speaker plays, a room mixes like its listeners hear.
+        let channel_map = match (&self.config.channel_map, &room) {
+            (Some(map), None) => Some(checked_map(map, channels, target_channels)?),
+            _ => None,
+        };
+        info!(
+            room = room.as_ref().map(|room| room.id.as_str()), sample_rate = format.sample_rate, channels, resampled = resampler.is_some(), remapped = channels != target_channels,
+            "receiving flow",
+        );
+        let mut received = self.config.throughput("received");
+        Ok(tokio::spawn(async move {
+            let _sending = sending;
+            let mut decoder = None;
+            let mut rate_limited = 0u64;
+            let mut sequence = SequenceTracker::default();
+            let mut resampled = 0; // seq of the last package out of the resampler or regrouper
+            let mut resampled_ns = None; // captured_ns of the next package out of the resampler
+            let mut due = move |captured_ns| playout.as_mut().and_then(|playout| playout.due(captured_ns));
+            let mut regrouper: Option<Framer> = None; // once the sender's frames don't fit the device's
+            let mut last = 0; // highest seq handed on to be played
+            let mut disconnected = None; // why the stream broke off, if the sender didn't end it
+            let mut refused = None; // why the server stopped reading, sent back to the sender
+            while let Some(flow) = stream.next().await {
+                let flow = match flow {
+                    Ok(flow) => flow,
+                    Err(status) => {
+                        disconnected = Some(status);
+                        break;
+                    }
+                };
+                let arrival = sequence.track(flow.seq);
+                Counters::add(&counters.frames_received, 1);
+                Counters::add(&counters.bytes_received, flow.encoded_len() as u64);
+                received.add(flow.encoded_len());
+                received.tick();
+                match arrival {
+                    Arrival::InOrder => {}
+                    Arrival::Gap(missing) => {
+                        Counters::add(&counters.frames_lost, missing);
+                        warn!(seq = flow.seq, missing, lost = sequence.lost, "frames lost");
+                    }
+                    Arrival::Late => debug!(seq = flow.seq, reordered = sequence.reordered, "frame arrived out of order"),
+                }
+                // Unnumbered senders are played in arrival order.
+                let seq = if flow.seq == 0 { sequence.received } else { flow.seq };
+                if let (Some(peer), Some(health)) = (&peer, &flow.health) {
+                    peer.store(health.underruns, Ordering::Relaxed);
+                }
+                if flow.flow.is_empty() && flow.payload.is_empty() {
+                    Counters::add(&counters.frames_empty, 1);
+                    continue;
+                }
+                let captured_ns = flow.captured_ns;
+                let captured = latency::from_ns(captured_ns);
+                let samples = match decode_flow(flow, &mut decoder, &format) {
+                    Ok(samples) => samples,
+                    Err(e) => {
+                        warn!("failed to decode flow: {}", e);
+                        continue;
+                    }
+                };
+                if samples.len() > max_frame_samples {
+                    Counters::add(&counters.frames_oversized, 1);
+                    warn!(seq, samples = samples.len(), max_frame_samples, "refused an oversized frame, ending the flow");
+                    refused = Some(Status::invalid_argument(format!("a frame of {} samples, more than --max-frame-samples {}", samples.len(), max_frame_samples)));
+                    break;
+                }
+                let samples = match &channel_map {
+                    Some(map) => map.apply(&samples),
+                    None if channels != target_channels => remap(&samples, channels, target_channels),
+                    None => samples,
+                };
+                if !limit.allow(samples.len()) {
+                    rate_limited += 1;
+                    Counters::add(&counters.rate_limited, 1);
+                    if rate_limited.is_power_of_two() {
+                        warn!(rate_limited, "sender is faster than --max-ingest-speed, dropping frames");
+                    }
+                    continue;
+                }
+                let packets = match resampler.as_mut() {
+                    None if regrouper.is_none() && samples.len() == package_size => vec![Packet { stream: stream_id, seq, samples, captured, due: due(captured_ns), end: false }],
+                    // The resampler and regrouper carry state from one chunk to the next, so they can't take late frames.
+                    _ if arrival == Arrival::Late => continue,
+                    None => {
+                        let regrouper = regrouper.get_or_insert_with(|| {
+                            debug!(received = samples.len(), package_size, "regrouping frames into the device's packages");
+                            resampled = last;
+                            Framer::fixed(package_size).timed(target_rate, target_channels)
+                        });
+                        regrouper.push(&samples, captured_ns).into_iter().map(|frame| {
+                            resampled += 1;
+                            Packet { stream: stream_id, seq: resampled, samples: frame.flow, captured: latency::from_ns(frame.captured_ns), due: due(frame.captured_ns), end: false }
+                        }).collect()
+                    }
+                    Some(resampler) => match resampler.process(&samples) {
+                        Ok(packages) => packages.into_iter().map(|samples| {
+                            resampled += 1;
+                            // The resampler holds samples over from one frame to the next, so
+                            // its packages are timed by how many came out, not by their frame.
+                            let captured_ns = resampled_ns.take().unwrap_or(captured_ns);
+                            if captured_ns != 0 {
+                                resampled_ns = Some(captured_ns + package_ns);
+                            }
+                            Packet { stream: stream_id, seq: resampled, samples, captured: latency::from_ns(captured_ns), due: due(captured_ns), end: false }
+                        }).collect(),
+                        Err(e) => {
+                            warn!("failed to resample flow: {}", e);
+                            continue;
+                        }
+                    },
+                };
+                last = packets.iter().map(|packet| packet.seq).fold(last, u64::max);
+                match &room {
+                    None => {
+                        for packet in packets {
+                            if playback.push(packet, overflow).await {
+                                Counters::add(&counters.output_overruns, 1);
+                                warn!(policy = ?overflow, "output stream fell behind: try increasing latency");
+                            }
+                        }
+                    }
+                    Some(room) => packets.into_iter().for_each(|packet| room.push(packet)),
+                }
+            }
+            drop(stream); // a refused sender's stream isn't read on
+            // Play what the regrouper still holds, then mark the end, so what is still buffered
+            // plays out and fades instead of running dry.
+            let mut closing = Vec::new();
+            if let Some(rest) = regrouper.as_mut().and_then(Framer::flush) {
+                last = last.max(resampled + 1);
+                closing.push(Packet { stream: stream_id, seq: resampled + 1, samples: rest.flow, captured: latency::from_ns(rest.captured_ns), due: due(rest.captured_ns), end: false });
+            }
+            let end = Packet::end(stream_id, last + 1);
+            match &room {
+                None => {
+                    for packet in closing {
+                        if playback.push(packet, overflow).await {
+                            Counters::add(&counters.output_overruns, 1);
+                        }
+                    }
+                    if playback.finish(end).await {
+                        Counters::add(&counters.output_overruns, 1);
+                        warn!("dropped the end of the flow, it leaves the mix once it goes idle");
+                    }
+                }
+                Some(room) => closing.into_iter().chain([end]).for_each(|packet| room.push(packet)),
+            }
+            match (&disconnected, &refused) {
+                (Some(status), _) => info!(received = sequence.received, lost = sequence.lost, reordered = sequence.reordered, rate_limited, reason = status.message(), "sender disconnected"),
+                (None, Some(status)) => info!(received = sequence.received, lost = sequence.lost, reordered = sequence.reordered, rate_limited, reason = status.message(), "sender refused"),
+                (None, None) => info!(received = sequence.received, lost = sequence.lost, reordered = sequence.reordered, rate_limited, "flow ended"),
+            }
+            refused.map_or(Ok(()), Err)
+        }.in_current_span()))
+    }
+
+    /// Starts streaming the capture broadcast, or what is sent to `room`, encoded with `codec` (or
+    /// packed in `sample_format` for RAW) and without silence unless --no-vad is set, to a new
+    /// listener, in a response whose metadata gives the frames' sample rate and channels. RAW
+    /// frames adapt their size with --adaptive-package-size, taking the underruns of a Duplex
+    /// `peer` into account, and Duplex frames report the server's own buffer back. The task ends
+    /// when capture does or the listener goes away.
+    ///
+    /// The task never waits for the listener, so capture and the other listeners never wait on a
+    /// slow one. Frames it can't keep up with are dropped instead, at either of two points: the
+    /// broadcast overwrites frames the task hasn't read yet (it fell behind the capture), or the
+    /// listener's queue is full (the client or its link fell behind the task). Either way the
+    /// listener's `seq` skips ahead by the frames dropped, so the client sees the gap, and they
+    /// count towards `listener_drops`.
+    fn listen(&self, codec: Codec, sample_format: SampleFormat, room: Option<Arc<Room>>, peer: Option<Arc<AtomicU64>>) -> Result<(Response<FlowStream>, JoinHandle<()>), Status> {
+        if room.is_none() {
+            self.require_capture()?;
+        }
+        let capture_format = self.capture_format.lock().unwrap().clone();
+        let mut processing = Chain::default();
+        if let Some(vad) = self.config.vad(&capture_format) {
+            processing.push(vad);
+        }
+        let mut framer = if codec == Codec::Raw { self.config.framer(&capture_format) } else { None };
+        let mut encoder = listener_encoder(codec, &capture_format)?;
+        let mut sample_format = sample_format;
+        let config = self.config.clone();
+        let counters = self.counters.clone();
+        let max_listeners = self.config.max_listeners;
+        if counters.listeners.fetch_add(1, Ordering::Relaxed) >= max_listeners {
+            counters.listeners.fetch_sub(1, Ordering::Relaxed);
+            warn!(max_listeners, "refused a listener, --max-listeners reached");
+            return Err(Status::resource_exhausted(format!("already serving {} listeners", max_listeners)));
+        }
+        if room.is_none() {
+            counters.capture_listeners.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut consumer = room.as_ref().map_or_else(|| self.consumer.subscribe(), |room| room.flows.subscribe());
+        let (id, mut switches) = self.switchboard.open();
+        let mut listener = Listener { counters: counters.clone(), seq: 0, dropped: 0, room, id, switchboard: self.switchboard.clone() };
+        let (tx, rx) = tokio::sync::mpsc::channel(self.config.listener_queue(&capture_format));
+        let format = capture_format.clone();
+        let task = tokio::spawn(async move {
+            let Listener { seq, dropped, .. } = &mut listener;
+            let mut last_ns = 0; // captured_ns of the frame before, which a flushed opus packet ends with
+            let mut sent = config.throughput("sent");
+            'listening: loop {
+                let mut v = match consumer.recv().await {
+                    Ok(Ok(v)) => v,
+                    Ok(Err(())) | Err(RecvError::Closed) => break, // capture ended, finish the stream cleanly
+                    Err(RecvError::Lagged(missed)) => {
+                        // Skip seq past the missed frames so the listener sees the gap.
+                        *seq += missed;
+                        if let Some(framer) = framer.as_mut() {
+                            framer.discard();
+                        }
+                        *dropped += missed;
+                        Counters::add(&counters.listener_drops, missed);
+                        warn!(missed, dropped = *dropped, "listener fell behind the capture broadcast");
+                        continue;
+                    }
+                };
+                // SetCodec lands between two frames: what the old codec still holds goes out in
+                // it, and everything from this frame on in the new one.
+                let mut frames = Vec::new();
+                if let Ok(switch) = switches.try_recv() {
+                    frames = flush_listener(encoder.as_mut(), framer.as_mut(), sample_format, last_ns);
+                    match listener_encoder(switch.codec, &format) {
+                        Ok(switched) => {
+                            encoder = switched;
+                            framer = if switch.codec == Codec::Raw { config.framer(&format) } else { None };
+                            sample_format = switch.sample_format;
+                            info!(codec = ?switch.codec, sample_format = ?switch.sample_format, "listener switched codec");
+                            let _ = switch.reply.send(Ok(*seq + frames.len() as u64 + 1));
+                        }
+                        Err(status) => {
+                            let _ = switch.reply.send(Err(status));
+                        }
+                    }
+                }
+                processing.process(&mut v.flow, &format);
+                let captured_ns = v.captured_ns; // for opus, of the frame that completed the packet
+                // Silence isn't worth the bandwidth, what a switch flushed still goes out.
+                if !v.flow.is_empty() {
+                    last_ns = captured_ns;
+                    if let (Some(framer), Some(peer)) = (framer.as_mut(), &peer) {
+                        framer.peer_underruns(peer.load(Ordering::Relaxed));
+                    }
+                    match (encoder.as_mut(), framer.as_mut()) {
+                        (None, None) if sample_format == SampleFormat::F32 => frames.push(v),
+                        (None, None) => frames.push(Flow { payload: to_payload(&v.flow, sample_format), encoding: encoding(sample_format).into(), captured_ns, ..Default::default() }),
+                        (None, Some(framer)) => frames.extend(framer.push(&v.flow, captured_ns).into_iter().map(|frame| packed(frame, sample_format))),
+                        (Some(encoder), _) => match encoder.encode(&v.flow) {
+                            Ok(packets) => frames.extend(packets.into_iter().map(|payload| Flow { payload, encoding: Encoding::Opus.into(), captured_ns, ..Default::default() })),
+                            Err(e) => warn!("failed to encode flow: {}", e),
+                        },
+                    }
+                }
+                for frame in frames {
+                    *seq += 1;
+                    // Never wait on a slow listener, that would only make it lag the broadcast.
+                    let health = peer.is_some().then(|| BufferHealth {
+                        depth: counters.jitter_depth.load(Ordering::Relaxed) as u32,
+                        underruns: counters.output_underruns.load(Ordering::Relaxed),
+                    });
+                    let frame = Flow { seq: *seq, health, ..frame };
+                    let bytes = frame.encoded_len() as u64;
+                    match tx.try_send(Ok(frame)) {
+                        Ok(()) => {
+                            Counters::add(&counters.frames_sent, 1);
+                            Counters::add(&counters.bytes_sent, bytes);
+                            sent.add(bytes as usize);
+                            sent.tick();
+                        }
+                        Err(TrySendError::Full(_)) => {
+                            if let Some(framer) = framer.as_mut() {
+                                framer.congested();
+                            }
+                            *dropped += 1;
+                            Counters::add(&counters.listener_drops, 1);
+                            if dropped.is_power_of_two() {
+                                warn!(dropped = *dropped, "listener isn't keeping up, dropping frames");
+                            }
+                        }
+                        Err(TrySendError::Closed(_)) => break 'listening, // the listener went away
+                    }
+                }
+            }
+        }.in_current_span());
+        let mut response = Response::new(ReceiverStream::new(rx));
+        response.metadata_mut().insert(SAMPLE_RATE_HEADER, capture_format.sample_rate.into());
+        response.metadata_mut().insert(CHANNELS_HEADER, capture_format.channels.into());
+        response.metadata_mut().insert(LISTENER_HEADER, id.into());
+        Ok((response, task))
+    }
+
+    /// When `request` has to be answered by, see `call_deadline`.
+    fn deadline<T>(&self, request: &Request<T>) -> tokio::time::Instant {
+        tokio::time::Instant::now() + call_deadline(request.metadata(), self.config.rpc_timeout())
+    }
+
+    /// The room `request` asks to join in its metadata, `None` for the device's own.
+    fn room<T>(&self, request: &Request<T>) -> Result<Option<Arc<Room>>, Status> {
+        let capture_format = self.capture_format.lock().unwrap().clone();
+        Ok(requested_room(request.metadata())?.map(|id| self.rooms.join(&id, &capture_format)))
+    }
+
+    /// Fails while there is no input device to capture from.
+    fn require_capture(&self) -> Result<(), Status> {
+        match self.capture_open.load(Ordering::Relaxed) {
+            true => Ok(()),
+            false => Err(Status::unavailable("no input device, waiting for one to appear")),
+        }
+    }
+
+    /// Fails while there is no output device to play on.
+    fn require_playback(&self) -> Result<(), Status> {
+        match self.playback_open.load(Ordering::Relaxed) {
+            true => Ok(()),
+            false => Err(Status::unavailable("no output device, waiting for one to appear")),
+        }
+    }
+
+    fn mute_state(&self) -> MuteState {
+        MuteState {
+            capture: self.capture_muted.load(Ordering::Relaxed),
+            playback: self.playback_muted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A send_flow sender, counted as open until dropped.
+struct Sending {
+    counters: Arc<Counters>,
+}
+
+impl Drop for Sending {
+    fn drop(&mut self) {
+        self.counters.senders.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A monitor, counted as open until dropped.
+struct Monitoring {
+    counters: Arc<Counters>,
+}
+
+impl Drop for Monitoring {
+    fn drop(&mut self) {
+        self.counters.monitors.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A meter, counted as open until dropped.
+struct Metering {
+    counters: Arc<Counters>,
+}
+
+impl Drop for Metering {
+    fn drop(&mut self) {
+        self.counters.meters.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A get_flow listener, counted as open until dropped, which happens even if its task is aborted.
+struct Listener {
+    counters: Arc<Counters>,
+    seq: u64, // of the last frame sent or dropped
+    dropped: u64,
+    room: Option<Arc<Room>>, // keeps the room open while listening
+    id: u64, // what SetCodec knows it by
+    switchboard: Arc<Switchboard>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.counters.listeners.fetch_sub(1, Ordering::Relaxed);
+        if self.room.is_none() {
+            self.counters.capture_listeners.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.switchboard.close(self.id);
+        info!(sent = self.seq - self.dropped, dropped = self.dropped, "listener left");
+    }
+}
+
+#[tonic::async_trait]
+impl SoundFlow for SoundFlowService {
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn get_devices(&self, request: Request<Direction>) -> Result<Response<Devices>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let direction = request.into_inner().direction();
+        let devices = run_blocking(deadline, "listing the devices", move || controller.list(direction)).await?;
+        Ok(Response::new(Devices { devices }))
+    }
+
+    type WatchDevicesStream = ReceiverStream<Result<Devices, Status>>;
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn watch_devices(&self, request: Request<Direction>) -> Result<Response<Self::WatchDevicesStream>, Status> {
+        let direction = request.into_inner().direction();
+        let mut changes = self.devices.clone();
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            loop {
+                let devices = changes.borrow_and_update().iter()
+                    .filter(|device| direction == DeviceDirection::All || device.direction() == direction)
+                    .cloned()
+                    .collect();
+                if tx.send(Ok(Devices { devices })).await.is_err() || changes.changed().await.is_err() {
+                    break; // the watcher went away or the server is shutting down
+                }
+            }
+        }.in_current_span());
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn send_flow(&self, request: Request<Streaming<Flow>>) -> Result<Response<()>, Status> {
+        let room = self.room(&request)?;
+        let playing = self.play(request.into_inner(), room, None)?;
+        // Answer once the stream is played, so a refused sender gets the status why.
+        playing.await.unwrap_or_else(|e| Err(Status::internal(format!("playing the flow failed: {}", e))))?;
+        Ok(Response::new(()))
+    }
+
+    type GetFlowStream = FlowStream;
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn get_flow(&self, request: Request<FlowRequest>) -> Result<Response<Self::GetFlowStream>, Status> {
+        let room = self.room(&request)?;
+        let request = request.into_inner();
+        let (flow, _) = self.listen(request.codec(), request.sample_format(), room, None)?;
+        Ok(flow)
+    }
+
+    type DuplexStream = FlowStream;
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn duplex(&self, request: Request<Streaming<Flow>>) -> Result<Response<Self::DuplexStream>, Status> {
+        // Both directions use the codec and sample format the peer negotiated for sending.
+        let (codec, sample_format) = self.negotiated_format.lock().unwrap().as_ref()
+            .map_or((Codec::Raw, SampleFormat::F32), |format| (format.codec(), format.sample_format()));
+        let room = self.room(&request)?;
+        let peer = Arc::new(AtomicU64::new(0)); // the underruns the peer reports, for adapting what it is sent
+        let (flow, listening) = self.listen(codec, sample_format, room.clone(), Some(peer.clone()))?;
+        let playing = match self.play(request.into_inner(), room, Some(peer)) {
+            Ok(playing) => playing,
+            Err(e) => {
+                listening.abort();
+                return Err(e);
+            }
+        };
+        tokio::spawn(async move {
+            let _ = playing.await;
+            listening.abort(); // the peer stopped sending, so end what it receives as well
+        }.in_current_span());
+        Ok(flow)
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_codec(&self, request: Request<CodecSwitch>) -> Result<Response<CodecSwitched>, Status> {
+        let request = request.into_inner();
+        let seq = self.switchboard.switch(request.listener, request.codec(), request.sample_format()).await?;
+        Ok(Response::new(CodecSwitched { seq }))
+    }
+
+    type MonitorStream = FlowStream;
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn monitor(&self, _request: Request<()>) -> Result<Response<Self::MonitorStream>, Status> {
+        self.require_capture()?;
+        let capture_format = self.capture_format.lock().unwrap().clone();
+        // A subscription of its own, outside the listeners: no VAD, codec, room or --max-listeners.
+        let mut consumer = self.consumer.subscribe();
+        let (tx, rx) = mpsc::channel(self.config.listener_queue(&capture_format));
+        let mut monitored = self.config.throughput("monitored");
+        self.counters.monitors.fetch_add(1, Ordering::Relaxed);
+        let monitoring = Monitoring { counters: self.counters.clone() };
+        tokio::spawn(async move {
+            let _monitoring = monitoring;
+            let (mut seq, mut dropped) = (0, 0u64);
+            loop {
+                let v = match consumer.recv().await {
+                    Ok(Ok(v)) => v,
+                    Ok(Err(())) | Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(missed)) => {
+                        seq += missed; // the gap shows like a listener's
+                        dropped += missed;
+                        continue;
+                    }
+                };
+                seq += 1;
+                let frame = Flow { seq, ..v };
+                let bytes = frame.encoded_len();
+                match tx.try_send(Ok(frame)) {
+                    Ok(()) => {
+                        monitored.add(bytes);
+                        monitored.tick();
+                    }
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Closed(_)) => break, // the monitor went away
+                }
+            }
+            info!(sent = seq - dropped, dropped, "monitor left");
+        }.in_current_span());
+        let mut response = Response::new(ReceiverStream::new(rx));
+        response.metadata_mut().insert(SAMPLE_RATE_HEADER, capture_format.sample_rate.into());
+        response.metadata_mut().insert(CHANNELS_HEADER, capture_format.channels.into());
+        Ok(response)
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_device(&self, request: Request<DeviceId>) -> Result<Response<()>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let request = request.into_inner();
+        let device = request.clone();
+        run_blocking(deadline, "setting the default device", move || controller.set_default(&device)).await?;
+        if request.direction() != DeviceDirection::Capture {
+            return Ok(Response::new(()));
+        }
+        // The default input device follows the system's default capture device, so a new stream captures from it.
+        within(deadline, "reopening the input device", async {
+            let (reply, reopened) = oneshot::channel();
+            self.audio.send(AudioCommand::ReopenInput(reply)).await
+                .map_err(|_| Status::unavailable("audio streams are shutting down"))?;
+            reopened.await
+                .map_err(|_| Status::unavailable("audio streams are shutting down"))?
+                .map_err(|e| Status::unavailable(format!("failed to reopen input device: {:#}", e)))
+        }).await?;
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_output_devices(&self, request: Request<OutputDevices>) -> Result<Response<()>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let ids = request.into_inner().ids;
+        let outputs = run_blocking(deadline, "finding the playback devices", move || {
+            let default = controller.current(DeviceDirection::Playback).ok().map(|device| device.id);
+            let mut outputs = Vec::new();
+            for id in ids.into_iter().filter(|&id| Some(id) != default) {
+                let output = controller.output(id)?;
+                if !outputs.contains(&output) {
+                    outputs.push(output);
+                }
+            }
+            Ok(outputs)
+        }).await?;
+        let (reply, opened) = oneshot::channel();
+        within(deadline, "opening the output devices", async {
+            self.audio.send(AudioCommand::SetOutputs(outputs, reply)).await
+                .map_err(|_| Status::unavailable("audio streams are shutting down"))?;
+            opened.await
+                .map_err(|_| Status::unavailable("audio streams are shutting down"))?
+                .map_err(|e| Status::unavailable(format!("failed to open output devices: {:#}", e)))
+        }).await?;
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn get_current_device(&self, request: Request<Direction>) -> Result<Response<Device>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let direction = request.into_inner().direction();
+        Ok(Response::new(run_blocking(deadline, "finding the current device", move || controller.current(direction)).await?))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn negotiate_format(&self, request: Request<AudioFormat>) -> Result<Response<AudioFormat>, Status> {
+        self.require_playback()?;
+        let format = request.into_inner();
+        let playback = self.playback_format.lock().unwrap().clone();
+        if format.channels == 0 || format.sample_rate == 0 {
+            return Err(Status::invalid_argument("sample rate and channels must be greater than 0"));
+        }
+        if format.codec() == Codec::Opus && !opus_supports(&format) {
+            return Err(Status::invalid_argument("opus needs 8, 12, 16, 24 or 48 kHz with 1 or 2 channels"));
+        }
+        if let Some(map) = &self.config.channel_map {
+            checked_map(map, format.channels as usize, playback.channels as usize)?;
+        }
+        let accepted = AudioFormat { codec: format.codec, sample_format: format.sample_format, ..playback };
+        *self.negotiated_format.lock().unwrap() = Some(format);
+        Ok(Response::new(accepted))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn get_stream_config(&self, _request: Request<()>) -> Result<Response<StreamConfig>, Status> {
+        Ok(Response::new(StreamConfig {
+            capture: self.require_capture().ok().map(|()| self.capture_format.lock().unwrap().clone()),
+            playback: self.require_playback().ok().map(|()| self.playback_format.lock().unwrap().clone()),
+            negotiated: self.negotiated_format.lock().unwrap().clone(),
+        }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn start_recording(&self, request: Request<RecordingRequest>) -> Result<Response<()>, Status> {
+        let dir = self.config.recordings_dir.as_ref()
+            .ok_or_else(|| Status::failed_precondition("recording is disabled, start the server with --recordings-dir"))?;
+        self.require_capture()?;
+        let path = recording_path(dir, &request.into_inner().path)
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        let mut recording = self.recording.lock().unwrap();
+        if let Some(current) = recording.as_ref() {
+            return Err(Status::failed_precondition(format!("already recording to {}", current.path.display())));
+        }
+        let format = self.capture_format.lock().unwrap().clone();
+        let started = Recording::start(path.clone(), &format, self.consumer.subscribe())
+            .map_err(|e| Status::invalid_argument(format!("failed to create {}: {}", path.display(), e)))?;
+        info!(path = %path.display(), sample_rate = format.sample_rate, channels = format.channels, "recording started");
+        *recording = Some(started);
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn stop_recording(&self, _request: Request<()>) -> Result<Response<RecordingSummary>, Status> {
+        let recording = self.recording.lock().unwrap().take()
+            .ok_or_else(|| Status::failed_precondition("not recording"))?;
+        let path = recording.path.display().to_string();
+        let samples = recording.stop().await
+            .map_err(|e| Status::internal(format!("{:#}", e)))?;
+        info!(%path, samples, "recording saved");
+        Ok(Response::new(RecordingSummary { path, samples }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_volume(&self, request: Request<Volume>) -> Result<Response<Volume>, Status> {
+        let gain = request.into_inner().gain;
+        if gain.is_nan() || gain < 0.0 {
+            return Err(Status::invalid_argument("gain must be between 0.0 and 1.0"));
+        }
+        let gain = gain.min(1.0);
+        self.volume.set(gain);
+        info!(gain, "volume changed");
+        Ok(Response::new(Volume { gain }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn get_volume(&self, _request: Request<()>) -> Result<Response<Volume>, Status> {
+        Ok(Response::new(Volume { gain: self.volume.get() }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_device_volume(&self, request: Request<DeviceVolume>) -> Result<Response<DeviceVolume>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let request = request.into_inner();
+        let device = request.device.ok_or_else(|| Status::invalid_argument("device is required"))?;
+        if request.level.is_nan() || request.level < 0.0 {
+            return Err(Status::invalid_argument(format!("level must be between 0.0 and {}", MAX_DEVICE_LEVEL)));
+        }
+        let level = request.level.min(MAX_DEVICE_LEVEL);
+        let set = device.clone();
+        let level = run_blocking(deadline, "setting the device volume", move || controller.set_volume(&set, level)).await?;
+        info!(id = device.id, direction = ?device.direction(), level, "device volume changed");
+        Ok(Response::new(DeviceVolume { device: Some(device), level }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn get_device_volume(&self, request: Request<DeviceId>) -> Result<Response<DeviceVolume>, Status> {
+        let (deadline, controller) = (self.deadline(&request), self.controller.clone());
+        let device = request.into_inner();
+        let read = device.clone();
+        let level = run_blocking(deadline, "reading the device volume", move || controller.volume(&read)).await?;
+        Ok(Response::new(DeviceVolume { device: Some(device), level }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn set_mute(&self, request: Request<Mute>) -> Result<Response<MuteState>, Status> {
+        let request = request.into_inner();
+        let direction = request.direction();
+        if direction != DeviceDirection::Playback {
+            self.capture_muted.store(request.muted, Ordering::Relaxed);
+        }
+        if direction != DeviceDirection::Capture {
+            self.playback_muted.store(request.muted, Ordering::Relaxed);
+        }
+        info!(muted = request.muted, ?direction, "mute changed");
+        Ok(Response::new(self.mute_state()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn get_mute(&self, _request: Request<()>) -> Result<Response<MuteState>, Status> {
+        Ok(Response::new(self.mute_state()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn get_stats(&self, _request: Request<()>) -> Result<Response<Stats>, Status> {
+        Ok(Response::new(self.counters.snapshot()))
+    }
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn get_server_info(&self, _request: Request<()>) -> Result<Response<ServerInfo>, Status> {
+        Ok(Response::new(ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            advertised_address: self.config.advertised_address(),
+            codecs: vec![Codec::Raw.into(), Codec::Opus.into()],
+            sample_formats: vec![SampleFormat::F32.into(), SampleFormat::I16.into(), SampleFormat::U16.into()],
+            accept_compression: self.config.accept_compression.iter().filter_map(|compression| compression.name()).map(String::from).collect(),
+            protocol: PROTOCOL,
+            max_listeners: self.config.max_listeners as u32,
+            auth_required: self.config.auth_token.is_some(),
+            tls: self.tls,
+        }))
+    }
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn play_test_tone(&self, request: Request<TestTone>) -> Result<Response<()>, Status> {
+        self.require_playback()?;
+        let tone = request.into_inner();
+        let format = self.playback_format.lock().unwrap().clone();
+        let amplitude = tone::validate(&tone, &format).map_err(Status::invalid_argument)?;
+        let samples = tone::sine(&tone, amplitude, &format);
+        let channels = (format.channels as usize).max(1);
+        let package_size = self.config.package_samples(channels);
+        let period = Duration::from_secs_f64((package_size / channels) as f64 / format.sample_rate as f64);
+        let stream = self.flows.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut playback = PlaybackSender::new(self.playback_ring.clone());
+        let (overflow, counters) = (self.config.overflow, self.counters.clone());
+        info!(frequency_hz = tone.frequency_hz, amplitude, duration_ms = tone.duration_ms, "playing test tone");
+        // Played from its own task, so a caller that gives up doesn't cut it off before its end marker.
+        let playing = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            let mut seq = 0;
+            for package in samples.chunks(package_size) {
+                ticks.tick().await;
+                seq += 1;
+                let mut samples = package.to_vec();
+                samples.resize(package_size, 0.0);
+                if playback.push(Packet { stream, seq, samples, captured: None, due: None, end: false }, overflow).await {
+                    Counters::add(&counters.output_overruns, 1);
+                }
+            }
+            if playback.finish(Packet::end(stream, seq + 1)).await {
+                Counters::add(&counters.output_overruns, 1);
+                warn!("dropped the end of the test tone, it leaves the mix once it goes idle");
+            }
+        }.in_current_span());
+        playing.await.map_err(|e| Status::internal(format!("test tone failed: {}", e)))?;
+        Ok(Response::new(()))
+    }
+
+    type MeterStream = ReceiverStream<Result<Levels, Status>>;
+
+    #[instrument(skip_all, fields(peer = ?_request.remote_addr()))]
+    async fn meter(&self, _request: Request<()>) -> Result<Response<Self::MeterStream>, Status> {
+        let mut levels = self.levels.clone();
+        levels.borrow_and_update(); // only send levels measured from now on
+        let (tx, rx) = mpsc::channel(8);
+        self.counters.meters.fetch_add(1, Ordering::Relaxed);
+        let metering = Metering { counters: self.counters.clone() };
+        tokio::spawn(async move {
+            let _metering = metering;
+            loop {
+                // Levels stop changing while the microphone is paused, the client may leave meanwhile.
+                tokio::select! {
+                    changed = levels.changed() => if changed.is_err() {
+                        break;
+                    },
+                    () = tx.closed() => break,
+                }
+                let measured = levels.borrow_and_update().clone();
+                if tx.send(Ok(measured)).await.is_err() {
+                    break; // the client went away
+                }
+            }
+        }.in_current_span());
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type WatchBuffersStream = ReceiverStream<Result<BufferLevels, Status>>;
+
+    #[instrument(skip_all, fields(peer = ?request.remote_addr()))]
+    async fn watch_buffers(&self, request: Request<BufferWatch>) -> Result<Response<Self::WatchBuffersStream>, Status> {
+        let interval_ms = match request.into_inner().interval_ms {
+            0 => WATCH_INTERVAL_MS,
+            ms if WATCH_INTERVALS_MS.contains(&ms) => ms,
+            ms => return Err(Status::invalid_argument(format!("interval_ms {} is outside {:?}", ms, WATCH_INTERVALS_MS))),
+        };
+        let (counters, capacity) = (self.counters.clone(), self.config.ring_capacity as f32);
+        let percent = move |fill: &AtomicUsize| fill.load(Ordering::Relaxed) as f32 / capacity * 100.0;
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            // Read off the counters the audio callbacks keep anyway, so watching costs them nothing.
+            let mut ticks = tokio::time::interval(Duration::from_millis(interval_ms.into()));
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Skip); // a client that fell behind gets the levels now, not a burst of stale ones
+            loop {
+                ticks.tick().await;
+                let levels = BufferLevels {
+                    capture_fill_percent: percent(&counters.capture_ring_fill),
+                    playback_fill_percent: percent(&counters.playback_lane_fill),
+                };
+                if tx.send(Ok(levels)).await.is_err() {
+                    break; // the client went away
+                }
+            }
+        }.in_current_span());
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+impl From<&cpal::StreamConfig> for AudioFormat {
+    fn from(config: &cpal::StreamConfig) -> Self {
+        AudioFormat {
+            sample_rate: config.sample_rate.0,
+            channels: config.channels.into(),
+            sample_format: SampleFormat::F32.into(),
+            codec: Codec::Raw.into(),
+        }
+    }
+}
+
+/// Runs the server, or --loopback, --diagnose or --probe, with the configuration from the command
+/// line, environment and --config, until shut down.
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load()?;
+    init_logging(&config);
+    let ran = run_with(config).await;
+    telemetry::shutdown();
+    ran
+}
+
+/// Runs the server, or --loopback, --diagnose or --probe, with `config` until shut down, for an
+/// app that embeds it. Logging is left to the app, which `run` sets up from --log-format.
+pub async fn run_with(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Arc::new(config);
+    debug!(config = ?config.redacted(), "effective config");
+    if config.diagnose {
+        diagnose::run(&config).await;
+        return Ok(());
+    }
+    if config.probe {
+        return Ok(diagnose::probe(&config)?);
+    }
+    config.warn_suspicious();
+    // The devices aren't open yet, so this is for the format they usually come up in.
+    info!(preset = ?config.preset, estimated_latency = ?config.estimated_latency(&PLACEHOLDER_FORMAT), "buffering");
+    check_host(&config)?;
+    let tls = if config.loopback { None } else { config.server_tls()? }; // loopback never serves
+    let (mut health, health_service) = tonic_health::server::health_reporter();
+    set_health(&mut health, false).await;
+    let counters = Arc::new(Counters::default());
+    let capture_muted = Arc::new(AtomicBool::new(false));
+    let playback_muted = Arc::new(AtomicBool::new(false));
+    let echo = config.echo_reference();
+    let volume = Gain::new(1.0);
+    // Ctrl-C or SIGTERM cancels every background task, and ends the loop below.
+    let tasks = Tasks::default();
+    let signalled = tasks.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown requested");
+        signalled.cancel();
+    });
+    if config.loopback {
+        let (input_health, output_health) = (StreamHealth::new(None), StreamHealth::new(None));
+        let (mut recorded_consumer, _input_stream, capture_format) = retry("input device", || open_input(&config, &input_health, &capture_muted, &counters, &echo)).await;
+        let mirrors = Mirrors::default();
+        let (output_ring, _output_stream, playback_format) = retry("output device", || open_output(&config, &output_health, &volume, &playback_muted, &counters, &echo, &mirrors)).await;
+        check_formats(&capture_format, &playback_format);
+        return Ok(loopback(&config, &mut recorded_consumer, &output_ring, &capture_format, &playback_format, &counters, &tasks).await?);
+    }
+    // Serve even without devices, the streaming calls wait for them with UNAVAILABLE.
+    let (input_lost, mut input_losses) = mpsc::unbounded_channel();
+    let (output_lost, mut output_losses) = mpsc::unbounded_channel();
+    let mut input = Input {
+        config: config.clone(),
+        health: StreamHealth::new(Some(input_lost)),
+        muted: capture_muted.clone(),
+        counters: counters.clone(),
+        echo: echo.clone(),
+        format: Arc::new(Mutex::new(PLACEHOLDER_FORMAT)),
+        open: Arc::new(AtomicBool::new(false)),
+        stream: None,
+        recovery: None,
+        paused: false,
+    };
+    let mut output = Output {
+        config: config.clone(),
+        health: StreamHealth::new(Some(output_lost)),
+        volume: volume.clone(),
+        muted: playback_muted.clone(),
+        counters: counters.clone(),
+        echo,
+        format: Arc::new(Mutex::new(PLACEHOLDER_FORMAT)),
+        open: Arc::new(AtomicBool::new(false)),
+        ring: Arc::new(Mutex::new(PlaybackRing::new(config.ring_capacity).0)), // drained by no stream, so senders wait for one
+        stream: None,
+        recovery: None,
+        mirrors: Mirrors::default(),
+        mirror_streams: Vec::new(),
+    };
+    if let Err(e) = input.reopen(None) {
+        warn!("no input device: {:#}, serving without capture until one appears", e);
+    }
+    if let Err(e) = output.reopen(input.current()) {
+        warn!("no output device: {:#}, serving without playback until one appears", e);
+    }
+    let (audio, mut commands) = mpsc::channel(8);
+    let (device_changes, mut devices) = watch::channel(Vec::new());
+    let controller = devices::controller(audio_host(&config)?.id());
+    let watched = controller.clone();
+    tasks.spawn("device watcher", devices::watch(watched, device_changes));
+    let listener: Bound = match &config.socket {
+        Some(path) => bind_socket(path, config.socket_mode)?,
+        None => {
+            let addr = config.bound(config.listen)?;
+            tokio::net::TcpListener::from_std(bind_listener(addr)?).with_context(|| format!("failed to listen on {}", addr))?.into()
+        }
+    };
+    let addr = config.serving_on();
+    let side = AudioSide {
+        counters: counters.clone(),
+        playback_ring: output.ring.clone(),
+        capture_format: input.format.clone(),
+        playback_format: output.format.clone(),
+        capture_open: input.open.clone(),
+        playback_open: output.open.clone(),
+        commands: audio,
+        controller,
+        devices: devices.clone(),
+        volume: volume.clone(),
+        capture_muted: capture_muted.clone(),
+        playback_muted: playback_muted.clone(),
+    };
+    let service = SoundFlowService::new(config.clone(), side, &tasks, tls.is_some());
+    let (tx, recording, rooms) = (service.consumer.clone(), service.recording.clone(), service.rooms.clone());
+
+    info!("Sound Flow Server listening on {}", addr);
+
+    let router = router(&config, service, health_service, tls)?;
+
+    let room_idle_timeout = config.room_idle_timeout();
+    tasks.spawn("room sweeper", async move {
+        let mut sweeps = tokio::time::interval(ROOM_SWEEP_INTERVAL);
+        loop {
+            sweeps.tick().await;
+            rooms.close_idle(room_idle_timeout);
+        }
+    });
+
+    telemetry::export_metrics(&config, counters.clone());
+    if let Some(metrics_addr) = config.metrics_listen.map(|addr| config.bound(addr)).transpose()? {
+        let counters = counters.clone();
+        info!("serving metrics on http://{}/metrics", metrics_addr);
+        tasks.spawn("metrics", async move {
+            if let Err(e) = metrics::serve(metrics_addr, counters).await {
+                error!("failed to serve metrics on {}: {:#}", metrics_addr, e);
+            }
+        });
+    }
+
+    let stopped = tasks.cancelled_owned(); // stops taking connections, those open are waited for below
+    let server = tokio::spawn(async move {
+        let served = listener.serve(router, stopped).await;
+        if let Err(e) = served {
+            error!("failed to serve on {}: {:?}", addr, e);
+        }
+    });
+    let mut serving = false;
+    let mut captured = config.throughput("captured");
+    let mut capture_idle = config.capture_idle();
+    let mut overruns = OverrunLog::default();
+    let mut retries = tokio::time::interval(RETRY_INTERVAL);
+    loop {
+        if let Some((recorded_consumer, _)) = input.stream.as_mut() {
+            counters.capture_ring_fill.store(recorded_consumer.len(), Ordering::Relaxed);
+            broadcast_captured(recorded_consumer, &tx, &mut captured);
+        }
+        captured.tick();
+        overruns.tick(&counters);
+        if let Some(idle) = capture_idle.as_mut() {
+            input.set_paused(idle.paused(capture_in_use(&counters, &recording), Instant::now()));
+        }
+        let streams_ok = input.health.ok() && output.health.ok() && input.recovery.is_none() && output.recovery.is_none();
+        if streams_ok != serving {
+            serving = streams_ok;
+            set_health(&mut health, serving).await;
+        }
+        let input_retry = input.recovery.as_ref().map(|recovery| recovery.next);
+        let output_retry = output.recovery.as_ref().map(|recovery| recovery.next);
+        tokio::select! {
+            Some(command) = commands.recv() => match command {
+                AudioCommand::ReopenInput(reply) => {
+                    let playback = output.current();
+                    let _ = reply.send(input.reopen(playback));
+                }
+                AudioCommand::SetOutputs(outputs, reply) => {
+                    let _ = reply.send(output.mirror(&outputs));
+                }
+            },
+            Ok(()) = devices.changed() => {
+                devices.borrow_and_update();
+                // A stream whose device went away stays broken, so move it to the new default device.
+                if !input.health.ok() {
+                    match input.reopen(output.current()) {
+                        Ok(()) => info!("input moved to the default device"),
+                        Err(e) => warn!("failed to reopen input device: {:#}", e),
+                    }
+                }
+                if !output.health.ok() {
+                    match output.reopen(input.current()) {
+                        Ok(()) => info!("output moved to the default device"),
+                        Err(e) => warn!("failed to reopen output device: {:#}", e),
+                    }
+                }
+            },
+            Some(()) = input_losses.recv() => {
+                if input.recovery.is_none() && config.device_retries > 0 {
+                    warn!("input device lost, rebuilding the stream");
+                    input.recovery = Some(Recovery::new());
+                }
+            },
+            Some(()) = output_losses.recv() => {
+                if output.recovery.is_none() && config.device_retries > 0 {
+                    warn!("output device lost, rebuilding the stream");
+                    output.recovery = Some(Recovery::new());
+                }
+            },
+            () = tokio::time::sleep_until(input_retry.unwrap_or_else(tokio::time::Instant::now)), if input_retry.is_some() => {
+                let playback = output.current();
+                input.recover(playback);
+            },
+            () = tokio::time::sleep_until(output_retry.unwrap_or_else(tokio::time::Instant::now)), if output_retry.is_some() => {
+                let capture = input.current();
+                output.recover(capture);
+            },
+            // Not every host reports a device being plugged in, so keep looking while one is missing.
+            _ = retries.tick(), if input.stream.is_none() || output.stream.is_none() => {
+                if input.stream.is_none() && input.reopen(output.current()).is_ok() {
+                    info!("input device appeared");
+                }
+                if output.stream.is_none() && output.reopen(input.current()).is_ok() {
+                    info!("output device appeared");
+                }
+            },
+            () = tasks.cancelled() => break,
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+        }
+    }
+
+    info!("shutting down");
+    set_health(&mut health, false).await;
+    if let Some((recorded_consumer, input_stream)) = input.stream.as_mut() {
+        let _ = input_stream.pause();
+        broadcast_captured(recorded_consumer, &tx, &mut captured);
+    }
+    let _ = tx.send(Err(())); // ends every get_flow stream and the recording
+    let finished = recording.lock().unwrap().take();
+    if let Some(recording) = finished {
+        let path = recording.path.display().to_string();
+        match recording.stop().await {
+            Ok(samples) => info!(%path, samples, "recording saved"),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    if tokio::time::timeout(config.shutdown_grace(), server).await.is_err() {
+        warn!("clients still connected after {:?}, closing anyway", config.shutdown_grace());
+    }
+    if !tasks.stop(config.shutdown_grace()).await {
+        warn!("background tasks still running after {:?}, exiting anyway", config.shutdown_grace());
+    }
+    if let Some(path) = &config.socket {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Some(output_stream) = &output.stream {
+        let _ = output_stream.pause();
+    }
+    output.mirror_streams.iter().for_each(|stream| { let _ = stream.pause(); });
+    drop(input);
+    drop(output);
+    Ok(())
+}
+
+/// The input stream and where it captures to, owned by the task in `run`.
+struct Input {
+    config: Arc<Config>,
+    health: StreamHealth,
+    muted: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+    echo: Option<EchoReference>,
+    format: Arc<Mutex<AudioFormat>>, // shared with the service, PLACEHOLDER_FORMAT until a device opens
+    open: Arc<AtomicBool>, // shared with the service, whether `stream` is set
+    stream: Option<(HeapConsumer<Captured>, Stream)>, // `None` until there is an input device
+    recovery: Option<Recovery>, // while rebuilding the stream after its device went away
+    paused: bool, // by --capture-idle-ms, a reopened stream plays again
+}
+
+impl Input {
+    /// Opens the default input device in place of the current stream. The new stream is built
+    /// before the old one is dropped, so capture only pauses for the swap. `playback` is the
+    /// output's format, if there is an output, to compare with.
+    fn reopen(&mut self, playback: Option<AudioFormat>) -> anyhow::Result<()> {
+        let opened = open_input(&self.config, &self.health, &self.muted, &self.counters, &self.echo);
+        let (consumer, stream, format) = opened.inspect_err(|_| self.health.ok.store(false, Ordering::Relaxed))?;
+        if let Some(playback) = playback {
+            check_formats(&format, &playback);
+        }
+        self.stream = Some((consumer, stream));
+        *self.format.lock().unwrap() = format;
+        self.open.store(true, Ordering::Relaxed);
+        self.recovery = None;
+        self.paused = false;
+        self.counters.capture_paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Pauses or resumes the stream, for --capture-idle-ms.
+    fn set_paused(&mut self, paused: bool) {
+        let Some((_, stream)) = self.stream.as_ref().filter(|_| paused != self.paused) else {
+            return;
+        };
+        let set = if paused { stream.pause().map_err(|e| e.to_string()) } else { stream.play().map_err(|e| e.to_string()) };
+        match set {
+            Ok(()) if paused => info!("nothing uses the capture, microphone paused"),
+            Ok(()) => info!("microphone resumed"),
+            Err(e) => {
+                warn!(paused, "failed to pause or resume the microphone: {}", e);
+                return;
+            }
+        }
+        self.paused = paused;
+        self.counters.capture_paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// The next attempt at rebuilding the stream after its device went away.
+    fn recover(&mut self, playback: Option<AudioFormat>) {
+        let Some(recovery) = self.recovery.take() else {
+            return;
+        };
+        let attempt = recovery.attempts + 1;
+        match self.reopen(playback) {
+            Ok(()) => info!(attempt, "input stream rebuilt"),
+            Err(e) => {
+                self.recovery = recovery.failed(self.config.device_retries);
+                match &self.recovery {
+                    Some(recovery) => warn!(attempt, "failed to rebuild the input stream: {:#}, retrying in {:?}", e, recovery.backoff),
+                    None => error!(attempt, "failed to rebuild the input stream: {:#}, giving up until the devices change", e),
+                }
+            }
+        }
+    }
+
+    /// The capture format, if there is an input stream.
+    fn current(&self) -> Option<AudioFormat> {
+        self.stream.is_some().then(|| self.format.lock().unwrap().clone())
+    }
+}
+
+/// The output stream and the ring it plays from, owned by the task in `run`.
+struct Output {
+    config: Arc<Config>,
+    health: StreamHealth,
+    volume: Gain,
+    muted: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+    echo: Option<EchoReference>,
+    format: Arc<Mutex<AudioFormat>>, // shared with the service, PLACEHOLDER_FORMAT until a device opens
+    open: Arc<AtomicBool>, // shared with the service, whether `stream` is set
+    ring: Arc<Mutex<PlaybackRing>>, // shared with the service, replaced along with the stream
+    stream: Option<Stream>, // `None` until there is an output device
+    recovery: Option<Recovery>, // while rebuilding the stream after its device went away
+    mirrors: Mirrors, // what the playback chain copies to the other sinks, kept across reopens
+    mirror_streams: Vec<Stream>, // playing those copies, one per other sink
+}
+
+impl Output {
+    /// Opens the default output device in place of the current stream. `capture` is the input's
+    /// format, if there is an input, to compare with.
+    fn reopen(&mut self, capture: Option<AudioFormat>) -> anyhow::Result<()> {
+        let opened = open_output(&self.config, &self.health, &self.volume, &self.muted, &self.counters, &self.echo, &self.mirrors);
+        let (ring, stream, format) = opened.inspect_err(|_| self.health.ok.store(false, Ordering::Relaxed))?;
+        if let Some(capture) = capture {
+            check_formats(&capture, &format);
+        }
+        *self.ring.lock().unwrap() = ring;
+        self.stream = Some(stream);
+        *self.format.lock().unwrap() = format;
+        self.open.store(true, Ordering::Relaxed);
+        self.recovery = None;
+        Ok(())
+    }
+
+    /// The next attempt at rebuilding the stream after its device went away.
+    fn recover(&mut self, capture: Option<AudioFormat>) {
+        let Some(recovery) = self.recovery.take() else {
+            return;
+        };
+        let attempt = recovery.attempts + 1;
+        match self.reopen(capture) {
+            Ok(()) => info!(attempt, "output stream rebuilt"),
+            Err(e) => {
+                self.recovery = recovery.failed(self.config.device_retries);
+                match &self.recovery {
+                    Some(recovery) => warn!(attempt, "failed to rebuild the output stream: {:#}, retrying in {:?}", e, recovery.backoff),
+                    None => error!(attempt, "failed to rebuild the output stream: {:#}, giving up until the devices change", e),
+                }
+            }
+        }
+    }
+
+    /// The playback format, if there is an output stream.
+    fn current(&self) -> Option<AudioFormat> {
+        self.stream.is_some().then(|| self.format.lock().unwrap().clone())
+    }
+
+    /// Plays what the speaker plays on `outputs` too, in place of the devices it was played on so
+    /// far. Either all of them open or nothing changes.
+    fn mirror(&mut self, outputs: &[OutputDevice]) -> anyhow::Result<()> {
+        let host = audio_host(&self.config)?;
+        let mut opened = Vec::new();
+        for output in outputs {
+            let mirror = match output {
+                OutputDevice::Host(name) => open_mirror_named(&self.config, &host, name),
+                // The pulse device plays on the sink PULSE_SINK names when it is opened, which the
+                // host already does while listing it.
+                OutputDevice::Pulse(sink) => {
+                    let before = std::env::var_os(PULSE_SINK_VAR);
+                    std::env::set_var(PULSE_SINK_VAR, sink);
+                    let mirror = open_mirror_named(&self.config, &host, PULSE_DEVICE);
+                    match before {
+                        Some(before) => std::env::set_var(PULSE_SINK_VAR, before),
+                        None => std::env::remove_var(PULSE_SINK_VAR),
+                    }
+                    mirror
+                }
+            };
+            opened.push(mirror.with_context(|| format!("failed to open {}", output))?);
+        }
+        let (mirrors, streams) = opened.into_iter().unzip();
+        self.mirrors.set(mirrors);
+        self.mirror_streams = streams;
+        info!(sinks = outputs.len(), "playing on other output devices");
+        Ok(())
+    }
+}
+
+/// Whether anything uses the capture, which --capture-idle-ms waits for: a listener of the
+/// device rather than a room, a monitor, a meter or a recording.
+pub(crate) fn capture_in_use(counters: &Counters, recording: &Mutex<Option<Recording>>) -> bool {
+    counters.capture_listeners.load(Ordering::Relaxed) > 0
+        || counters.monitors.load(Ordering::Relaxed) > 0
+        || counters.meters.load(Ordering::Relaxed) > 0
+        || recording.lock().unwrap().is_some()
+}
+
+/// Logs the formats both streams run at, and when they differ, what a sender of this capture
+/// has to negotiate for it to be converted for this machine's speaker.
+fn check_formats(capture: &AudioFormat, playback: &AudioFormat) {
+    info!(
+        "capture runs at {} Hz with {} channel(s), playback at {} Hz with {} channel(s)",
+        capture.sample_rate, capture.channels, playback.sample_rate, playback.channels,
+    );
+    if capture.sample_rate != playback.sample_rate || capture.channels != playback.channels {
+        info!(
+            "capture looped back is only converted if its sender negotiates {} Hz with {} channel(s)",
+            capture.sample_rate, capture.channels,
+        );
+    }
+}
+
+/// `map` if it routes `from` channels into `to`, else INVALID_ARGUMENT saying what it routes.
+fn checked_map(map: &ChannelMap, from: usize, to: usize) -> Result<ChannelMap, Status> {
+    if map.inputs() != from || map.outputs() != to {
+        return Err(Status::invalid_argument(format!(
+            "--channel-map routes {} channels to {}, but {} channels are sent to a {} channel speaker",
+            map.inputs(), map.outputs(), from, to,
+        )));
+    }
+    Ok(map.clone())
+}
+
+/// Logs the captured packages the input callback dropped, at most once every
+/// OVERRUN_LOG_INTERVAL, since logging from the callback itself would flood the log.
+#[derive(Default)]
+struct OverrunLog {
+    logged: u64, // input_overruns as of the last line
+    at: Option<Instant>, // when the last line was logged
+}
+
+impl OverrunLog {
+    fn tick(&mut self, counters: &Counters) {
+        let overruns = counters.input_overruns.load(Ordering::Relaxed);
+        if overruns <= self.logged || self.at.is_some_and(|at| at.elapsed() < OVERRUN_LOG_INTERVAL) {
+            return;
+        }
+        warn!(dropped = overruns - self.logged, "input stream fell behind: try increasing latency");
+        self.logged = overruns;
+        self.at = Some(Instant::now());
+    }
+}
+
+/// Sends everything captured so far to the get_flow listeners.
+fn broadcast_captured(recorded_consumer: &mut HeapConsumer<Captured>, tx: &Sender<Result<Flow, ()>>, throughput: &mut Throughput) {
+    while let Some(v) = recorded_consumer.pop() {
+        throughput.add(v.samples.len() * size_of::<f32>());
+        let _ = tx.send(Ok(Flow {
+            flow: v.samples,
+            captured_ns: latency::to_ns(v.at),
+            ..Default::default()
+        }));
+    }
+}
+
+/// Plays the capture straight back on the speaker, through the same rings and jitter buffer as
+/// frames from a sender, and logs the latency every LOOPBACK_REPORT_INTERVAL until `tasks` are
+/// cancelled.
+async fn loopback(config: &Config, capture: &mut HeapConsumer<Captured>, playback: &PlaybackRing, capture_format: &AudioFormat, playback_format: &AudioFormat, counters: &Counters, tasks: &Tasks) -> anyhow::Result<()> {
+    let (channels, playback_channels) = (capture_format.channels as usize, playback_format.channels as usize);
+    let channel_map = config.channel_map.as_ref().map(|map| checked_map(map, channels, playback_channels)).transpose()?;
+    let mut resampler = (capture_format.sample_rate != playback_format.sample_rate)
+        .then(|| Resampler::new(capture_format.sample_rate, playback_format.sample_rate, playback_channels, config.package_size))
+        .transpose()
+        .context("can't resample the capture for the speaker")?;
+    info!("looping the microphone back to the speaker, the server isn't started");
+    let mut report = tokio::time::interval(LOOPBACK_REPORT_INTERVAL);
+    let mut lane = playback.open();
+    let mut seq = 0;
+    let mut overruns = OverrunLog::default();
+    loop {
+        overruns.tick(counters);
+        while let Some(captured) = capture.pop() {
+            let samples = match &channel_map {
+                Some(map) => map.apply(&captured.samples),
+                None if channels != playback_channels => remap(&captured.samples, channels, playback_channels),
+                None => captured.samples,
+            };
+            let packages = match resampler.as_mut() {
+                None => vec![samples],
+                Some(resampler) => resampler.process(&samples).context("failed to resample the capture")?,
+            };
+            for samples in packages {
+                seq += 1;
+                if lane.push(Packet { stream: 1, seq, samples, captured: Some(captured.at), due: None, end: false }, config.overflow).await {
+                    Counters::add(&counters.output_overruns, 1);
+                    warn!(policy = ?config.overflow, "output stream fell behind: try increasing latency");
+                }
+            }
+        }
+        tokio::select! {
+            () = tasks.cancelled() => return Ok(()),
+            _ = report.tick() => match counters.latency.summary() {
+                None => info!("nothing captured has been played yet"),
+                Some(latency) => info!(mean = ?latency.mean, p95 = ?latency.p95, "microphone to speaker"),
+            },
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+        }
+    }
+}
+
+/// The SoundFlow service with the compression and authentication from `config`, next to
+/// `health` and reflection if enabled, served over `tls` if given.
+fn router(config: &Config, service: SoundFlowService, health: HealthServer<impl Health>, tls: Option<ServerTlsConfig>) -> anyhow::Result<Router<web::Layers>> {
+    let mut service = SoundFlowServer::new(service);
+    if let Some(encoding) = config.send_compression.encoding() {
+        service = service.send_compressed(encoding);
+    }
+    for encoding in config.accept_compression.iter().filter_map(|compression| compression.encoding()) {
+        service = service.accept_compressed(encoding);
+    }
+
+    let service = InterceptedService::new(service, require_token(config.auth_token.clone()));
+
+    let reflection = if config.reflection {
+        Some(tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(sound_flow::FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+            .build()?)
+    } else {
+        None
+    };
+
+    let mut builder = Server::builder();
+    if let Some(tls) = tls {
+        builder = builder.tls_config(tls)?;
+    }
+    let mut builder = web::enable(config, builder)?;
+    Ok(builder.add_service(health).add_service(service).add_optional_service(reflection))
+}
+
+/// Reports the SoundFlow service, and the server as a whole, as serving or not to grpc.health.v1 clients.
+async fn set_health(health: &mut HealthReporter, serving: bool) {
+    let status = if serving { ServingStatus::Serving } else { ServingStatus::NotServing };
+    info!(?status, "health changed");
+    health.set_service_status("", status).await;
+    health.set_service_status(<SoundFlowServer<SoundFlowService> as NamedService>::NAME, status).await;
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM as well on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            },
+            Err(e) => {
+                error!("failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Logs to stderr, filtered by SF_LOG or else RUST_LOG (e.g. `SF_LOG=sf_core=debug`), at info by
+/// default, and exports the spans that pass the filter with --otlp-endpoint.
+fn init_logging(config: &Config) {
+    let filter = EnvFilter::try_from_env("SF_LOG")
+        .or_else(|_| EnvFilter::try_from_default_env())
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let logger = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let logger = match config.log_format {
+        LogFormat::Text => logger.boxed(),
+        LogFormat::Json => logger.json().boxed(),
+    };
+    let (spans, failed) = match telemetry::layer(config) {
+        Ok(spans) => (spans, None),
+        Err(e) => (None, Some(e)),
+    };
+    tracing_subscriber::registry().with(spans).with(filter).with(logger).init();
+    if let Some(e) = failed {
+        error!("{:#}, not exporting spans", e);
+    }
+}
+
+/// Keeps calling `setup` until it succeeds, so a device that is missing at startup doesn't abort the service.
+async fn retry<T>(what: &str, setup: impl Fn() -> anyhow::Result<T>) -> T {
+    loop {
+        match setup() {
+            Ok(value) => return value,
+            Err(e) => {
+                warn!("failed to set up {}: {:#}, retrying in {:?}", what, e, RETRY_INTERVAL);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Turns a received frame back into raw samples, decoding its payload first if it carries one, as
+/// its encoding says or else as `format` was negotiated.
+fn decode_flow(flow: Flow, decoder: &mut Option<OpusDecoder>, format: &AudioFormat) -> anyhow::Result<Vec<f32>> {
+    if flow.payload.is_empty() {
+        return Ok(flow.flow);
+    }
+    let sample_format = match Encoding::try_from(flow.encoding) {
+        Ok(Encoding::Negotiated) if format.codec() == Codec::Raw => format.sample_format(),
+        Ok(Encoding::Negotiated | Encoding::Opus) => return decode_opus(&flow.payload, decoder, format),
+        Ok(Encoding::F32) => SampleFormat::F32,
+        Ok(Encoding::I16) => SampleFormat::I16,
+        Ok(Encoding::U16) => SampleFormat::U16,
+        Err(_) => anyhow::bail!("unknown payload encoding {}", flow.encoding),
+    };
+    from_payload(&flow.payload, sample_format)
+}
+
+/// The opus encoder a listener asking for `codec` needs for capture in `format`, if any.
+fn listener_encoder(codec: Codec, format: &AudioFormat) -> Result<Option<OpusEncoder>, Status> {
+    match codec {
+        Codec::Raw => Ok(None),
+        Codec::Opus if !opus_supports(format) => Err(Status::failed_precondition("capture format can't be encoded with opus")),
+        Codec::Opus => OpusEncoder::new(format).map(Some)
+            .map_err(|e| Status::internal(format!("failed to create opus encoder: {}", e))),
+    }
+}
+
+/// `frame` with its samples packed in `sample_format`, or as it is for F32.
+fn packed(frame: Flow, sample_format: SampleFormat) -> Flow {
+    match sample_format {
+        SampleFormat::F32 => frame,
+        _ => Flow { payload: to_payload(&frame.flow, sample_format), encoding: encoding(sample_format).into(), flow: Vec::new(), ..frame },
+    }
+}
+
+/// The frames of what a listener's `encoder` or `framer` still holds, for a switch of codec.
+/// An opus packet is padded with silence and timed by `captured_ns`, the last frame it took in.
+fn flush_listener(encoder: Option<&mut OpusEncoder>, framer: Option<&mut Framer>, sample_format: SampleFormat, captured_ns: u64) -> Vec<Flow> {
+    match (encoder, framer) {
+        (Some(encoder), _) => match encoder.flush() {
+            Ok(packet) => packet.into_iter().map(|payload| Flow { payload, encoding: Encoding::Opus.into(), captured_ns, ..Default::default() }).collect(),
+            Err(e) => {
+                warn!("failed to encode flow: {}", e);
+                Vec::new()
+            }
+        },
+        (None, Some(framer)) => framer.flush().into_iter().map(|frame| packed(frame, sample_format)).collect(),
+        (None, None) => Vec::new(),
+    }
+}
+
+fn decode_opus(packet: &[u8], decoder: &mut Option<OpusDecoder>, format: &AudioFormat) -> anyhow::Result<Vec<f32>> {
+    let decoder = match decoder {
+        Some(decoder) => decoder,
+        None => decoder.insert(OpusDecoder::new(format)?),
+    };
+    Ok(decoder.decode(packet)?)
+}
+
+/// How long one interleaved sample lasts in a stream built with `config`.
+fn sample_duration(config: &cpal::StreamConfig) -> Duration {
+    Duration::from_secs_f64(1.0 / (config.sample_rate.0 as f64 * config.channels as f64))
+}
+
+/// Logs stream errors and marks the stream unhealthy until its data callback runs again. A
+/// device that went away won't call it again, so that is reported to be rebuilt.
+fn err_fn(health: &StreamHealth) -> impl FnMut(cpal::StreamError) {
+    let health = health.clone();
+    move |err| {
+        error!("an error occurred on stream: {}", err);
+        health.ok.store(false, Ordering::Relaxed);
+        if let (cpal::StreamError::DeviceNotAvailable, Some(lost)) = (&err, &health.lost) {
+            let _ = lost.send(());
+        }
+    }
+}
+
+/// Builds an input stream capturing in the device's own `sample_format`, handing `on_data` the
+/// samples converted to f32 along with how long ago the first of them was captured.
+fn build_input(device: &cpal::Device, config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, on_data: impl FnMut(Vec<f32>, Duration) + Send + 'static, health: &StreamHealth) -> Result<Stream, SetupError> {
+    fn build<T: DeviceSample>(device: &cpal::Device, config: &cpal::StreamConfig, to_f32: fn(T) -> f32, mut on_data: impl FnMut(Vec<f32>, Duration) + Send + 'static, health: &StreamHealth) -> Result<Stream, BuildStreamError> {
+        let channels = config.channels.max(1) as usize;
+        let mut granted = false;
+        let data_fn = move |data: &[T], info: &cpal::InputCallbackInfo| {
+            if !granted {
+                granted = true;
+                info!(frames = data.len() / channels, "input device buffer");
+            }
+            let delay = info.timestamp().callback.duration_since(&info.timestamp().capture).unwrap_or_default();
+            on_data(data.iter().map(|&s| to_f32(s)).collect(), delay);
+        };
+        device.build_input_stream(config, data_fn, err_fn(health), None)
+    }
+    let stream = match sample_format {
+        cpal::SampleFormat::I8 => build(device, config, i8::to_f32, on_data, health),
+        cpal::SampleFormat::I16 => build(device, config, i16::to_f32, on_data, health),
+        cpal::SampleFormat::I32 => build(device, config, i32::to_f32, on_data, health),
+        cpal::SampleFormat::I64 => build(device, config, i64::to_f32, on_data, health),
+        cpal::SampleFormat::U8 => build(device, config, u8::to_f32, on_data, health),
+        cpal::SampleFormat::U16 => build(device, config, u16::to_f32, on_data, health),
+        cpal::SampleFormat::U32 => build(device, config, u32::to_f32, on_data, health),
+        cpal::SampleFormat::U64 => build(device, config, u64::to_f32, on_data, health),
+        cpal::SampleFormat::F32 => build(device, config, f32::to_f32, on_data, health),
+        cpal::SampleFormat::F64 => build(device, config, f64::to_f32, on_data, health),
+        other => return Err(SetupError::UnsupportedFormat(other)),
+    };
+    stream.map_err(SetupError::Build)
+}
+
+/// Builds an output stream playing in the device's own `sample_format`, converting the f32
+/// samples `on_data` fills in, which is told how long until the first of them is heard.
+fn build_output(device: &cpal::Device, config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, on_data: impl FnMut(&mut [f32], Duration) + Send + 'static, health: &StreamHealth) -> Result<Stream, SetupError> {
+    fn build<T: DeviceSample>(device: &cpal::Device, config: &cpal::StreamConfig, from_f32: fn(f32) -> T, mut on_data: impl FnMut(&mut [f32], Duration) + Send + 'static, health: &StreamHealth) -> Result<Stream, BuildStreamError> {
+        let mut buffer = Vec::new();
+        let channels = config.channels.max(1) as usize;
+        let mut granted = false;
+        let data_fn = move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            if !granted {
+                granted = true;
+                info!(frames = data.len() / channels, "output device buffer");
+            }
+            let delay = info.timestamp().playback.duration_since(&info.timestamp().callback).unwrap_or_default();
+            buffer.resize(data.len(), 0.0);
+            on_data(&mut buffer, delay);
+            data.iter_mut().zip(&buffer).for_each(|(out, &s)| *out = from_f32(s));
+        };
+        device.build_output_stream(config, data_fn, err_fn(health), None)
+    }
+    let stream = match sample_format {
+        cpal::SampleFormat::I8 => build(device, config, i8::from_f32, on_data, health),
+        cpal::SampleFormat::I16 => build(device, config, i16::from_f32, on_data, health),
+        cpal::SampleFormat::I32 => build(device, config, i32::from_f32, on_data, health),
+        cpal::SampleFormat::I64 => build(device, config, i64::from_f32, on_data, health),
+        cpal::SampleFormat::U8 => build(device, config, u8::from_f32, on_data, health),
+        cpal::SampleFormat::U16 => build(device, config, u16::from_f32, on_data, health),
+        cpal::SampleFormat::U32 => build(device, config, u32::from_f32, on_data, health),
+        cpal::SampleFormat::U64 => build(device, config, u64::from_f32, on_data, health),
+        cpal::SampleFormat::F32 => build(device, config, f32::from_f32, on_data, health),
+        cpal::SampleFormat::F64 => build(device, config, f64::from_f32, on_data, health),
+        other => return Err(SetupError::UnsupportedFormat(other)),
+    };
+    stream.map_err(SetupError::Build)
+}
+
+/// The audio host --host names, or the platform's default.
+fn audio_host(config: &Config) -> anyhow::Result<cpal::Host> {
+    let Some(name) = &config.host else {
+        return Ok(cpal::default_host());
+    };
+    let available = cpal::available_hosts();
+    let id = available.iter().find(|id| id.name().eq_ignore_ascii_case(name)).with_context(|| {
+        let names: Vec<_> = available.iter().map(|id| id.name()).collect();
+        format!("audio host {} isn't available, this machine has: {}", name, names.join(", "))
+    })?;
+    cpal::host_from_id(*id).with_context(|| format!("failed to open audio host {}", id.name()))
+}
+
+/// Opens the audio host once up front, so a wrong --host fails right away rather than in the
+/// device retry loops, and logs it with the devices it would use.
+fn check_host(config: &Config) -> anyhow::Result<()> {
+    let host = audio_host(config)?;
+    let input = host.default_input_device().map(|device| device.name().unwrap_or_else(|_| "Unknown".to_string()));
+    let output = host.default_output_device().map(|device| device.name().unwrap_or_else(|_| "Unknown".to_string()));
+    info!(host = host.id().name(), ?input, ?output, "using audio host");
+    if input.is_none() || output.is_none() {
+        warn!(host = host.id().name(), "audio host has no default {} device, waiting for one", if input.is_none() { "input" } else { "output" });
+    }
+    Ok(())
+}
+
+/// The host's default input device and the config it prefers.
+fn default_input(host: &cpal::Host) -> Result<(cpal::Device, cpal::SupportedStreamConfig), SetupError> {
+    let device = host.default_input_device().ok_or(SetupError::NoDevice("input"))?;
+    let supported = device.default_input_config().map_err(SetupError::DefaultConfig)?;
+    Ok((device, supported))
+}
+
+/// The host's default output device and the config it prefers.
+fn default_output(host: &cpal::Host) -> Result<(cpal::Device, cpal::SupportedStreamConfig), SetupError> {
+    let device = host.default_output_device().ok_or(SetupError::NoDevice("output"))?;
+    let supported = device.default_output_config().map_err(SetupError::DefaultConfig)?;
+    Ok((device, supported))
+}
+
+/// The `supported` config with the buffer size --buffer-frames asks for, unless the `what`
+/// device reports a range that doesn't include it.
+fn buffered(supported: &cpal::SupportedStreamConfig, config: &Config, what: &str) -> cpal::StreamConfig {
+    let mut stream_config = supported.config();
+    match (config.buffer_frames, supported.buffer_size()) {
+        (None, _) => {}
+        (Some(frames), cpal::SupportedBufferSize::Range { min, max }) if !(*min..=*max).contains(&frames) => {
+            warn!(frames, min, max, "the {} device doesn't support --buffer-frames, using its default", what);
+        }
+        (Some(frames), _) => stream_config.buffer_size = cpal::BufferSize::Fixed(frames),
+    }
+    stream_config
+}
+
+/// Captures from the default input device of the configured host, see `microphone`.
+pub fn open_input(config: &Config, health: &StreamHealth, muted: &Arc<AtomicBool>, counters: &Arc<Counters>, echo: &Option<EchoReference>) -> anyhow::Result<(HeapConsumer<Captured>, Stream, AudioFormat)> {
+    let (device, supported) = default_input(&audio_host(config)?).context("failed to open input device")?;
+    let sample_format = supported.sample_format();
+    info!("Using input device: \"{}\" ({})", device.name().unwrap_or_else(|_| "Unknown".to_string()), sample_format);
+    let mut stream_config = buffered(&supported, config, "input");
+    let (consumer, stream) = match microphone(&device, &stream_config, sample_format, config, health, muted, counters, echo) {
+        Err(SetupError::Build(e)) if stream_config.buffer_size != cpal::BufferSize::Default => {
+            warn!("the input device refused --buffer-frames: {}, using its default", e);
+            stream_config.buffer_size = cpal::BufferSize::Default;
+            microphone(&device, &stream_config, sample_format, config, health, muted, counters, echo)
+        }
+        opened => opened,
+    }.context("failed to open input device")?;
+    Ok((consumer, stream, AudioFormat::from(&stream_config)))
+}
+
+/// Plays on the default output device of the configured host, see `speaker`.
+pub fn open_output(config: &Config, health: &StreamHealth, volume: &Gain, muted: &Arc<AtomicBool>, counters: &Arc<Counters>, echo: &Option<EchoReference>, mirrors: &Mirrors) -> anyhow::Result<(PlaybackRing, Stream, AudioFormat)> {
+    let (device, supported) = default_output(&audio_host(config)?).context("failed to open output device")?;
+    let sample_format = supported.sample_format();
+    info!("Using output device: \"{}\" ({})", device.name().unwrap_or_else(|_| "Unknown".to_string()), sample_format);
+    let mut stream_config = buffered(&supported, config, "output");
+    let (ring, stream) = match speaker(&device, &stream_config, sample_format, config, health, volume, muted, counters, echo, mirrors) {
+        Err(SetupError::Build(e)) if stream_config.buffer_size != cpal::BufferSize::Default => {
+            warn!("the output device refused --buffer-frames: {}, using its default", e);
+            stream_config.buffer_size = cpal::BufferSize::Default;
+            speaker(&device, &stream_config, sample_format, config, health, volume, muted, counters, echo, mirrors)
+        }
+        opened => opened,
+    }.context("failed to open output device")?;
+    Ok((ring, stream, AudioFormat::from(&stream_config)))
+}
+
+/// Starts capturing from `device` in `stream_config`, with samples arriving as `sample_format`,
+/// into the returned ring in packages of --package-size samples, with the speaker's echo taken
+/// out if there is an `echo` reference and the noise suppressed with --noise-suppression.
+#[allow(clippy::too_many_arguments)]
+pub fn microphone(device: &cpal::Device, stream_config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, config: &Config, health: &StreamHealth, muted: &Arc<AtomicBool>, counters: &Arc<Counters>, echo: &Option<EchoReference>) -> Result<(HeapConsumer<Captured>, Stream), SetupError> {
+    // The buffer to share samples
+    let ring = HeapRb::<Captured>::new(config.ring_capacity);
+    let (mut producer, consumer) = ring.split();
+    let package_size = config.package_samples(stream_config.channels.into());
+    let package_duration = sample_duration(stream_config) * package_size as u32;
+    let recovered = health.ok.clone();
+    recovered.store(true, Ordering::Relaxed);
+    let counters = counters.clone();
+    let format = AudioFormat::from(stream_config);
+    let mut processing = capture_chain(config, &format, muted, &counters, echo);
+    let processing_delay = processing.delay();
+
+    let input_data_fn = move |mut data: Vec<f32>, delay: Duration| {
+        recovered.store(true, Ordering::Relaxed);
+        processing.process(&mut data, &format);
+        let at = Instant::now().checked_sub(delay + processing_delay).unwrap_or_else(Instant::now);
+        capture_packages(&data, package_size, at, package_duration).for_each(|(package, at)| {
+            let captured = Captured { samples: package.to_vec(), at };
+            if producer.push(captured).is_err() {
+                Counters::add(&counters.input_overruns, 1); // logged from the main loop, see OverrunLog
+            }
+        });
+    };
+
+    let input_stream = build_input(device, stream_config, sample_format, input_data_fn, health)?;
+    input_stream.play().map_err(SetupError::Play)?;
+    Ok((consumer, input_stream))
+}
+
+/// What the microphone's audio goes through, in order: echo cancellation if there is an `echo`
+/// reference, --noise-suppression on 48 kHz capture, --normalize-dbfs, and the mute.
+fn capture_chain(config: &Config, format: &AudioFormat, muted: &Arc<AtomicBool>, counters: &Arc<Counters>, echo: &Option<EchoReference>) -> Chain {
+    let channels = (format.channels as usize).max(1);
+    let mut chain = Chain::default();
+    if let Some(reference) = echo {
+        chain.push(EchoCancellation::new(reference.clone(), config.echo_canceller(channels)));
+    }
+    let mut suppression_delay = Duration::ZERO;
+    if config.noise_suppression && format.sample_rate != denoise::SAMPLE_RATE {
+        warn!(capture = format.sample_rate, "noise suppression is off, it needs {} Hz capture", denoise::SAMPLE_RATE);
+    } else if config.noise_suppression {
+        chain.push(Timed::new(NoiseSuppressor::new(channels), counters.clone(), |counters| &counters.noise_suppression_processing_us));
+        suppression_delay = NoiseSuppressor::delay();
+    }
+    counters.noise_suppression_delay_us.store(suppression_delay.as_micros() as u64, Ordering::Relaxed);
+    counters.input_gain_mdb.store(0, Ordering::Relaxed);
+    if let Some(target) = config.normalize_dbfs {
+        chain.push(Normalizer::new(target, config.normalize_max_gain_db).reporting(counters.clone()));
+    }
+    chain.push(GainProcessor::new(Gain::new(1.0), muted.clone()));
+    chain
+}
+
+/// What the mix goes through on its way to the speaker, in order: the --fade-ms fade-in, the
+/// volume and mute, then into the `echo` reference if there is one and the other sinks' `mirrors`.
+fn playback_chain(config: &Config, volume: &Gain, muted: &Arc<AtomicBool>, echo: &Option<EchoReference>, mirrors: &Mirrors) -> Chain {
+    let mut chain = Chain::default();
+    chain.push(FadeIn::new(config.fade()));
+    chain.push(GainProcessor::new(volume.clone(), muted.clone()));
+    if let Some(reference) = echo {
+        chain.push(reference.clone());
+    }
+    chain.push(mirrors.clone());
+    chain
+}
+
+/// Starts playing on `device` in `stream_config`, with samples leaving as `sample_format`, whatever
+/// is pushed to the returned ring, mixed and at the given volume.
+#[allow(clippy::too_many_arguments)]
+pub fn speaker(device: &cpal::Device, stream_config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, config: &Config, health: &StreamHealth, volume: &Gain, muted: &Arc<AtomicBool>, counters: &Arc<Counters>, echo: &Option<EchoReference>, mirrors: &Mirrors) -> Result<(PlaybackRing, Stream), SetupError> {
+    // The buffer to share samples
+    let (ring, mut queued) = PlaybackRing::new(config.ring_capacity);
+    let package_size = config.package_samples(stream_config.channels.into());
+    let sample_duration = sample_duration(stream_config);
+    let package_duration = sample_duration * package_size as u32;
+    let format = AudioFormat::from(stream_config);
+    let mut mixer = config.mixer(&format);
+    let mut accumulator = Accumulator::new(package_size);
+    let counters = counters.clone();
+    if let Some(echo) = echo {
+        echo.started(format.sample_rate);
+    }
+    let mut processing = playback_chain(config, volume, muted, echo, mirrors);
+
+    // Each callback drains the ring into the mixer, then tops the accumulator up with a mixed package whenever it runs short.
+    let recovered = health.ok.clone();
+    recovered.store(true, Ordering::Relaxed);
+    let output_data_fn = move |data: &mut [f32], delay: Duration| {
+        recovered.store(true, Ordering::Relaxed);
+        let fill = queued.drain(|packet| mixer.push(packet));
+        counters.playback_ring_fill.store(fill, Ordering::Relaxed);
+        counters.playback_lane_fill.store(queued.fullest(), Ordering::Relaxed);
+        let now = Instant::now() + delay;
+        accumulator.fill(data, |ahead| {
+            let heard = now + sample_duration * ahead as u32;
+            // Silence goes through too, so the volume still ramps and the echo reference stays in step.
+            let mut mixed = mixer.pop(package_size, &(heard..heard + package_duration)).unwrap_or_else(|| vec![0.0; package_size]);
+            processing.process(&mut mixed, &format);
+            if let Some(captured) = mixer.take_captured() {
+                counters.latency.record(heard.saturating_duration_since(captured));
+            }
+            mixed
+        });
+        counters.output_underruns.store(mixer.underruns(), Ordering::Relaxed);
+        counters.frames_late.store(mixer.late(), Ordering::Relaxed);
+        counters.jitter_depth.store(mixer.depth(), Ordering::Relaxed);
+        counters.clock_drift_ppb.store((mixer.drift_ppm() * 1000.0) as i64, Ordering::Relaxed);
+        counters.drift_corrections.store(mixer.drift_corrections(), Ordering::Relaxed);
+    };
+    let output_stream = build_output(device, stream_config, sample_format, output_data_fn, health)?;
+    output_stream.play().map_err(SetupError::Play)?;
+    Ok((ring, output_stream))
+}
+
+/// Starts playing a copy of what the speaker plays on `host`'s output device called `name`.
+fn open_mirror_named(config: &Config, host: &cpal::Host, name: &str) -> anyhow::Result<(Mirror, Stream)> {
+    let device = host.output_devices().context("failed to list output devices")?
+        .find(|device| device.name().is_ok_and(|device| device == name))
+        .with_context(|| format!("{} has no output device \"{}\"", host.id().name(), name))?;
+    open_mirror(config, &device)
+}
+
+/// Starts playing on `device`, in the config it prefers, a copy of what the speaker plays.
+fn open_mirror(config: &Config, device: &cpal::Device) -> anyhow::Result<(Mirror, Stream)> {
+    let supported = device.default_output_config().map_err(SetupError::DefaultConfig)?;
+    let stream_config = buffered(&supported, config, "other output");
+    let format = AudioFormat::from(&stream_config);
+    let capacity = config.ring_capacity * config.package_samples(stream_config.channels.into());
+    let (mirror, mut mirrored) = Mirror::new(format, capacity);
+    let fill = move |data: &mut [f32], _delay: Duration| mirrored.fill(data);
+    // Nothing rebuilds it, a sink that goes away is just missing until SetOutputDevices is called again.
+    let stream = build_output(device, &stream_config, supported.sample_format(), fill, &StreamHealth::new(None))?;
+    stream.play().map_err(SetupError::Play)?;
+    Ok((mirror, stream))
+}
@@ -0,0 +1,27 @@
+use std::time::Instant;
+
+/// A token bucket over samples: refills at `rate` samples per second and holds at most a second's
+/// worth, so a sender may catch up after a stall but can't keep sending faster than `rate`.
+pub struct RateLimit {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    pub fn new(rate: f64) -> Self {
+        RateLimit { rate, tokens: rate, last: Instant::now() }
+    }
+
+    /// Whether `samples` more fit in the rate, taking them from the bucket if so.
+    pub fn allow(&mut self, samples: usize) -> bool {
+        let now = Instant::now();
+        self.tokens = (self.tokens + (now - self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+        if self.tokens < samples as f64 {
+            return false;
+        }
+        self.tokens -= samples as f64;
+        true
+    }
+}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use socket2::{Domain, Protocol, Socket, Type};
+use tonic::transport::server::{Router, TcpIncoming};
+
+use crate::web::Layers;
+
+const BACKLOG: i32 = 1024; // connections waiting to be accepted, as std and tokio use
+
+/// `addr` in IPv4, for --ipv4-only: the IPv6 wildcard and loopback become `0.0.0.0` and
+/// `127.0.0.1`, and an IPv4-mapped address the address it maps. Any other IPv6 address has no
+/// IPv4 counterpart.
+pub fn ipv4_addr(addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) if ip.is_unspecified() => Ipv4Addr::UNSPECIFIED,
+        IpAddr::V6(ip) if ip.is_loopback() => Ipv4Addr::LOCALHOST,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip,
+            None => bail!("{} is an IPv6 address, which --ipv4-only can't listen on", addr),
+        },
+    };
+    Ok(SocketAddr::new(IpAddr::V4(ip), addr.port()))
+}
+
+/// A non-blocking socket listening on `addr`. The IPv6 wildcard `[::]` is made dual-stack, so it
+/// takes IPv4 connections as IPv4-mapped addresses too, where the platform would otherwise
+/// decide: Linux does by default unless `net.ipv6.bindv6only` is set, Windows and the BSDs don't.
+pub fn bind_listener(addr: SocketAddr) -> anyhow::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))
+        .with_context(|| format!("failed to create a socket for {}", addr))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false).with_context(|| format!("failed to make {} dual-stack", addr))?;
+    }
+    // Lets a restarted server bind again while the old one's connections linger; on Windows
+    // it would let another process take the port, which is why std doesn't set it there either.
+    #[cfg(unix)]
+    socket.set_reuse_address(true).with_context(|| format!("failed to set up {}", addr))?;
+    socket.bind(&addr.into()).with_context(|| format!("failed to bind {}", addr))?;
+    socket.listen(BACKLOG).with_context(|| format!("failed to listen on {}", addr))?;
+    socket.set_nonblocking(true).with_context(|| format!("failed to set up {}", addr))?;
+    Ok(socket.into())
+}
+
+/// Where a server takes its connections from: a TCP port, or a Unix domain socket from
+/// `bind_socket`.
+pub enum Bound {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl From<tokio::net::TcpListener> for Bound {
+    fn from(listener: tokio::net::TcpListener) -> Self {
+        Bound::Tcp(listener)
+    }
+}
+
+impl Bound {
+    /// Serves `router` on every connection until `shutdown` completes.
+    pub(crate) async fn serve(self, router: Router<Layers>, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        match self {
+            Bound::Tcp(listener) => {
+                let incoming = TcpIncoming::from_listener(listener, false, None).map_err(|e| anyhow::anyhow!("failed to accept connections: {}", e))?;
+                router.serve_with_incoming_shutdown(incoming, shutdown).await?;
+            }
+            #[cfg(unix)]
+            Bound::Unix(listener) => {
+                router.serve_with_incoming_shutdown(tokio_stream::wrappers::UnixListenerStream::new(listener), shutdown).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A Unix domain socket listening at `path`, which only users `mode` lets write to it can
+/// connect to. A socket file left behind by a server that is gone is replaced, one a server still
+/// answers on is not.
+#[cfg(unix)]
+pub fn bind_socket(path: &Path, mode: u32) -> anyhow::Result<Bound> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("another server is already listening on {}", path.display());
+        }
+        std::fs::remove_file(path).with_context(|| format!("failed to remove the stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path).with_context(|| format!("failed to listen on {}", path.display()))?;
+    // Until this, the socket has the permissions the umask leaves, for a moment.
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set the permissions of {}", path.display()))?;
+    Ok(Bound::Unix(listener))
+}
+
+/// Unix domain sockets need a Unix.
+#[cfg(not(unix))]
+pub fn bind_socket(path: &Path, _mode: u32) -> anyhow::Result<Bound> {
+    bail!("can't listen on {}, this platform has no Unix domain sockets", path.display())
+}
@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::watch;
+
+use crate::sound_flow::{AudioFormat, ChannelLevel, Flow, Levels};
+
+const MIN_DBFS: f32 = -120.0; // reported for digital silence instead of minus infinity
+const CLIP: f32 = 0.999; // a sample at least this loud counts as clipped, full scale give or take integer rounding
+
+/// RMS and peak levels per channel of interleaved samples, measured over consecutive windows.
+pub struct Meter {
+    channels: usize,
+    window: usize, // frames per measurement
+    frames: usize, // in the window so far
+    squares: Vec<f64>,
+    peaks: Vec<f32>,
+}
+
+impl Meter {
+    /// Measures `format`'s audio `rate_hz` times a second.
+    pub fn new(format: &AudioFormat, rate_hz: u32) -> Self {
+        let channels = (format.channels as usize).max(1);
+        let window = (format.sample_rate / rate_hz.max(1)).max(1) as usize;
+        Meter { channels, window, frames: 0, squares: vec![0.0; channels], peaks: vec![0.0; channels] }
+    }
+
+    /// Adds `samples` to the measurement and returns the levels of the last window they complete.
+    pub fn feed(&mut self, samples: &[f32]) -> Option<Levels> {
+        let mut levels = None;
+        for frame in samples.chunks_exact(self.channels) {
+            for ((sample, square), peak) in frame.iter().zip(&mut self.squares).zip(&mut self.peaks) {
+                *square += (*sample as f64).powi(2);
+                *peak = peak.max(sample.abs());
+            }
+            self.frames += 1;
+            if self.frames == self.window {
+                levels = Some(self.take());
+            }
+        }
+        levels
+    }
+
+    fn take(&mut self) -> Levels {
+        let frames = std::mem::take(&mut self.frames) as f64;
+        let channels = self.squares.iter_mut().zip(&mut self.peaks).map(|(square, peak)| {
+            let rms = (std::mem::take(square) / frames).sqrt() as f32;
+            let peak = std::mem::take(peak);
+            ChannelLevel { rms_dbfs: dbfs(rms), peak_dbfs: dbfs(peak), clipped: peak >= CLIP }
+        }).collect();
+        Levels { channels }
+    }
+}
+
+fn dbfs(level: f32) -> f32 {
+    (20.0 * level.log10()).max(MIN_DBFS)
+}
+
+/// Meters every captured frame from `flows` and publishes the levels to `levels`, until capture
+/// ends. Starts over whenever the capture format changes.
+pub async fn run(mut flows: Receiver<Result<Flow, ()>>, capture_format: Arc<Mutex<AudioFormat>>, rate_hz: u32, levels: watch::Sender<Levels>) {
+    let mut format = capture_format.lock().unwrap().clone();
+    let mut meter = Meter::new(&format, rate_hz);
+    loop {
+        match flows.recv().await {
+            Ok(Ok(flow)) => {
+                let current = capture_format.lock().unwrap().clone();
+                if current != format {
+                    format = current;
+                    meter = Meter::new(&format, rate_hz);
+                }
+                if let Some(measured) = meter.feed(&flow.flow) {
+                    levels.send_replace(measured);
+                }
+            }
+            Ok(Err(())) | Err(RecvError::Closed) => return,
+            Err(RecvError::Lagged(_)) => {} // a level a bit off for one window is fine
+        }
+    }
+}
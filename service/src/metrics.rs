@@ -0,0 +1,91 @@
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+
+use crate::latency::Summary;
+use crate::listen::bind_listener;
+use crate::stats::Counters;
+
+/// Serves the counters in the Prometheus text format on `GET /metrics` until the task is dropped.
+pub async fn serve(addr: SocketAddr, counters: Arc<Counters>) -> anyhow::Result<()> {
+    let listener = bind_listener(addr)?;
+    let make_service = make_service_fn(move |_| {
+        let counters = counters.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let counters = counters.clone();
+                async move { Ok::<_, Infallible>(respond(&request, &counters)) }
+            }))
+        }
+    });
+    Ok(Server::from_tcp(listener)?.serve(make_service).await?)
+}
+
+fn respond(request: &Request<Body>, counters: &Counters) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("not found\n"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let mut response = Response::new(Body::from(render(counters)));
+    response.headers_mut().insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+    response
+}
+
+/// Every counter as its name, Prometheus type, help text and current value, for /metrics and
+/// for OTLP export.
+pub fn metrics(counters: &Counters) -> Vec<(&'static str, &'static str, &'static str, f64)> {
+    let counter = |value: &AtomicU64| value.load(Ordering::Relaxed) as f64;
+    let gauge = |value: &AtomicUsize| value.load(Ordering::Relaxed) as f64;
+    let latency = counters.latency.summary();
+    let micros = |pick: fn(&Summary) -> Duration| latency.as_ref().map_or(0.0, |latency| pick(latency).as_micros() as f64);
+    vec![
+        ("input_overruns_total", "counter", "Captured packages dropped because the capture ring was full.", counter(&counters.input_overruns)),
+        ("output_underruns_total", "counter", "Times the speaker ran dry while playing.", counter(&counters.output_underruns)),
+        ("output_overruns_total", "counter", "Received packages dropped because the playback ring was full.", counter(&counters.output_overruns)),
+        ("frames_sent_total", "counter", "Frames sent to GetFlow listeners.", counter(&counters.frames_sent)),
+        ("frames_received_total", "counter", "Frames received from SendFlow senders.", counter(&counters.frames_received)),
+        ("frames_lost_total", "counter", "Frames missing from the senders' sequence numbers.", counter(&counters.frames_lost)),
+        ("listener_drops_total", "counter", "Frames dropped because a listener fell behind.", counter(&counters.listener_drops)),
+        ("bytes_sent_total", "counter", "Encoded Flow bytes sent to listeners.", counter(&counters.bytes_sent)),
+        ("bytes_received_total", "counter", "Encoded Flow bytes received from senders.", counter(&counters.bytes_received)),
+        ("capture_ring_fill", "gauge", "Packages waiting in the capture ring.", gauge(&counters.capture_ring_fill)),
+        ("playback_ring_fill", "gauge", "Packages waiting in the playback ring.", gauge(&counters.playback_ring_fill)),
+        ("jitter_depth", "gauge", "Packages held by the jitter buffer.", gauge(&counters.jitter_depth)),
+        ("listeners", "gauge", "GetFlow streams currently open.", gauge(&counters.listeners)),
+        ("rate_limited_total", "counter", "Frames dropped because a sender went past the ingest speed limit.", counter(&counters.rate_limited)),
+        ("frames_empty_total", "counter", "Frames received from senders without samples, skipped.", counter(&counters.frames_empty)),
+        ("frames_oversized_total", "counter", "Frames received past the frame size limit, each ending its sender's flow.", counter(&counters.frames_oversized)),
+        ("senders", "gauge", "SendFlow streams currently open.", gauge(&counters.senders)),
+        ("monitors", "gauge", "Monitor streams currently open.", gauge(&counters.monitors)),
+        ("meters", "gauge", "Meter streams currently open.", gauge(&counters.meters)),
+        ("capture_paused", "gauge", "1 while the microphone is off for going unused, else 0.", u8::from(counters.capture_paused.load(Ordering::Relaxed)).into()),
+        ("latency_mean_microseconds", "gauge", "Average capture to playback latency of recent frames that came back.", micros(|latency| latency.mean)),
+        ("latency_p50_microseconds", "gauge", "Median capture to playback latency of recent frames that came back.", micros(|latency| latency.p50)),
+        ("latency_p95_microseconds", "gauge", "95th percentile capture to playback latency of recent frames that came back.", micros(|latency| latency.p95)),
+        ("latency_p99_microseconds", "gauge", "99th percentile capture to playback latency of recent frames that came back.", micros(|latency| latency.p99)),
+        ("noise_suppression_delay_microseconds", "gauge", "Capture delay added by noise suppression, 0 while off.", counter(&counters.noise_suppression_delay_us)),
+        ("noise_suppression_processing_microseconds", "gauge", "Time noise suppression took in the latest input callback.", counter(&counters.noise_suppression_processing_us)),
+        ("frames_late_total", "counter", "Received packages dropped for arriving after their playout time.", counter(&counters.frames_late)),
+        ("clock_drift_ppm", "gauge", "How much faster than the speaker's the clock of the sender that drifts the most runs.", counters.clock_drift_ppm().into()),
+        ("drift_corrections_total", "counter", "Frames drift compensation dropped or inserted.", counter(&counters.drift_corrections)),
+        ("input_gain_db", "gauge", "Gain capture normalization applies before the limiter, 0 while off.", counters.input_gain_db().into()),
+    ]
+}
+
+fn render(counters: &Counters) -> String {
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics(counters) {
+        let _ = writeln!(text, "# HELP soundflow_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE soundflow_{} {}", name, kind);
+        let _ = writeln!(text, "soundflow_{} {}", name, value);
+    }
+    text
+}
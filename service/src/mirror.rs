@@ -0,0 +1,105 @@
+use std::sync::{Arc, Mutex};
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use tracing::warn;
+
+use crate::channels::remap;
+use crate::dsp::Processor;
+use crate::resample::Resampler;
+use crate::sound_flow::AudioFormat;
+
+/// What the speaker plays, copied to every other sink SetOutputDevices added, as the last step
+/// of the playback chain. The output callback only ever tries to lock them, so swapping the sinks
+/// costs it a frame at most.
+#[derive(Clone, Default)]
+pub struct Mirrors {
+    mirrors: Arc<Mutex<Vec<Mirror>>>,
+}
+
+impl Mirrors {
+    /// Copies from now on to `mirrors` only.
+    pub fn set(&self, mirrors: Vec<Mirror>) {
+        *self.mirrors.lock().unwrap() = mirrors;
+    }
+}
+
+impl Processor for Mirrors {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        let Ok(mut mirrors) = self.mirrors.try_lock() else {
+            return;
+        };
+        mirrors.iter_mut().for_each(|mirror| mirror.push(frame, format));
+    }
+}
+
+/// One other sink's copy of the speaker, converted to its `format` on the way into its ring.
+pub struct Mirror {
+    format: AudioFormat,
+    producer: HeapProducer<f32>,
+    from: Option<(u32, u32)>, // the sample rate and channels converted from, set by the first frame
+    resampler: Option<Resampler>, // when the speaker runs at another sample rate
+    failed: bool, // the resampler couldn't be built, so the sink stays silent
+}
+
+impl Mirror {
+    /// A sink in `format` and what plays in it, which holds up to `capacity` samples.
+    pub fn new(format: AudioFormat, capacity: usize) -> (Self, Mirrored) {
+        let (producer, consumer) = HeapRb::new(capacity).split();
+        let mirror = Mirror { format, producer, from: None, resampler: None, failed: false };
+        (mirror, Mirrored { consumer, capacity, playing: false })
+    }
+
+    /// Queues `frame`, played in `format`, for the sink, dropping what doesn't fit.
+    fn push(&mut self, frame: &[f32], format: &AudioFormat) {
+        let channels = (self.format.channels as usize).max(1);
+        let from_channels = (format.channels as usize).max(1);
+        if self.from != Some((format.sample_rate, format.channels)) {
+            self.from = Some((format.sample_rate, format.channels));
+            self.failed = false;
+            self.resampler = None;
+            if format.sample_rate != self.format.sample_rate {
+                let package_size = frame.len() / from_channels * channels;
+                match Resampler::new(format.sample_rate, self.format.sample_rate, channels, package_size) {
+                    Ok(resampler) => self.resampler = Some(resampler),
+                    Err(e) => {
+                        warn!(from = format.sample_rate, to = self.format.sample_rate, "can't resample for another sink: {}", e);
+                        self.failed = true;
+                    }
+                }
+            }
+        }
+        if self.failed {
+            return;
+        }
+        let remapped = remap(frame, from_channels, channels);
+        match &mut self.resampler {
+            None => { self.producer.push_slice(&remapped); }
+            Some(resampler) => match resampler.process(&remapped) {
+                Ok(packages) => packages.iter().for_each(|package| { self.producer.push_slice(package); }),
+                Err(e) => warn!("failed to resample for another sink: {}", e),
+            },
+        }
+    }
+}
+
+/// The ring a sink's output callback plays a Mirror from.
+pub struct Mirrored {
+    consumer: HeapConsumer<f32>,
+    capacity: usize,
+    playing: bool, // false until twice a callback's worth is queued, and again after running dry
+}
+
+impl Mirrored {
+    /// Fills `data` with what was copied, or silence while too little is queued. It waits for
+    /// twice what a callback asks for before playing, so the two devices' callbacks running at
+    /// different times don't leave gaps.
+    pub fn fill(&mut self, data: &mut [f32]) {
+        if !self.playing && self.consumer.len() < (2 * data.len()).min(self.capacity) {
+            data.fill(0.0);
+            return;
+        }
+        let popped = self.consumer.pop_slice(data);
+        data[popped..].fill(0.0);
+        self.playing = popped == data.len();
+    }
+}
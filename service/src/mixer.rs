@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Instant;
+
+use tracing::info;
+
+use crate::drift::DriftCompensator;
+use crate::jitter::{JitterBuffer, Packet};
+
+const IDLE_PACKAGES: u32 = 200; // packages a stream may go without sending before it leaves the mix, ~2 s
+const CEILING: f32 = 1.0; // the limiter keeps every mixed sample within ±CEILING
+const RELEASE: f32 = 1.0 / 9600.0; // gain the limiter recovers per sample once the peak has passed, ~100 ms at 48 kHz stereo
+
+/// Sums the packages of every send_flow stream, each through its own jitter buffer so they are
+/// played in step, into one package per `pop`.
+pub struct Mixer {
+    voices: BTreeMap<u64, Voice>, // by stream id
+    depth: usize,
+    min: usize,
+    max: usize,
+    fill_gaps: bool,
+    conceal: bool,
+    fade_out: usize, // samples faded out at the end of each stream
+    drift: Option<usize>, // channels to compensate clock drift in, if it is
+    limiter: Limiter,
+    past_underruns: u64, // of streams that already left the mix
+    past_late: u64,
+    past_corrections: u64,
+}
+
+struct Voice {
+    jitter: JitterBuffer,
+    idle: u32, // pops since the stream last pushed a package
+    drift: Option<DriftCompensator>,
+    carry: Vec<f32>, // samples left over from a package the drift compensation stretched
+}
+
+impl Mixer {
+    /// Every stream gets a JitterBuffer built from these settings.
+    pub fn new(depth: usize, min: usize, max: usize, fill_gaps: bool, conceal: bool, fade_out: usize) -> Self {
+        Mixer { voices: BTreeMap::new(), depth, min, max, fill_gaps, conceal, fade_out, drift: None, limiter: Limiter::default(), past_underruns: 0, past_late: 0, past_corrections: 0 }
+    }
+
+    /// Keeps every stream's jitter buffer centred however its sender's clock drifts from the
+    /// pace packages are popped at, by stretching or squeezing them a frame of `channels` at a time.
+    pub fn compensating_drift(mut self, channels: usize) -> Self {
+        self.drift = Some(channels);
+        self
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        let streams = self.voices.len();
+        let voice = self.voices.entry(packet.stream).or_insert_with(|| {
+            info!(stream = packet.stream, streams = streams + 1, "stream joined the mix");
+            let jitter = JitterBuffer::new(self.depth, self.min, self.max, self.fill_gaps, self.conceal, self.fade_out);
+            Voice { jitter, idle: 0, drift: self.drift.map(DriftCompensator::new), carry: Vec::new() }
+        });
+        voice.idle = 0;
+        voice.jitter.push(packet);
+    }
+
+    /// The next `len` samples of every stream summed and limited, to be heard during `slot`, or
+    /// `None` if none of them had anything to play. Streams leave the mix once they have played out after their end marker,
+    /// or after IDLE_PACKAGES if they stopped sending without one.
+    pub fn pop(&mut self, len: usize, slot: &Range<Instant>) -> Option<Vec<f32>> {
+        let mut mixed: Option<Vec<f32>> = None;
+        for voice in self.voices.values_mut() {
+            voice.idle += 1;
+            if let Some(package) = voice.pop(len, slot) {
+                let mixed = mixed.get_or_insert_with(|| vec![0.0; len]);
+                mixed.iter_mut().zip(&package).for_each(|(mixed, sample)| *mixed += sample);
+            }
+        }
+        self.voices.retain(|&stream, voice| {
+            let ended = voice.jitter.finished();
+            if voice.idle < IDLE_PACKAGES && !ended {
+                return true;
+            }
+            info!(stream, ended, "stream left the mix");
+            self.past_underruns += voice.jitter.underruns;
+            self.past_late += voice.jitter.late;
+            self.past_corrections += voice.drift.as_ref().map_or(0, |drift| drift.corrections);
+            false
+        });
+        if let Some(mixed) = mixed.as_mut() {
+            self.limiter.apply(mixed);
+        }
+        mixed
+    }
+
+    /// Packages buffered by the deepest jitter buffer.
+    pub fn depth(&self) -> usize {
+        self.voices.values().map(|voice| voice.jitter.depth()).max().unwrap_or(0)
+    }
+
+    /// Underruns since the mixer was created, summed over its streams.
+    pub fn underruns(&self) -> u64 {
+        self.past_underruns + self.voices.values().map(|voice| voice.jitter.underruns).sum::<u64>()
+    }
+
+    /// Packages dropped for arriving after they were due, since the mixer was created.
+    pub fn late(&self) -> u64 {
+        self.past_late + self.voices.values().map(|voice| voice.jitter.late).sum::<u64>()
+    }
+
+    /// How much faster than the pace packages are popped at the clock of the stream that drifts
+    /// the most runs, in parts per million, or 0 without drift compensation.
+    pub fn drift_ppm(&self) -> f64 {
+        let drifts = self.voices.values().filter_map(|voice| voice.drift.as_ref());
+        drifts.map(|drift| drift.ppm).fold(0.0, |most, ppm| if ppm.abs() > most.abs() { ppm } else { most })
+    }
+
+    /// Frames drift compensation dropped or inserted since the mixer was created.
+    pub fn drift_corrections(&self) -> u64 {
+        let corrections = self.voices.values().filter_map(|voice| voice.drift.as_ref()).map(|drift| drift.corrections);
+        self.past_corrections + corrections.sum::<u64>()
+    }
+
+    /// The capture time of the last package popped, for the first stream that has one.
+    pub fn take_captured(&mut self) -> Option<Instant> {
+        self.voices.values_mut().fold(None, |first, voice| first.or(voice.jitter.captured.take()))
+    }
+}
+
+impl Voice {
+    /// The stream's next `len` samples, from its jitter buffer through the drift compensation
+    /// if there is one, which takes another package when the last was squeezed and none when
+    /// enough was left over from stretching.
+    fn pop(&mut self, len: usize, slot: &Range<Instant>) -> Option<Vec<f32>> {
+        let Some(drift) = self.drift.as_mut() else {
+            return self.jitter.pop(slot);
+        };
+        while self.carry.len() < len {
+            let level = self.jitter.depth() * len + self.carry.len();
+            let underruns = self.jitter.underruns;
+            let Some(package) = self.jitter.pop(slot) else {
+                break;
+            };
+            if self.jitter.underruns != underruns {
+                drift.restart();
+            }
+            self.carry.extend(drift.correct(package, level, self.jitter.target() * len, slot.end - slot.start));
+        }
+        if self.carry.is_empty() {
+            return None;
+        }
+        let rest = self.carry.split_off(len.min(self.carry.len()));
+        let mut package = std::mem::replace(&mut self.carry, rest);
+        package.resize(len, 0.0);
+        Some(package)
+    }
+}
+
+/// A peak limiter: lowers the gain instantly for a sample that would go past CEILING and brings
+/// it back by RELEASE per sample, so a loud sum is turned down instead of clipped.
+struct Limiter {
+    gain: f32,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter { gain: 1.0 }
+    }
+}
+
+impl Limiter {
+    fn apply(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let peak = sample.abs();
+            self.gain = (self.gain + RELEASE).min(1.0);
+            if peak * self.gain > CEILING {
+                self.gain = CEILING / peak;
+            }
+            *sample *= self.gain;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::dsp::Processor;
+use crate::sound_flow::AudioFormat;
+use crate::stats::Counters;
+
+const CEILING: f32 = 0.891; // -1 dBFS, the limiter's brick wall
+const GATE_DBFS: f32 = -60.0; // quieter frames count as silence and leave the gain where it is
+const MIN_GAIN_DB: f32 = -40.0; // furthest a loud microphone is turned down
+const ATTACK: Duration = Duration::from_millis(500); // time constant of the gain falling for a loud microphone
+const RELEASE: Duration = Duration::from_secs(4); // and of it rising for a quiet one, slowly so pauses don't pump
+const LIMITER_RELEASE: Duration = Duration::from_millis(50); // how fast the limiter lets go after a peak
+
+/// Brings the capture towards a target RMS level with a slow automatic gain control, then keeps
+/// the peaks below CEILING with a limiter. The gain follows the level of whole frames, in dB,
+/// falling faster than it rises; frames quieter than GATE_DBFS hold it. The limiter acts on the
+/// very sample that would exceed the ceiling, and whatever still does is clipped to it.
+pub struct Normalizer {
+    target_dbfs: f32,
+    max_gain_db: f32,
+    gain_db: f32, // the slow gain, applied to every sample of a frame
+    limiter: f32, // the limiter's own gain on top, 1.0 while nothing is near the ceiling
+    counters: Option<Arc<Counters>>, // told the gain after every frame
+}
+
+impl Normalizer {
+    /// Normalizes towards `target_dbfs` RMS, boosting by at most `max_gain_db`.
+    pub fn new(target_dbfs: f32, max_gain_db: f32) -> Self {
+        Normalizer { target_dbfs, max_gain_db, gain_db: 0.0, limiter: 1.0, counters: None }
+    }
+
+    /// Keeps the gain in `counters.input_gain_mdb` after every frame.
+    pub(crate) fn reporting(self, counters: Arc<Counters>) -> Self {
+        Normalizer { counters: Some(counters), ..self }
+    }
+
+    /// The gain applied to the latest frame, before the limiter.
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+}
+
+impl Processor for Normalizer {
+    fn process(&mut self, frame: &mut Vec<f32>, format: &AudioFormat) {
+        let channels = (format.channels as usize).max(1);
+        let frames = frame.len() / channels;
+        if frames == 0 || format.sample_rate == 0 {
+            return;
+        }
+        let rms = (frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32).sqrt();
+        let level_dbfs = 20.0 * rms.max(f32::MIN_POSITIVE).log10();
+        if level_dbfs > GATE_DBFS {
+            let wanted = (self.target_dbfs - level_dbfs).clamp(MIN_GAIN_DB, self.max_gain_db);
+            let time = if wanted < self.gain_db { ATTACK } else { RELEASE };
+            let duration = frames as f32 / format.sample_rate as f32;
+            self.gain_db += (wanted - self.gain_db) * (1.0 - (-duration / time.as_secs_f32()).exp());
+        }
+        let gain = 10f32.powf(self.gain_db / 20.0);
+        let recovery = 1.0 - (-1.0 / (LIMITER_RELEASE.as_secs_f32() * format.sample_rate as f32)).exp();
+        for samples in frame.chunks_mut(channels) {
+            let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs())) * gain;
+            if peak * self.limiter > CEILING {
+                self.limiter = CEILING / peak;
+            }
+            let applied = gain * self.limiter;
+            samples.iter_mut().for_each(|sample| *sample = (*sample * applied).clamp(-CEILING, CEILING));
+            self.limiter += (1.0 - self.limiter) * recovery;
+        }
+        if let Some(counters) = &self.counters {
+            counters.input_gain_mdb.store((self.gain_db * 1000.0) as i64, Ordering::Relaxed);
+        }
+    }
+}
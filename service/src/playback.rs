@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use crate::config::Overflow;
+use crate::jitter::Packet;
+
+const BLOCK_TIMEOUT: Duration = Duration::from_millis(20); // longest --overflow block waits for room, about two packages
+const BLOCK_POLL: Duration = Duration::from_millis(1); // how often a blocked push looks for room again
+
+/// Where senders queue packages for the output callback. Each sender opens a Lane of its own, a
+/// lock-free single-producer single-consumer ring the callback drains, so senders contend with
+/// neither each other nor the callback. New lanes reach the callback through a channel it polls
+/// without blocking.
+#[derive(Clone)]
+pub struct PlaybackRing {
+    lanes: mpsc::Sender<Drained>,
+    capacity: usize, // packages per lane
+    draining: Arc<AtomicBool>, // until the PlaybackDrain is dropped along with its stream
+}
+
+impl PlaybackRing {
+    /// A ring with lanes of `capacity` packages, and the end of it the output callback drains.
+    pub fn new(capacity: usize) -> (Self, PlaybackDrain) {
+        let (lanes, opened) = mpsc::channel();
+        let draining = Arc::new(AtomicBool::new(true));
+        let drain = PlaybackDrain { opened, lanes: Vec::new(), draining: draining.clone(), fullest: 0 };
+        (PlaybackRing { lanes, capacity, draining }, drain)
+    }
+
+    /// A lane for one sender, to push its packages in order.
+    pub fn open(&self) -> Lane {
+        let (producer, consumer) = HeapRb::new(self.capacity).split();
+        let (closed, stale) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let _ = self.lanes.send(Drained { consumer, closed: closed.clone(), stale: stale.clone() });
+        Lane { producer, backlog: VecDeque::new(), closed, stale, draining: self.draining.clone() }
+    }
+}
+
+/// One sender's packages on their way to the output callback. The sender owns it, so pushing takes
+/// no lock. Packages that don't fit wait in a backlog of the sender's until the callback makes room.
+pub struct Lane {
+    producer: HeapProducer<Packet>,
+    backlog: VecDeque<Packet>, // newer than anything in the ring, with --overflow drop-oldest
+    closed: Arc<AtomicBool>, // set when the lane is dropped
+    stale: Arc<AtomicUsize>, // oldest packages the callback should skip, to make room with --overflow drop-oldest
+    draining: Arc<AtomicBool>,
+}
+
+impl Lane {
+    /// Whether the output callback still drains the lane, which stops when its stream is replaced.
+    pub fn is_open(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Queues `packet`, making room according to `policy` if the lane is full. Returns whether a
+    /// package, this one or an older one, was dropped.
+    pub async fn push(&mut self, packet: Packet, policy: Overflow) -> bool {
+        self.flush();
+        let mut packet = match self.backlog.is_empty() {
+            true => match self.producer.push(packet) {
+                Ok(()) => return false,
+                Err(rejected) => rejected,
+            },
+            false => packet,
+        };
+        match policy {
+            Overflow::DropNewest => true,
+            Overflow::DropOldest => {
+                // The oldest package in the ring goes once the callback gets to it, or else the oldest waiting here.
+                self.backlog.push_back(packet);
+                if self.stale.load(Ordering::Acquire) < self.producer.len() {
+                    self.stale.fetch_add(1, Ordering::AcqRel);
+                } else {
+                    self.backlog.pop_front();
+                }
+                true
+            }
+            Overflow::Block => {
+                let deadline = Instant::now() + BLOCK_TIMEOUT;
+                loop {
+                    match self.producer.push(packet) {
+                        Ok(()) => return false,
+                        Err(rejected) => packet = rejected,
+                    }
+                    if Instant::now() >= deadline || !self.is_open() {
+                        return true;
+                    }
+                    tokio::time::sleep(BLOCK_POLL).await;
+                }
+            }
+        }
+    }
+
+    /// Queues the end marker `end` behind whatever still waits in the backlog, and waits up to
+    /// BLOCK_TIMEOUT for the callback to make room for them, whatever the --overflow policy, so the
+    /// mix still learns the stream ended. Returns whether any of it was dropped.
+    pub async fn finish(&mut self, end: Packet) -> bool {
+        self.backlog.push_back(end);
+        let deadline = Instant::now() + BLOCK_TIMEOUT;
+        loop {
+            self.flush();
+            if self.backlog.is_empty() {
+                return false;
+            }
+            if Instant::now() >= deadline || !self.is_open() {
+                return true;
+            }
+            tokio::time::sleep(BLOCK_POLL).await;
+        }
+    }
+
+    /// Moves what waits in the backlog into the ring, as far as it has room.
+    fn flush(&mut self) {
+        while let Some(packet) = self.backlog.pop_front() {
+            if let Err(packet) = self.producer.push(packet) {
+                self.backlog.push_front(packet);
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for Lane {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+    }
+}
+
+/// The output callback's end of a PlaybackRing.
+pub struct PlaybackDrain {
+    opened: mpsc::Receiver<Drained>,
+    lanes: Vec<Drained>,
+    draining: Arc<AtomicBool>,
+    fullest: usize, // packages in the fullest lane at the last drain
+}
+
+struct Drained {
+    consumer: HeapConsumer<Packet>,
+    closed: Arc<AtomicBool>,
+    stale: Arc<AtomicUsize>,
+}
+
+impl PlaybackDrain {
+    /// Hands every queued package to `each`, lane after lane, and returns how many there were.
+    /// Never waits, and forgets lanes whose sender is gone once they are empty.
+    pub fn drain(&mut self, mut each: impl FnMut(Packet)) -> usize {
+        while let Ok(lane) = self.opened.try_recv() {
+            self.lanes.push(lane);
+        }
+        let (mut queued, mut fullest) = (0, 0);
+        self.lanes.retain_mut(|lane| {
+            let closed = lane.closed.load(Ordering::Acquire); // before draining, so nothing pushed before it is left behind
+            lane.consumer.skip(lane.stale.swap(0, Ordering::AcqRel));
+            queued += lane.consumer.len();
+            fullest = fullest.max(lane.consumer.len());
+            lane.consumer.pop_iter().for_each(&mut each);
+            !closed
+        });
+        self.fullest = fullest;
+        queued
+    }
+
+    /// Packages the fullest lane held at the last drain, how close a sender came to overrunning.
+    pub fn fullest(&self) -> usize {
+        self.fullest
+    }
+}
+
+impl Drop for PlaybackDrain {
+    fn drop(&mut self) {
+        self.draining.store(false, Ordering::Relaxed);
+    }
+}
+
+/// A sender's lane on whichever ring the output stream currently plays from, opened with the
+/// first package and again after the stream was replaced, e.g. when its device went away.
+pub struct PlaybackSender {
+    ring: Arc<Mutex<PlaybackRing>>,
+    lane: Option<Lane>,
+}
+
+impl PlaybackSender {
+    pub fn new(ring: Arc<Mutex<PlaybackRing>>) -> Self {
+        PlaybackSender { ring, lane: None }
+    }
+
+    /// Queues `packet` like Lane::push. Only opening a lane takes the lock on the current ring.
+    pub async fn push(&mut self, packet: Packet, policy: Overflow) -> bool {
+        let lane = match &mut self.lane {
+            Some(lane) if lane.is_open() => lane,
+            lane => lane.insert(self.ring.lock().unwrap().open()),
+        };
+        lane.push(packet, policy).await
+    }
+
+    /// Ends the stream like Lane::finish.
+    pub async fn finish(&mut self, end: Packet) -> bool {
+        let lane = match &mut self.lane {
+            Some(lane) if lane.is_open() => lane,
+            lane => lane.insert(self.ring.lock().unwrap().open()),
+        };
+        lane.finish(end).await
+    }
+}
+
+/// Hands the output callback exactly as many samples as it asks for, whatever its buffer size:
+/// whole packages are made as needed and what one callback leaves over starts the next, so
+/// packages that don't divide the buffer play out whole instead of being cut off.
+pub struct Accumulator {
+    pending: VecDeque<f32>, // made but not played yet
+}
+
+impl Accumulator {
+    pub fn new(package_size: usize) -> Self {
+        Accumulator { pending: VecDeque::with_capacity(2 * package_size) }
+    }
+
+    /// Fills `data`, calling `package` for the next one while there aren't enough samples left
+    /// over. It gets how many samples will play before that package starts, counting from the
+    /// start of `data`. Only what an empty package leaves short is zeroed.
+    pub fn fill(&mut self, data: &mut [f32], mut package: impl FnMut(usize) -> Vec<f32>) {
+        while self.pending.len() < data.len() {
+            let next = package(self.pending.len());
+            if next.is_empty() {
+                break;
+            }
+            self.pending.extend(next);
+        }
+        let len = self.pending.len().min(data.len());
+        data.iter_mut().zip(self.pending.drain(..len)).for_each(|(out, sample)| *out = sample);
+        data[len..].fill(0.0);
+    }
+}
@@ -0,0 +1,172 @@
+use libpulse_binding::volume::Volume;
+use pulsectl::controllers::{DeviceControl, SinkController, SourceController};
+use pulsectl::ControllerError;
+use pulsectl::controllers::types::DeviceInfo;
+use tonic::Status;
+use tracing::debug;
+
+use crate::devices::{DeviceController, OutputDevice};
+use crate::sound_flow::{Device, DeviceDirection, DeviceId};
+
+const LEVEL_TOLERANCE: f32 = 0.01; // how far a device may round a level it was set to
+const MONITOR_PREFIX: &str = "Monitor of "; // how PulseAudio describes a sink's monitor source
+
+/// PulseAudio's sinks and sources. Its controllers aren't Send, so each call connects anew.
+pub struct Pulse;
+
+impl Pulse {
+    /// Checks that a PulseAudio daemon is there to talk to.
+    pub fn connect() -> Result<Self, Status> {
+        SinkController::create().map_err(no_daemon)?;
+        Ok(Pulse)
+    }
+}
+
+impl DeviceController for Pulse {
+    fn list(&self, direction: DeviceDirection) -> Result<Vec<Device>, Status> {
+        let mut devices = Vec::new();
+        if direction != DeviceDirection::Capture {
+            let mut handler = SinkController::create().map_err(no_daemon)?;
+            devices.extend(list_devices(&mut handler, DeviceDirection::Playback)?);
+        }
+        if direction != DeviceDirection::Playback {
+            let mut handler = SourceController::create().map_err(no_daemon)?;
+            devices.extend(list_devices(&mut handler, DeviceDirection::Capture)?);
+        }
+        Ok(devices)
+    }
+
+    fn set_default(&self, device: &DeviceId) -> Result<(), Status> {
+        if device.direction() == DeviceDirection::Capture {
+            set_default_device(&mut SourceController::create().map_err(no_daemon)?, device.id)
+        } else {
+            set_default_device(&mut SinkController::create().map_err(no_daemon)?, device.id)
+        }
+    }
+
+    fn current(&self, direction: DeviceDirection) -> Result<Device, Status> {
+        let server_info = |e| pulse_err("get the server info", e);
+        if direction == DeviceDirection::Capture {
+            let mut handler = SourceController::create().map_err(no_daemon)?;
+            let name = handler.get_server_info().map_err(server_info)?.default_source_name;
+            return default_device(&mut handler, name, DeviceDirection::Capture);
+        }
+        let mut handler = SinkController::create().map_err(no_daemon)?;
+        let name = handler.get_server_info().map_err(server_info)?.default_sink_name;
+        default_device(&mut handler, name, DeviceDirection::Playback)
+    }
+
+    /// Listed by its description, a sink is played on by its name.
+    fn output(&self, id: u32) -> Result<OutputDevice, Status> {
+        let sink = find_device(&mut SinkController::create().map_err(no_daemon)?, id)?;
+        let name = sink.name.ok_or_else(|| Status::failed_precondition("device has no name to play on it by"))?;
+        Ok(OutputDevice::Pulse(name))
+    }
+
+    fn volume(&self, device: &DeviceId) -> Result<f32, Status> {
+        let info = if device.direction() == DeviceDirection::Capture {
+            find_device(&mut SourceController::create().map_err(no_daemon)?, device.id)?
+        } else {
+            find_device(&mut SinkController::create().map_err(no_daemon)?, device.id)?
+        };
+        device_level(&info)
+    }
+
+    fn set_volume(&self, device: &DeviceId, level: f32) -> Result<f32, Status> {
+        if device.direction() == DeviceDirection::Capture {
+            set_device_level(&mut SourceController::create().map_err(no_daemon)?, device.id, level)
+        } else {
+            set_device_level(&mut SinkController::create().map_err(no_daemon)?, device.id, level)
+        }
+    }
+}
+
+fn no_daemon(err: ControllerError) -> Status {
+    Status::unavailable(format!("no PulseAudio daemon: {}", err))
+}
+
+/// `err` from trying to `op`, as a status a client can act on: UNAVAILABLE when the daemon went
+/// away or dropped the request, NOT_FOUND for a device it doesn't know, INTERNAL otherwise.
+/// pulsectl only hands over the messages, so they are what tells the cases apart.
+fn pulse_err(op: &str, err: ControllerError) -> Status {
+    let message = format!("failed to {}: {}", op, err);
+    match &err {
+        ControllerError::PulseCtl(e) if e.starts_with("ConnectError") || e.starts_with("OperationError") => Status::unavailable(message),
+        ControllerError::PulseCtl(e) if e.contains("No such entity") => Status::not_found(message),
+        ControllerError::GetInfo(e) if e.contains("requested device") => Status::not_found(message),
+        _ => Status::internal(message),
+    }
+}
+
+/// Lists the sinks or sources of `handler`, tagging each with the `direction` it belongs to.
+fn list_devices(handler: &mut impl DeviceControl<DeviceInfo>, direction: DeviceDirection) -> Result<Vec<Device>, Status> {
+    let devices = handler.list_devices()
+        .map_err(|e| pulse_err("list devices", e))?;
+    Ok(devices.iter().map(|device| {
+        debug!("Device: {:?}", device);
+        to_device(device, direction)
+    }).collect())
+}
+
+/// Makes the device with index `id` the default sink or source of `handler`.
+fn set_default_device(handler: &mut impl DeviceControl<DeviceInfo>, id: u32) -> Result<(), Status> {
+    let device = find_device(handler, id)?;
+    let name = device.name.as_deref().ok_or_else(|| Status::failed_precondition("device has no settable name"))?;
+    let changed = handler.set_default_device(name)
+        .map_err(|e| pulse_err(&format!("make {} the default device", name), e))?;
+    if !changed {
+        return Err(Status::failed_precondition(format!("PulseAudio refused to make {} the default device", name)));
+    }
+    Ok(())
+}
+
+/// The device of `handler` with index `id`.
+fn find_device(handler: &mut impl DeviceControl<DeviceInfo>, id: u32) -> Result<DeviceInfo, Status> {
+    let devices = handler.list_devices()
+        .map_err(|e| pulse_err("list devices", e))?;
+    devices.into_iter().find(|device| device.index == id).ok_or_else(|| Status::not_found(format!("no device {}", id)))
+}
+
+/// The device of `handler` called `name`, the default PulseAudio reported.
+fn default_device(handler: &mut impl DeviceControl<DeviceInfo>, name: Option<String>, direction: DeviceDirection) -> Result<Device, Status> {
+    let name = name.ok_or_else(|| Status::not_found("no default device is set"))?;
+    let devices = handler.list_devices()
+        .map_err(|e| pulse_err("list devices", e))?;
+    devices.iter().find(|device| device.name.as_deref() == Some(name.as_str()))
+        .map(|device| to_device(device, direction))
+        .ok_or_else(|| Status::not_found(format!("the default device {} is gone", name)))
+}
+
+/// The average volume of `device`'s channels, relative to 100%.
+fn device_level(device: &DeviceInfo) -> Result<f32, Status> {
+    if !device.volume.is_valid() || device.volume.len() == 0 {
+        return Err(Status::failed_precondition("device has no volume"));
+    }
+    Ok(device.volume.avg().0 as f32 / Volume::NORMAL.0 as f32)
+}
+
+/// Sets every channel of device `id` to `level` relative to 100% and returns the level it ended
+/// up at, which may be rounded to the device's volume steps.
+fn set_device_level(handler: &mut impl DeviceControl<DeviceInfo>, id: u32, level: f32) -> Result<f32, Status> {
+    let device = find_device(handler, id)?;
+    let before = device_level(&device)?;
+    let mut volume = device.volume;
+    let channels = volume.len();
+    volume.set(channels, Volume((level * Volume::NORMAL.0 as f32).round() as u32));
+    handler.set_device_volume_by_index(id, &volume); // pulsectl drops the result, so read it back
+    let after = device_level(&find_device(handler, id)?)?;
+    if after == before && (after - level).abs() > LEVEL_TOLERANCE {
+        return Err(Status::failed_precondition("device doesn't support changing its volume"));
+    }
+    Ok(after)
+}
+
+/// A source that is a sink's monitor says so in its name, which PulseAudio usually does already.
+fn to_device(device: &DeviceInfo, direction: DeviceDirection) -> Device {
+    let monitor = direction == DeviceDirection::Capture && device.monitor.is_some(); // a source's is the sink it monitors
+    let mut name = device.description.clone().unwrap_or_else(|| "Unknown".to_string());
+    if monitor && !name.starts_with(MONITOR_PREFIX) {
+        name = format!("{}{}", MONITOR_PREFIX, name);
+    }
+    Device { id: device.index, name, direction: direction.into(), monitor }
+}
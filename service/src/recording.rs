@@ -0,0 +1,69 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{warn, Instrument};
+
+use crate::sound_flow::{AudioFormat, Flow};
+
+/// Captured audio being teed to a WAV file, fed from the same broadcast as get_flow.
+pub struct Recording {
+    pub path: PathBuf,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<hound::Result<u64>>, // resolves to the samples written once the header is finalized
+}
+
+impl Recording {
+    /// Creates the WAV file at `path` and writes every frame from `flows` to it until stopped or
+    /// until capture ends.
+    pub fn start(path: PathBuf, format: &AudioFormat, mut flows: Receiver<Result<Flow, ()>>) -> hound::Result<Self> {
+        let spec = WavSpec {
+            channels: format.channels as u16,
+            sample_rate: format.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut writer = WavWriter::create(&path, spec)?;
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                let flow = tokio::select! {
+                    _ = &mut stopped => break,
+                    flow = flows.recv() => flow,
+                };
+                match flow {
+                    Ok(Ok(flow)) => {
+                        for sample in flow.flow {
+                            writer.write_sample(sample)?;
+                        }
+                    }
+                    Ok(Err(())) | Err(RecvError::Closed) => break, // capture ended
+                    Err(RecvError::Lagged(n)) => warn!("recording fell behind, {} frames missing", n),
+                }
+            }
+            let samples = writer.len().into();
+            writer.finalize()?;
+            Ok(samples)
+        }.in_current_span());
+        Ok(Recording { path, stop, task })
+    }
+
+    /// Stops writing, finalizes the WAV header and returns the samples written.
+    pub async fn stop(self) -> anyhow::Result<u64> {
+        let _ = self.stop.send(()); // the task may already have ended with capture
+        let written = self.task.await.context("recording task panicked")?;
+        written.with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+/// Resolves a client supplied recording `name` inside `dir`, refusing anything that could escape it.
+pub fn recording_path(dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    let name = Path::new(name);
+    if name.as_os_str().is_empty() || !name.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("{} must be a relative path without `..`", name.display());
+    }
+    Ok(dir.join(name))
+}
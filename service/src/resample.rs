@@ -0,0 +1,43 @@
+use rubato::{FftFixedOut, ResampleError, Resampler as _, ResamplerConstructionError};
+
+const SUB_CHUNKS: usize = 2; // rubato's FFT sub-chunks per package, more lowers latency but costs CPU
+
+/// Converts interleaved f32 samples of any chunk size from one sample rate to another, emitting
+/// packages of a fixed size. The resampler keeps its state between calls, so chunk boundaries,
+/// where a package ends a fraction of a sample into the next, don't click.
+pub struct Resampler {
+    resampler: FftFixedOut<f32>,
+    channels: usize,
+    pending: Vec<Vec<f32>>, // de-interleaved input per channel, waiting for enough frames
+    output: Vec<Vec<f32>>, // reused per-channel output buffer
+}
+
+impl Resampler {
+    /// `package_size` is in interleaved samples and rounded down to whole frames.
+    pub fn new(from: u32, to: u32, channels: usize, package_size: usize) -> Result<Self, ResamplerConstructionError> {
+        let frames = (package_size / channels).max(1);
+        let resampler = FftFixedOut::new(from as usize, to as usize, frames, SUB_CHUNKS, channels)?;
+        let output = resampler.output_buffer_allocate(true);
+        Ok(Resampler { resampler, channels, pending: vec![Vec::new(); channels], output })
+    }
+
+    /// Buffers `samples` and returns every package that could be completed, possibly none.
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<Vec<f32>>, ResampleError> {
+        for frame in samples.chunks_exact(self.channels) {
+            for (pending, &sample) in self.pending.iter_mut().zip(frame) {
+                pending.push(sample);
+            }
+        }
+        let mut packages = Vec::new();
+        let mut start = 0;
+        while self.pending[0].len() - start >= self.resampler.input_frames_next() {
+            let needed = self.resampler.input_frames_next();
+            let input: Vec<&[f32]> = self.pending.iter().map(|pending| &pending[start..start + needed]).collect();
+            let (_, written) = self.resampler.process_into_buffer(&input, &mut self.output, None)?;
+            packages.push((0..written).flat_map(|i| self.output.iter().map(move |channel| channel[i])).collect());
+            start += needed;
+        }
+        self.pending.iter_mut().for_each(|pending| { pending.drain(..start); });
+        Ok(packages)
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+use tonic::Status;
+use tonic::metadata::MetadataMap;
+use tracing::info;
+
+use crate::config::Config;
+use crate::jitter::Packet;
+use crate::latency;
+use crate::mixer::Mixer;
+use crate::sound_flow::{AudioFormat, Flow};
+use crate::tasks::Tasks;
+
+pub const ROOM_HEADER: &str = "sf-room"; // request metadata naming the room to join, the device's own if absent or empty
+const MAX_ROOM_ID: usize = 64; // bytes
+
+/// A named room: what its senders send is mixed and broadcast to its listeners, instead of going
+/// to the speaker and coming from the microphone.
+pub struct Room {
+    pub id: String,
+    pub flows: broadcast::Sender<Result<Flow, ()>>,
+    mixer: Mutex<Mixer>,
+}
+
+impl Room {
+    /// Adds `packet` to the mix broadcast next.
+    pub fn push(&self, packet: Packet) {
+        self.mixer.lock().unwrap().push(packet);
+    }
+
+    /// Broadcasts `package_size` mixed samples every `period`, the way the speaker would play
+    /// them, until the room is closed.
+    async fn mix(room: Weak<Room>, package_size: usize, period: Duration) {
+        let mut ticks = tokio::time::interval(period);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            let now = ticks.tick().await.into_std();
+            let Some(room) = room.upgrade() else {
+                return;
+            };
+            let (mixed, captured) = {
+                let mut mixer = room.mixer.lock().unwrap();
+                (mixer.pop(package_size, &(now..now + period)), mixer.take_captured())
+            };
+            if let Some(flow) = mixed {
+                let _ = room.flows.send(Ok(Flow { flow, captured_ns: captured.map_or(0, latency::to_ns), ..Default::default() }));
+            }
+        }
+    }
+}
+
+/// The named rooms, each created when first joined. Whoever is in a room holds on to its `Arc`,
+/// so a room whose only reference is the map here is empty.
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Entry>>,
+    config: Config, // for each room's broadcast capacity and the jitter buffers of its mixer
+    tasks: Tasks, // the mixers run among
+}
+
+struct Entry {
+    room: Arc<Room>,
+    empty_since: Option<Instant>, // when close_idle first found nobody in the room
+}
+
+impl Rooms {
+    pub fn new(config: &Config, tasks: Tasks) -> Self {
+        Rooms { rooms: Mutex::new(HashMap::new()), config: config.clone(), tasks }
+    }
+
+    /// The room called `id`, opened if it doesn't exist yet and then mixed at the pace of `format`.
+    /// Keep it for as long as you are in it.
+    pub fn join(&self, id: &str, format: &AudioFormat) -> Arc<Room> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let entry = rooms.entry(id.to_string()).or_insert_with(|| {
+            info!(room = id, "room opened");
+            let (flows, _) = broadcast::channel(self.config.broadcast_capacity(format));
+            let room = Arc::new(Room { id: id.to_string(), flows, mixer: Mutex::new(self.config.mixer(format)) });
+            let package_size = self.config.package_samples(format.channels as usize);
+            let period = Duration::from_secs_f64(package_size as f64 / (format.sample_rate as f64 * format.channels.max(1) as f64));
+            self.tasks.spawn("room mixer", Room::mix(Arc::downgrade(&room), package_size, period));
+            Entry { room, empty_since: None }
+        });
+        entry.empty_since = None;
+        entry.room.clone()
+    }
+
+    /// Closes the rooms that have been empty for at least `timeout`.
+    pub fn close_idle(&self, timeout: Duration) {
+        let now = Instant::now();
+        self.rooms.lock().unwrap().retain(|id, entry| {
+            if Arc::strong_count(&entry.room) > 1 {
+                entry.empty_since = None;
+                return true;
+            }
+            let idle = now - *entry.empty_since.get_or_insert(now);
+            if idle < timeout {
+                return true;
+            }
+            info!(room = %id, "room closed, nobody was in it for {:?}", idle);
+            false
+        });
+    }
+}
+
+/// The room id a request names in its ROOM_HEADER metadata, `None` for the device's own room.
+pub fn requested_room(metadata: &MetadataMap) -> Result<Option<String>, Status> {
+    let Some(value) = metadata.get(ROOM_HEADER) else {
+        return Ok(None);
+    };
+    let id = value.to_str().map_err(|_| Status::invalid_argument(format!("{} must be printable ASCII", ROOM_HEADER)))?;
+    if id.len() > MAX_ROOM_ID {
+        return Err(Status::invalid_argument(format!("{} must be at most {} bytes", ROOM_HEADER, MAX_ROOM_ID)));
+    }
+    Ok(Some(id.to_string()).filter(|id| !id.is_empty()))
+}
@@ -0,0 +1,179 @@
+use anyhow::bail;
+
+use crate::sound_flow::{Encoding, SampleFormat};
+
+const SCALE: f32 = 32768.0; // i16 steps per 1.0, so -1.0 maps to i16::MIN exactly and 1.0 clamps to i16::MAX
+
+pub fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / SCALE
+}
+
+/// Rounds to the nearest step and clamps anything outside -1.0..=1.0, NaN becomes silence.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample * SCALE).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// u16 samples are offset by 32768, which is silence.
+pub fn u16_to_f32(sample: u16) -> f32 {
+    i16_to_f32((sample ^ 0x8000) as i16)
+}
+
+pub fn f32_to_u16(sample: f32) -> u16 {
+    f32_to_i16(sample) as u16 ^ 0x8000
+}
+
+/// A sample type a device may run in, converted to and from the f32 everything else works in.
+/// Integers map their whole range onto -1.0..1.0 like i16 does, and on the way back are rounded
+/// to the nearest step and clamped, NaN becoming silence. Unsigned ones are offset by half their
+/// range, which is silence. cpal has no 24-bit type: such devices report I32, in which 24-bit
+/// samples convert exactly.
+pub trait DeviceSample: cpal::SizedSample + Send + 'static {
+    fn to_f32(self) -> f32;
+    fn from_f32(sample: f32) -> Self;
+}
+
+/// A signed integer sample `bits` wide as f32, computed in f64 so wide ones keep what f32 can hold.
+fn from_signed(sample: f64, bits: i32) -> f32 {
+    (sample / 2f64.powi(bits - 1)) as f32
+}
+
+/// The nearest step of a signed integer `bits` wide to `sample`, within its range, to cast to it.
+fn to_signed(sample: f32, bits: i32) -> f64 {
+    let scale = 2f64.powi(bits - 1);
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0)
+}
+
+impl DeviceSample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample
+    }
+}
+
+impl DeviceSample for f64 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample.into()
+    }
+}
+
+impl DeviceSample for i8 {
+    fn to_f32(self) -> f32 {
+        from_signed(self.into(), 8)
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        to_signed(sample, 8) as i8
+    }
+}
+
+impl DeviceSample for i16 {
+    fn to_f32(self) -> f32 {
+        i16_to_f32(self)
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        f32_to_i16(sample)
+    }
+}
+
+impl DeviceSample for i32 {
+    fn to_f32(self) -> f32 {
+        from_signed(self.into(), 32)
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        to_signed(sample, 32) as i32
+    }
+}
+
+impl DeviceSample for i64 {
+    fn to_f32(self) -> f32 {
+        from_signed(self as f64, 64)
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        to_signed(sample, 64) as i64 // i64::MAX isn't an f64, the cast saturates to it
+    }
+}
+
+impl DeviceSample for u8 {
+    fn to_f32(self) -> f32 {
+        ((self ^ 0x80) as i8).to_f32()
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        i8::from_f32(sample) as u8 ^ 0x80
+    }
+}
+
+impl DeviceSample for u16 {
+    fn to_f32(self) -> f32 {
+        u16_to_f32(self)
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        f32_to_u16(sample)
+    }
+}
+
+impl DeviceSample for u32 {
+    fn to_f32(self) -> f32 {
+        ((self ^ 0x8000_0000) as i32).to_f32()
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        i32::from_f32(sample) as u32 ^ 0x8000_0000
+    }
+}
+
+impl DeviceSample for u64 {
+    fn to_f32(self) -> f32 {
+        ((self ^ 0x8000_0000_0000_0000) as i64).to_f32()
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        i64::from_f32(sample) as u64 ^ 0x8000_0000_0000_0000
+    }
+}
+
+/// The tag of a payload packed in `format`.
+pub fn encoding(format: SampleFormat) -> Encoding {
+    match format {
+        SampleFormat::F32 => Encoding::F32,
+        SampleFormat::I16 => Encoding::I16,
+        SampleFormat::U16 => Encoding::U16,
+    }
+}
+
+/// Packs samples into a Flow payload in `format`, little-endian.
+pub fn to_payload(samples: &[f32], format: SampleFormat) -> Vec<u8> {
+    match format {
+        SampleFormat::F32 => samples.iter().flat_map(|&s| s.to_le_bytes()).collect(),
+        SampleFormat::I16 => samples.iter().flat_map(|&s| f32_to_i16(s).to_le_bytes()).collect(),
+        SampleFormat::U16 => samples.iter().flat_map(|&s| f32_to_u16(s).to_le_bytes()).collect(),
+    }
+}
+
+/// Unpacks a payload written by `to_payload` with the same `format`.
+pub fn from_payload(payload: &[u8], format: SampleFormat) -> anyhow::Result<Vec<f32>> {
+    let size = match format {
+        SampleFormat::F32 => 4,
+        SampleFormat::I16 | SampleFormat::U16 => 2,
+    };
+    if !payload.len().is_multiple_of(size) {
+        bail!("{} byte payload doesn't hold whole {:?} samples", payload.len(), format);
+    }
+    let samples = payload.chunks_exact(size);
+    Ok(match format {
+        SampleFormat::F32 => samples.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        SampleFormat::I16 => samples.map(|b| i16_to_f32(i16::from_le_bytes([b[0], b[1]]))).collect(),
+        SampleFormat::U16 => samples.map(|b| u16_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect(),
+    })
+}
@@ -0,0 +1,41 @@
+/// What the sequence number of a newly arrived frame says about the frames before it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Arrival {
+    /// The frame directly follows the previous one, or the sender doesn't number its frames.
+    InOrder,
+    /// This many frames between the previous one and this one never arrived.
+    Gap(u64),
+    /// The frame belongs before one that already arrived.
+    Late,
+}
+
+/// Follows the `seq` numbers of one incoming flow to spot lost and reordered frames.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    next: Option<u64>, // the seq expected next, unknown until the first numbered frame
+    pub received: u64,
+    pub lost: u64,
+    pub reordered: u64,
+}
+
+impl SequenceTracker {
+    pub fn track(&mut self, seq: u64) -> Arrival {
+        self.received += 1;
+        if seq == 0 {
+            return Arrival::InOrder; // unnumbered
+        }
+        let expected = self.next.unwrap_or(seq);
+        if seq < expected {
+            self.reordered += 1;
+            return Arrival::Late;
+        }
+        self.next = Some(seq + 1);
+        match seq - expected {
+            0 => Arrival::InOrder,
+            missing => {
+                self.lost += missing;
+                Arrival::Gap(missing)
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+
+use cpal::{BuildStreamError, DefaultStreamConfigError, PlayStreamError, SampleFormat};
+
+/// Why an input or output stream couldn't be opened. The cpal error, if any, is the source, so
+/// `{:#}` through anyhow prints both.
+#[derive(Debug)]
+pub enum SetupError {
+    NoDevice(&'static str), // "input" or "output"
+    DefaultConfig(DefaultStreamConfigError),
+    UnsupportedFormat(SampleFormat),
+    Build(BuildStreamError),
+    Play(PlayStreamError),
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::NoDevice(direction) => write!(f, "failed to find {} device", direction),
+            SetupError::DefaultConfig(_) => write!(f, "failed to get default config"),
+            SetupError::UnsupportedFormat(format) => write!(f, "device uses {} samples, which sf_core has no conversion for", format),
+            SetupError::Build(_) => write!(f, "failed to build stream"),
+            SetupError::Play(_) => write!(f, "failed to start stream"),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SetupError::NoDevice(_) | SetupError::UnsupportedFormat(_) => None,
+            SetupError::DefaultConfig(e) => Some(e),
+            SetupError::Build(e) => Some(e),
+            SetupError::Play(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::latency::{Latencies, Summary};
+use crate::sound_flow::Stats;
+
+/// Counters shared by the cpal callbacks, the streaming handlers, get_stats and /metrics. Each one only
+/// ever has a single writer or is bumped with `fetch_add`, so relaxed ordering is enough.
+#[derive(Default)]
+pub struct Counters {
+    pub input_overruns: AtomicU64,
+    pub output_underruns: AtomicU64,
+    pub output_overruns: AtomicU64,
+    pub frames_sent: AtomicU64,
+    pub frames_received: AtomicU64,
+    pub frames_lost: AtomicU64,
+    pub listener_drops: AtomicU64,
+    pub rate_limited: AtomicU64, // frames dropped because a sender went past --max-ingest-speed
+    pub frames_empty: AtomicU64, // received without samples, skipped
+    pub frames_oversized: AtomicU64, // received past --max-frame-samples, each ending its flow
+    pub bytes_sent: AtomicU64, // encoded Flow sizes, what the frames cost on the wire before compression
+    pub bytes_received: AtomicU64,
+    pub capture_ring_fill: AtomicUsize,
+    pub playback_ring_fill: AtomicUsize,
+    pub playback_lane_fill: AtomicUsize, // of the fullest sender's lane
+    pub jitter_depth: AtomicUsize,
+    pub listeners: AtomicUsize, // get_flow streams currently open
+    pub capture_listeners: AtomicUsize, // of those, the ones on the device's capture rather than a room's
+    pub senders: AtomicUsize, // send_flow streams currently open
+    pub monitors: AtomicUsize, // monitor streams currently open
+    pub meters: AtomicUsize, // meter streams currently open
+    pub capture_paused: AtomicBool, // by --capture-idle-ms
+    pub latency: Latencies, // capture to playback of the frames that came back here
+    pub noise_suppression_delay_us: AtomicU64, // 0 while the capture isn't suppressed
+    pub noise_suppression_processing_us: AtomicU64, // of the latest input callback
+    pub input_gain_mdb: AtomicI64, // what --normalize-dbfs applies to the capture, in thousandths of a dB
+    pub frames_late: AtomicU64, // dropped by --playout-delay-ms
+    pub clock_drift_ppb: AtomicI64, // of the sender that drifts the most, in parts per billion
+    pub drift_corrections: AtomicU64,
+}
+
+impl Counters {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// The drift in parts per million, as it is reported.
+    pub fn clock_drift_ppm(&self) -> f32 {
+        self.clock_drift_ppb.load(Ordering::Relaxed) as f32 / 1000.0
+    }
+
+    /// The capture's normalization gain in dB, as it is reported.
+    pub fn input_gain_db(&self) -> f32 {
+        self.input_gain_mdb.load(Ordering::Relaxed) as f32 / 1000.0
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let level = |level: &AtomicUsize| level.load(Ordering::Relaxed) as u32;
+        let latency = self.latency.summary();
+        let micros = |pick: fn(&Summary) -> Duration| latency.as_ref().map_or(0, |latency| pick(latency).as_micros() as u64);
+        Stats {
+            input_overruns: get(&self.input_overruns),
+            output_underruns: get(&self.output_underruns),
+            output_overruns: get(&self.output_overruns),
+            frames_sent: get(&self.frames_sent),
+            frames_received: get(&self.frames_received),
+            frames_lost: get(&self.frames_lost),
+            listener_drops: get(&self.listener_drops),
+            bytes_sent: get(&self.bytes_sent),
+            bytes_received: get(&self.bytes_received),
+            capture_ring_fill: level(&self.capture_ring_fill),
+            playback_ring_fill: level(&self.playback_ring_fill),
+            jitter_depth: level(&self.jitter_depth),
+            listeners: level(&self.listeners),
+            rate_limited: get(&self.rate_limited),
+            senders: level(&self.senders),
+            latency_mean_us: micros(|latency| latency.mean),
+            latency_p50_us: micros(|latency| latency.p50),
+            latency_p95_us: micros(|latency| latency.p95),
+            latency_p99_us: micros(|latency| latency.p99),
+            noise_suppression_delay_us: get(&self.noise_suppression_delay_us),
+            noise_suppression_processing_us: get(&self.noise_suppression_processing_us),
+            input_gain_db: self.input_gain_db(),
+            frames_late: get(&self.frames_late),
+            clock_drift_ppm: self.clock_drift_ppm(),
+            drift_corrections: get(&self.drift_corrections),
+            frames_empty: get(&self.frames_empty),
+            frames_oversized: get(&self.frames_oversized),
+            monitors: level(&self.monitors),
+            meters: level(&self.meters),
+            capture_paused: self.capture_paused.load(Ordering::Relaxed),
+        }
+    }
+}
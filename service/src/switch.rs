@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::{mpsc, oneshot};
+use tonic::Status;
+
+use crate::sound_flow::{Codec, SampleFormat};
+
+pub const LISTENER_HEADER: &str = "sf-listener"; // response metadata of GetFlow and Duplex, the id SetCodec takes
+
+/// A SetCodec request on its way to a listener's task, answered with the seq of the first frame
+/// sent in the new codec.
+pub struct Switch {
+    pub codec: Codec,
+    pub sample_format: SampleFormat,
+    pub reply: oneshot::Sender<Result<u64, Status>>,
+}
+
+/// The open listeners SetCodec can reach, by the id their LISTENER_HEADER gives.
+#[derive(Default)]
+pub struct Switchboard {
+    next: AtomicU64,
+    listeners: Mutex<HashMap<u64, mpsc::Sender<Switch>>>,
+}
+
+impl Switchboard {
+    /// Numbers a new listener and returns where its switches arrive.
+    pub fn open(&self) -> (u64, mpsc::Receiver<Switch>) {
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::channel(4);
+        self.listeners.lock().unwrap().insert(id, tx);
+        (id, rx)
+    }
+
+    pub fn close(&self, id: u64) {
+        self.listeners.lock().unwrap().remove(&id);
+    }
+
+    /// Has listener `id` send `codec` from its next frame on, and returns that frame's seq.
+    pub async fn switch(&self, id: u64, codec: Codec, sample_format: SampleFormat) -> Result<u64, Status> {
+        let listener = self.listeners.lock().unwrap().get(&id).cloned()
+            .ok_or_else(|| Status::not_found(format!("no listener {}", id)))?;
+        let (reply, switched) = oneshot::channel();
+        let gone = || Status::not_found(format!("listener {} went away", id));
+        listener.send(Switch { codec, sample_format, reply }).await.map_err(|_| gone())?;
+        switched.await.map_err(|_| gone())?
+    }
+}
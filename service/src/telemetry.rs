@@ -0,0 +1,114 @@
+#[cfg(feature = "otel")]
+pub use self::otlp::{export_metrics, layer, shutdown};
+#[cfg(not(feature = "otel"))]
+pub use self::off::{export_metrics, layer, shutdown};
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use std::sync::{Arc, OnceLock};
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use opentelemetry::KeyValue;
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use opentelemetry_sdk::runtime::Tokio;
+    use tracing::{error, info};
+    use tracing_subscriber::{Layer, Registry};
+
+    use crate::config::Config;
+    use crate::metrics::metrics;
+    use crate::stats::Counters;
+
+    const EXPORT_INTERVAL: Duration = Duration::from_secs(10); // between metric exports
+
+    static METERS: OnceLock<SdkMeterProvider> = OnceLock::new(); // flushed by shutdown
+
+    fn resource(config: &Config) -> Resource {
+        Resource::new([KeyValue::new("service.name", config.otel_service_name.clone())])
+    }
+
+    /// A layer exporting every span, so every RPC's, to --otlp-endpoint, if it is set.
+    pub fn layer(config: &Config) -> anyhow::Result<Option<Box<dyn Layer<Registry> + Send + Sync>>> {
+        let Some(endpoint) = &config.otlp_endpoint else {
+            return Ok(None);
+        };
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(opentelemetry_sdk::trace::config().with_resource(resource(config)))
+            .install_batch(Tokio)
+            .with_context(|| format!("failed to export traces to {}", endpoint))?;
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer).boxed()))
+    }
+
+    /// Exports `counters` to --otlp-endpoint every EXPORT_INTERVAL, if it is set, named as on
+    /// /metrics under `soundflow.` and without the `_total` of counters.
+    pub fn export_metrics(config: &Config, counters: Arc<Counters>) {
+        let Some(endpoint) = &config.otlp_endpoint else {
+            return;
+        };
+        let built = opentelemetry_otlp::new_pipeline()
+            .metrics(Tokio)
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_resource(resource(config))
+            .with_period(EXPORT_INTERVAL)
+            .build();
+        let provider = match built {
+            Ok(provider) => provider,
+            Err(e) => return error!("failed to export metrics to {}: {}", endpoint, e),
+        };
+        let meter = provider.meter("sf_core");
+        for (i, (name, kind, help, _)) in metrics(&counters).into_iter().enumerate() {
+            let name = format!("soundflow.{}", name.trim_end_matches("_total"));
+            let counters = counters.clone();
+            let value = move || metrics(&counters).get(i).map(|&(_, _, _, value)| value);
+            if kind == "counter" {
+                meter.f64_observable_counter(name).with_description(help).with_callback(move |observer| {
+                    value().into_iter().for_each(|value| observer.observe(value, &[]));
+                }).init();
+            } else {
+                meter.f64_observable_gauge(name).with_description(help).with_callback(move |observer| {
+                    value().into_iter().for_each(|value| observer.observe(value, &[]));
+                }).init();
+            }
+        }
+        info!("exporting spans and metrics to {}", endpoint);
+        let _ = METERS.set(provider);
+    }
+
+    /// Exports what is still waiting, before the process exits.
+    pub fn shutdown() {
+        opentelemetry::global::shutdown_tracer_provider();
+        if let Some(Err(e)) = METERS.get().map(SdkMeterProvider::shutdown) {
+            error!("failed to export the last metrics: {}", e);
+        }
+    }
+}
+
+/// Without the `otel` feature, which leaves nothing to export with.
+#[cfg(not(feature = "otel"))]
+mod off {
+    use std::sync::Arc;
+
+    use tracing::warn;
+    use tracing_subscriber::{Layer, Registry};
+
+    use crate::config::Config;
+    use crate::stats::Counters;
+
+    pub fn layer(_: &Config) -> anyhow::Result<Option<Box<dyn Layer<Registry> + Send + Sync>>> {
+        Ok(None)
+    }
+
+    /// Warns that --otlp-endpoint is ignored.
+    pub fn export_metrics(config: &Config, _: Arc<Counters>) {
+        if let Some(endpoint) = &config.otlp_endpoint {
+            warn!("ignoring --otlp-endpoint {}, sf_core was built without the otel feature", endpoint);
+        }
+    }
+
+    pub fn shutdown() {}
+}
@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+/// Counts the frames and bytes going one way and logs their rate at info every interval, so a
+/// glance at the log shows whether audio flows and how fast, without a metrics stack. The counts
+/// start over with every line.
+pub struct Throughput {
+    what: &'static str, // how the log line names the frames, e.g. "captured"
+    interval: Option<Duration>, // None with --no-throughput-log
+    since: Instant, // start of the interval being counted
+    frames: u64,
+    bytes: u64,
+}
+
+impl Throughput {
+    pub fn new(what: &'static str, interval: Option<Duration>) -> Self {
+        Throughput { what, interval, since: Instant::now(), frames: 0, bytes: 0 }
+    }
+
+    /// Counts a frame of `bytes`.
+    pub fn add(&mut self, bytes: usize) {
+        self.frames += 1;
+        self.bytes += bytes as u64;
+    }
+
+    /// Logs the rates and starts over once the interval is up. Between frames nothing checks,
+    /// so a stream that stops sending logs its last interval with its next frame.
+    pub fn tick(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let elapsed = self.since.elapsed();
+        if elapsed < interval {
+            return;
+        }
+        let seconds = elapsed.as_secs_f64();
+        let frames_per_sec = (self.frames as f64 / seconds * 10.0).round() / 10.0;
+        let kbps = (self.bytes as f64 * 8.0 / 1000.0 / seconds).round();
+        info!(frames_per_sec, kbps, "frames {}", self.what);
+        *self = Throughput::new(self.what, self.interval);
+    }
+}
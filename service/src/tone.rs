@@ -0,0 +1,43 @@
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use crate::sound_flow::{AudioFormat, TestTone};
+
+pub const MIN_FREQUENCY_HZ: f32 = 20.0;
+pub const MAX_FREQUENCY_HZ: f32 = 20000.0;
+pub const MAX_DURATION: Duration = Duration::from_secs(10);
+const DEFAULT_AMPLITUDE: f32 = 0.5; // -6 dBFS, for a request that leaves it at 0
+const RAMP: Duration = Duration::from_millis(5); // faded in and out over, so it doesn't click
+
+/// Checks `tone` can be played in `format`, and returns its amplitude, defaulted if unset.
+pub fn validate(tone: &TestTone, format: &AudioFormat) -> Result<f32, String> {
+    let nyquist = format.sample_rate as f32 / 2.0;
+    if !(MIN_FREQUENCY_HZ..=MAX_FREQUENCY_HZ).contains(&tone.frequency_hz) {
+        return Err(format!("frequency must be between {} and {} Hz", MIN_FREQUENCY_HZ, MAX_FREQUENCY_HZ));
+    }
+    if tone.frequency_hz >= nyquist {
+        return Err(format!("frequency must be below {} Hz at the speaker's {} Hz", nyquist, format.sample_rate));
+    }
+    if tone.duration_ms == 0 || Duration::from_millis(tone.duration_ms.into()) > MAX_DURATION {
+        return Err(format!("duration must be between 1 and {} ms", MAX_DURATION.as_millis()));
+    }
+    match tone.amplitude {
+        0.0 => Ok(DEFAULT_AMPLITUDE),
+        amplitude if amplitude > 0.0 && amplitude <= 1.0 => Ok(amplitude),
+        _ => Err("amplitude must be between 0.0 and 1.0".to_string()),
+    }
+}
+
+/// `tone` as interleaved samples in `format`, the same on every channel.
+pub fn sine(tone: &TestTone, amplitude: f32, format: &AudioFormat) -> Vec<f32> {
+    let rate = format.sample_rate as f32;
+    let frames = (format.sample_rate as u64 * tone.duration_ms as u64 / 1000) as usize;
+    let ramp = ((RAMP.as_secs_f32() * rate) as usize).min(frames / 2).max(1);
+    (0..frames)
+        .flat_map(|frame| {
+            let envelope = (frame.min(frames - 1 - frame) as f32 / ramp as f32).min(1.0);
+            let sample = amplitude * envelope * (TAU * tone.frequency_hz * frame as f32 / rate).sin();
+            std::iter::repeat_n(sample, format.channels as usize)
+        })
+        .collect()
+}
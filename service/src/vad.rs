@@ -0,0 +1,53 @@
+use tracing::debug;
+
+use crate::dsp::Processor;
+use crate::sound_flow::AudioFormat;
+
+/// Voice activity detection by RMS energy: frames louder than the threshold are speech, and the
+/// hangover keeps frames flowing for a while after speech so word endings aren't clipped.
+pub struct Vad {
+    threshold: f32, // linear RMS
+    hangover: usize, // interleaved samples to keep sending after the last loud frame
+    remaining: usize,
+    active: bool,
+}
+
+impl Vad {
+    pub fn new(threshold_db: f32, hangover_ms: u64, format: &AudioFormat) -> Self {
+        let samples_per_ms = format.sample_rate as u64 * format.channels as u64 / 1000;
+        Vad {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            hangover: (hangover_ms * samples_per_ms) as usize,
+            remaining: 0,
+            active: false,
+        }
+    }
+
+    /// Whether `frame` should be sent.
+    pub fn is_active(&mut self, frame: &[f32]) -> bool {
+        let rms = (frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len().max(1) as f32).sqrt();
+        let active = if rms >= self.threshold {
+            self.remaining = self.hangover;
+            true
+        } else if self.remaining > 0 {
+            self.remaining = self.remaining.saturating_sub(frame.len());
+            true
+        } else {
+            false
+        };
+        if active != self.active {
+            self.active = active;
+            debug!(active, "voice activity changed");
+        }
+        active
+    }
+}
+
+/// Empties the frames that shouldn't be sent, which drops them.
+impl Processor for Vad {
+    fn process(&mut self, frame: &mut Vec<f32>, _format: &AudioFormat) {
+        if !self.is_active(frame) {
+            frame.clear();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::sync::broadcast::Sender;
+use tokio::sync::{mpsc, watch};
+use tonic::Status;
+
+use crate::config::Config;
+use crate::devices::{DeviceController, OutputDevice};
+use crate::dsp::{Chain, FadeIn, GainProcessor, Processor};
+use crate::listen::Bound;
+use crate::playback::{PlaybackDrain, PlaybackRing};
+use crate::sound_flow::{AudioFormat, Device, DeviceDirection, DeviceId, Flow};
+use crate::stats::Counters;
+use crate::tasks::Tasks;
+use crate::volume::Gain;
+use crate::recording::Recording;
+use crate::{capture_in_use, latency, router, set_health, AudioCommand, AudioSide, SoundFlowService};
+
+const NAME: &str = "Virtual loopback";
+const SECOND_SINK: &str = "Virtual second sink"; // a playback device besides NAME, which plays nothing
+const SECOND_SINK_KEY: &str = "virtual:1"; // how the audio side opens SECOND_SINK, unlike GetDevices names it
+
+/// Serves `config` on `listener`, a TCP listener or a socket from `bind_socket`, like `run` does, but on a virtual device in `format` instead of
+/// the sound card: every package the speaker would play is captured right back, so get_flow
+/// hears what send_flow sent. Needs no audio hardware, which is what the integration tests run
+/// against. Serves until the future is dropped.
+pub async fn serve_loopback(config: Config, format: AudioFormat, listener: impl Into<Bound>) -> anyhow::Result<()> {
+    let config = Arc::new(config);
+    let (mut health, health_service) = tonic_health::server::health_reporter();
+    let counters = Arc::new(Counters::default());
+    let (ring, drain) = PlaybackRing::new(config.ring_capacity);
+    let volume = Gain::new(1.0);
+    let capture_muted = Arc::new(AtomicBool::new(false));
+    let playback_muted = Arc::new(AtomicBool::new(false));
+    let tasks = Tasks::default();
+    let _stop = tasks.cancel_on_drop(); // stops them with the future
+    let (audio, mut commands) = mpsc::channel(8);
+    tasks.spawn("commands", async move {
+        while let Some(command) = commands.recv().await {
+            match command {
+                AudioCommand::ReopenInput(reply) => { let _ = reply.send(Ok(())); } // the virtual capture never needs reopening
+                // The copies have nowhere to go, so one that opens is just dropped.
+                AudioCommand::SetOutputs(outputs, reply) => {
+                    let unknown = outputs.into_iter().find(|output| *output != OutputDevice::Host(SECOND_SINK_KEY.to_string()));
+                    let _ = reply.send(unknown.map_or(Ok(()), |output| Err(anyhow::anyhow!("the virtual device has no output device {}", output))));
+                }
+            }
+        }
+    });
+    let (_device_changes, devices) = watch::channel(Virtual.list(DeviceDirection::All)?);
+    let side = AudioSide {
+        counters: counters.clone(),
+        playback_ring: Arc::new(Mutex::new(ring)),
+        capture_format: Arc::new(Mutex::new(format.clone())),
+        playback_format: Arc::new(Mutex::new(format.clone())),
+        capture_open: Arc::new(AtomicBool::new(true)),
+        playback_open: Arc::new(AtomicBool::new(true)),
+        commands: audio,
+        controller: Arc::new(Virtual),
+        devices,
+        volume: volume.clone(),
+        capture_muted: capture_muted.clone(),
+        playback_muted: playback_muted.clone(),
+    };
+    let service = SoundFlowService::new(config.clone(), side, &tasks, false); // the router below gets no TLS setup
+    let (capture, recording) = (service.consumer.clone(), service.recording.clone());
+    let device = Loopback { config: config.clone(), format, drain, capture, counters, volume, capture_muted, playback_muted, recording };
+    set_health(&mut health, true).await;
+    let serving = listener.into().serve(router(&config, service, health_service, None)?, std::future::pending());
+    tokio::select! {
+        served = serving => Ok(served?),
+        () = device.run() => Ok(()),
+    }
+}
+
+/// The virtual device's audio side, standing in for both cpal callbacks.
+struct Loopback {
+    config: Arc<Config>,
+    format: AudioFormat,
+    drain: PlaybackDrain,
+    capture: Sender<Result<Flow, ()>>,
+    counters: Arc<Counters>,
+    volume: Gain,
+    capture_muted: Arc<AtomicBool>,
+    playback_muted: Arc<AtomicBool>,
+    recording: Arc<Mutex<Option<Recording>>>, // whether one is in progress, for --capture-idle-ms
+}
+
+impl Loopback {
+    /// Every package period, mixes what was pushed to the ring like the output callback does and
+    /// broadcasts the result like the input callback would, silence included, unless
+    /// --capture-idle-ms paused the capture. Never returns.
+    async fn run(mut self) {
+        let channels = (self.format.channels as usize).max(1);
+        let package_size = self.config.package_samples(channels);
+        let period = Duration::from_secs_f64((package_size / channels) as f64 / self.format.sample_rate as f64);
+        let mut mixer = self.config.mixer(&self.format);
+        // The speaker's fade-in, volume and mute, then the microphone's mute.
+        let mut processing = Chain::default();
+        processing.push(FadeIn::new(self.config.fade()));
+        processing.push(GainProcessor::new(self.volume.clone(), self.playback_muted.clone()));
+        processing.push(GainProcessor::new(Gain::new(1.0), self.capture_muted.clone()));
+        let mut capture_idle = self.config.capture_idle();
+        let mut ticks = tokio::time::interval(period);
+        loop {
+            // The slot it was due in rather than the time now, so a tick that comes late plays on time.
+            let now = ticks.tick().await.into_std();
+            let fill = self.drain.drain(|packet| mixer.push(packet));
+            self.counters.playback_ring_fill.store(fill, Ordering::Relaxed);
+            self.counters.playback_lane_fill.store(self.drain.fullest(), Ordering::Relaxed);
+            let mut samples = mixer.pop(package_size, &(now..now + period)).unwrap_or_else(|| vec![0.0; package_size]);
+            mixer.take_captured();
+            self.counters.output_underruns.store(mixer.underruns(), Ordering::Relaxed);
+            self.counters.frames_late.store(mixer.late(), Ordering::Relaxed);
+            self.counters.jitter_depth.store(mixer.depth(), Ordering::Relaxed);
+            self.counters.clock_drift_ppb.store((mixer.drift_ppm() * 1000.0) as i64, Ordering::Relaxed);
+            self.counters.drift_corrections.store(mixer.drift_corrections(), Ordering::Relaxed);
+            processing.process(&mut samples, &self.format);
+            let paused = capture_idle.as_mut().is_some_and(|idle| idle.paused(capture_in_use(&self.counters, &self.recording), now));
+            self.counters.capture_paused.store(paused, Ordering::Relaxed);
+            if paused {
+                continue;
+            }
+            let _ = self.capture.send(Ok(Flow { flow: samples, captured_ns: latency::to_ns(now), ..Default::default() }));
+        }
+    }
+}
+
+/// The one playback and one capture device of the virtual device, always the defaults, and a
+/// second playback device to play copies on.
+struct Virtual;
+
+impl Virtual {
+    fn device(direction: DeviceDirection) -> Device {
+        Device { id: 0, name: NAME.to_string(), direction: direction.into(), monitor: false }
+    }
+
+    fn second_sink() -> Device {
+        Device { id: 1, name: SECOND_SINK.to_string(), direction: DeviceDirection::Playback.into(), monitor: false }
+    }
+}
+
+impl DeviceController for Virtual {
+    fn list(&self, direction: DeviceDirection) -> Result<Vec<Device>, Status> {
+        let mut devices = Vec::new();
+        if direction != DeviceDirection::Capture {
+            devices.extend([Virtual::device(DeviceDirection::Playback), Virtual::second_sink()]);
+        }
+        if direction != DeviceDirection::Playback {
+            devices.push(Virtual::device(DeviceDirection::Capture));
+        }
+        Ok(devices)
+    }
+
+    fn set_default(&self, device: &DeviceId) -> Result<(), Status> {
+        match device.id {
+            0 => Ok(()),
+            1 if device.direction() != DeviceDirection::Capture => Err(Status::failed_precondition("the second sink only plays copies")),
+            id => Err(Status::not_found(format!("no device {}, the virtual device is the only one", id))),
+        }
+    }
+
+    fn current(&self, direction: DeviceDirection) -> Result<Device, Status> {
+        Ok(Virtual::device(if direction == DeviceDirection::Capture { direction } else { DeviceDirection::Playback }))
+    }
+
+    fn output(&self, id: u32) -> Result<OutputDevice, Status> {
+        match id {
+            0 => Ok(OutputDevice::Host(NAME.to_string())),
+            1 => Ok(OutputDevice::Host(SECOND_SINK_KEY.to_string())),
+            id => Err(Status::not_found(format!("no playback device {}", id))),
+        }
+    }
+}
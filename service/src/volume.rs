@@ -0,0 +1,46 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const RAMP_FRAMES: f32 = 480.0; // frames a full 0 to 1 change takes, 10 ms at 48 kHz, so steps don't click
+
+/// A linear gain shared lock-free between the RPC handlers and the output callback.
+#[derive(Clone)]
+pub struct Gain(Arc<AtomicU32>); // the f32 bits
+
+impl Gain {
+    pub fn new(gain: f32) -> Self {
+        Gain(Arc::new(AtomicU32::new(gain.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Applies a gain to interleaved samples, ramping towards a new gain instead of jumping to it so
+/// changes don't cause zipper noise.
+pub struct Smoother {
+    current: f32,
+    channels: usize,
+}
+
+impl Smoother {
+    pub fn new(gain: f32, channels: usize) -> Self {
+        Smoother { current: gain, channels }
+    }
+
+    pub fn apply(&mut self, target: f32, samples: &mut [f32]) {
+        if self.current == target && target == 1.0 {
+            return;
+        }
+        for frame in samples.chunks_mut(self.channels) {
+            let step = (target - self.current).clamp(-1.0 / RAMP_FRAMES, 1.0 / RAMP_FRAMES);
+            self.current += step;
+            frame.iter_mut().for_each(|sample| *sample *= self.current);
+        }
+    }
+}
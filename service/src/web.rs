@@ -0,0 +1,81 @@
+#[cfg(feature = "web")]
+pub use self::on::{enable, Layers};
+#[cfg(not(feature = "web"))]
+pub use self::off::{enable, Layers};
+
+#[cfg(feature = "web")]
+mod on {
+    use std::time::Duration;
+
+    use anyhow::Context;
+    use tonic::codegen::http::{HeaderName, HeaderValue, Method};
+    use tonic::transport::Server;
+    use tonic_web::GrpcWebLayer;
+    use tower::layer::util::{Identity, Stack};
+    use tower::util::{option_layer, Either};
+    use tower_http::cors::{AllowOrigin, CorsLayer};
+    use tracing::{info, warn};
+
+    use crate::auth::AUTHORIZATION;
+    use crate::config::Config;
+    use crate::rooms::ROOM_HEADER;
+    use crate::switch::LISTENER_HEADER;
+    use crate::{CHANNELS_HEADER, SAMPLE_RATE_HEADER};
+
+    const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60); // how long browsers may cache a preflight
+    // What grpc-web clients send, and the metadata SoundFlow calls read.
+    const ALLOW_HEADERS: [&str; 6] = ["x-grpc-web", "content-type", "x-user-agent", "grpc-timeout", AUTHORIZATION, ROOM_HEADER];
+    // The trailers grpc-web clients read the status from, and the metadata SoundFlow answers with.
+    const EXPOSE_HEADERS: [&str; 6] = ["grpc-status", "grpc-message", "grpc-status-details-bin", SAMPLE_RATE_HEADER, CHANNELS_HEADER, LISTENER_HEADER];
+
+    /// grpc-web translation behind CORS for the --web-origin origins, or nothing without any, on
+    /// top of the builder's own nothing.
+    pub type Layers = Stack<Either<Stack<GrpcWebLayer, CorsLayer>, Identity>, Identity>;
+
+    /// Lets browsers on the --web-origin origins call the server over grpc-web, which they send
+    /// as HTTP/1.1 without TLS. Other requests go through untouched.
+    pub fn enable(config: &Config, builder: Server) -> anyhow::Result<Server<Layers>> {
+        if config.web_origin.is_empty() {
+            return Ok(builder.layer(option_layer(None)));
+        }
+        let origin = if config.web_origin.iter().any(|origin| origin == "*") {
+            if config.auth_token.is_none() {
+                warn!("--web-origin * without --auth-token lets any web page a browser here opens use the microphone and speaker");
+            }
+            AllowOrigin::any()
+        } else {
+            let origins = config.web_origin.iter()
+                .map(|origin| HeaderValue::from_str(origin).with_context(|| format!("--web-origin {} isn't a valid origin", origin)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            AllowOrigin::list(origins)
+        };
+        let cors = CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::POST])
+            .allow_headers(ALLOW_HEADERS.map(HeaderName::from_static))
+            .expose_headers(EXPOSE_HEADERS.map(HeaderName::from_static))
+            .max_age(MAX_AGE);
+        info!(origins = ?config.web_origin, "serving grpc-web");
+        Ok(builder.accept_http1(true).layer(option_layer(Some(Stack::new(GrpcWebLayer::new(), cors)))))
+    }
+}
+
+/// Without the `web` feature, which leaves nothing to translate grpc-web with.
+#[cfg(not(feature = "web"))]
+mod off {
+    use tonic::transport::Server;
+    use tower::layer::util::Identity;
+    use tracing::warn;
+
+    use crate::config::Config;
+
+    pub type Layers = Identity;
+
+    /// Warns that --web-origin is ignored.
+    pub fn enable(config: &Config, builder: Server) -> anyhow::Result<Server<Layers>> {
+        if !config.web_origin.is_empty() {
+            warn!("ignoring --web-origin, sf_core was built without the web feature");
+        }
+        Ok(builder)
+    }
+}
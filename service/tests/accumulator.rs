@@ -0,0 +1,74 @@
+//! Feeds numbered packages through the output callback's Accumulator with buffer sizes that
+//! don't divide the package size, and checks they come out whole and in order.
+
+use sf_core::Accumulator;
+
+const PACKAGE_SIZE: usize = 1000;
+
+/// Fills buffers of `sizes` in turn from packages numbering their samples on from 0, and returns
+/// what was played along with how far ahead each package was asked for.
+fn play(sizes: &[usize], packages: usize) -> (Vec<f32>, Vec<usize>) {
+    let mut accumulator = Accumulator::new(PACKAGE_SIZE);
+    let mut made = 0;
+    let mut asked = Vec::new();
+    let mut played = Vec::new();
+    for &size in sizes {
+        let mut data = vec![f32::NAN; size];
+        accumulator.fill(&mut data, |ahead| {
+            asked.push(ahead);
+            if made == packages {
+                return Vec::new();
+            }
+            made += 1;
+            ((made - 1) * PACKAGE_SIZE..made * PACKAGE_SIZE).map(|i| i as f32).collect()
+        });
+        played.extend(data);
+    }
+    (played, asked)
+}
+
+#[test]
+fn packages_play_out_whole_across_callbacks() {
+    let sizes = [768, 1536, 300, 2000, 1396];
+    let (played, _) = play(&sizes, 6);
+    let expected: Vec<f32> = (0..6 * PACKAGE_SIZE).map(|i| i as f32).collect();
+    assert_eq!(played, expected, "samples were cut off, repeated or left stale");
+}
+
+#[test]
+fn packages_are_asked_for_with_what_plays_before_them() {
+    let (_, asked) = play(&[768, 768], 2);
+    // The first callback takes one package and leaves 232 samples of it for the second.
+    assert_eq!(asked, [0, 232]);
+}
+
+#[test]
+fn only_the_shortfall_is_zeroed() {
+    let (played, _) = play(&[1500, 1000], 2);
+    let expected: Vec<f32> = (0..2 * PACKAGE_SIZE).map(|i| i as f32).chain([0.0; 500]).collect();
+    assert_eq!(played, expected);
+}
+
+#[test]
+fn packages_shorter_and_longer_than_the_buffer_play_back_to_back() {
+    // A drift correction or a resampler can hand over a few samples more or less than asked for.
+    let lengths = [10, 2500, 999, 1, 1001];
+    let mut accumulator = Accumulator::new(PACKAGE_SIZE);
+    let (mut made, mut next) = (0, 0);
+    let mut played = Vec::new();
+    for _ in 0..6 {
+        let mut data = vec![f32::NAN; PACKAGE_SIZE];
+        accumulator.fill(&mut data, |_| {
+            let Some(&len) = lengths.get(made) else {
+                return Vec::new();
+            };
+            made += 1;
+            next += len;
+            (next - len..next).map(|i| i as f32).collect()
+        });
+        played.extend(data);
+    }
+    let total: usize = lengths.iter().sum();
+    let expected: Vec<f32> = (0..total).map(|i| i as f32).chain(vec![0.0; 6 * PACKAGE_SIZE - total]).collect();
+    assert_eq!(played, expected, "samples were cut off, repeated or left stale");
+}
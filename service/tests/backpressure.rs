@@ -0,0 +1,39 @@
+//! A listener that stops reading for a while: the server drops frames for it rather than wait,
+//! and the gaps show up in its seq numbers and in GetStats.
+
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Endpoint;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{Codec, FlowRequest, SampleFormat};
+
+use common::{serve, TIMEOUT};
+
+mod common;
+
+const STALL: Duration = Duration::from_secs(1); // about 96 frames of capture at the defaults
+const READ: usize = 200; // frames read after the stall, well past what the link buffered
+const WINDOW: u32 = 65_535; // the HTTP/2 minimum, so the client buffers a few frames rather than megabytes
+
+#[tokio::test]
+async fn a_stalled_listener_sees_the_gap() {
+    let url = serve(&["--listener-queue", "4", "--broadcast-capacity", "8"]).await;
+    let channel = Endpoint::from_shared(url).unwrap().initial_stream_window_size(WINDOW).connect().await.unwrap();
+    let mut client = SoundFlowClient::new(channel);
+    let request = FlowRequest { codec: Codec::Raw.into(), sample_format: SampleFormat::F32.into() };
+    let mut flows = client.get_flow(request).await.unwrap().into_inner();
+    tokio::time::sleep(STALL).await;
+    let mut seqs = Vec::new();
+    while seqs.len() < READ {
+        let flow = tokio::time::timeout(TIMEOUT, flows.next()).await.expect("timed out waiting for frames");
+        seqs.push(flow.unwrap().unwrap().seq);
+    }
+    drop(flows);
+    assert!(seqs.windows(2).all(|pair| pair[1] > pair[0]), "seq went backwards: {:?}", seqs);
+    let skipped: u64 = seqs.windows(2).map(|pair| pair[1] - pair[0] - 1).sum::<u64>() + seqs[0] - 1;
+    assert!(skipped > 0, "no frames were dropped while the listener stalled");
+    let stats = client.get_stats(()).await.unwrap().into_inner();
+    assert!(stats.listener_drops >= skipped, "{} frames skipped but only {} counted", skipped, stats.listener_drops);
+}
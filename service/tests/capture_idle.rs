@@ -0,0 +1,96 @@
+//! When --capture-idle-ms turns the microphone off: only after nothing has used the capture for
+//! the whole timeout, back on with the first user, and monitors and meters counted as users, but
+//! not listeners of a room.
+
+use std::time::{Duration, Instant};
+
+use tonic::transport::Channel;
+use tonic::Request;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::FlowRequest;
+use sf_core::CaptureIdle;
+
+use common::connect;
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[test]
+fn pauses_only_after_the_whole_timeout_unused() {
+    let mut idle = CaptureIdle::new(TIMEOUT);
+    let start = Instant::now();
+    assert!(!idle.paused(false, start));
+    assert!(!idle.paused(false, start + TIMEOUT / 2));
+    assert!(idle.paused(false, start + TIMEOUT));
+    assert!(idle.paused(false, start + 10 * TIMEOUT));
+}
+
+#[test]
+fn a_user_resumes_it_and_starts_the_timeout_over() {
+    let mut idle = CaptureIdle::new(TIMEOUT);
+    let start = Instant::now();
+    idle.paused(false, start);
+    assert!(idle.paused(false, start + TIMEOUT));
+    assert!(!idle.paused(true, start + 2 * TIMEOUT));
+    // The listener left again, the microphone stays on for another timeout.
+    assert!(!idle.paused(false, start + 3 * TIMEOUT));
+    assert!(!idle.paused(false, start + 3 * TIMEOUT + TIMEOUT / 2));
+    assert!(idle.paused(false, start + 4 * TIMEOUT));
+}
+
+#[test]
+fn never_pauses_while_in_use() {
+    let mut idle = CaptureIdle::new(TIMEOUT);
+    let start = Instant::now();
+    for step in 0..10 {
+        assert!(!idle.paused(true, start + step * TIMEOUT));
+    }
+}
+
+#[tokio::test]
+async fn open_monitors_are_counted() {
+    let mut client = connect(&[]).await;
+    let monitor = client.monitor(()).await.unwrap();
+    assert_eq!(client.get_stats(()).await.unwrap().into_inner().monitors, 1);
+    drop(monitor);
+    let left = tokio::time::timeout(common::TIMEOUT, async {
+        while client.get_stats(()).await.unwrap().into_inner().monitors > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await;
+    assert!(left.is_ok(), "the monitor is still counted after it left");
+}
+
+/// Waits for the virtual microphone to be `paused`, and whether it got there in time.
+async fn becomes_paused(client: &mut SoundFlowClient<Channel>, paused: bool) -> bool {
+    tokio::time::timeout(common::TIMEOUT, async {
+        while client.get_stats(()).await.unwrap().into_inner().capture_paused != paused {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.is_ok()
+}
+
+#[tokio::test]
+async fn an_open_meter_keeps_the_microphone_on() {
+    let mut client = connect(&["--capture-idle-ms", "100"]).await;
+    assert!(becomes_paused(&mut client, true).await, "paused with nothing using the capture");
+    let meter = client.meter(()).await.unwrap();
+    assert!(becomes_paused(&mut client, false).await, "a meter didn't resume the microphone");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!client.get_stats(()).await.unwrap().into_inner().capture_paused, "paused under an open meter");
+    drop(meter);
+    assert!(becomes_paused(&mut client, true).await, "still on after the meter left");
+}
+
+#[tokio::test]
+async fn room_listeners_leave_the_microphone_paused() {
+    let mut client = connect(&["--capture-idle-ms", "100"]).await;
+    let mut request = Request::new(FlowRequest::default());
+    request.metadata_mut().insert("sf-room", "standup".parse().unwrap());
+    let _listener = client.get_flow(request).await.unwrap();
+    assert!(becomes_paused(&mut client, true).await, "a room's listener kept the microphone on");
+    let _listener = client.get_flow(FlowRequest::default()).await.unwrap();
+    assert!(becomes_paused(&mut client, false).await, "the device's listener didn't resume the microphone");
+}
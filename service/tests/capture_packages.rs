@@ -0,0 +1,54 @@
+//! Pushes multi-channel ramps through the microphone's packaging, callback by callback, at
+//! package sizes that aren't a whole number of frames, and checks that every sample comes out
+//! once, in order, with no package splitting a frame.
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use sf_core::{capture_packages, Config};
+
+const FRAMES: usize = 10_000;
+
+/// Every sample its own value, frame after frame and channel after channel, so a lost,
+/// repeated or swapped one shows.
+fn ramp(channels: usize) -> Vec<f32> {
+    (0..FRAMES * channels).map(|n| n as f32).collect()
+}
+
+/// What `callback_frames` at a time of `ramp(channels)` become with --package-size `size`.
+fn packaged(channels: usize, size: usize, callback_frames: usize) -> Vec<Vec<f32>> {
+    let config = Config::parse_from(["sf_core", "--package-size", &size.to_string()]);
+    let package_size = config.package_samples(channels);
+    let at = Instant::now();
+    ramp(channels).chunks(callback_frames * channels)
+        .flat_map(|callback| capture_packages(callback, package_size, at, Duration::ZERO).map(|(package, _)| package.to_vec()).collect::<Vec<_>>())
+        .collect()
+}
+
+#[test]
+fn no_sample_is_lost_or_reordered() {
+    for (channels, size, callback_frames) in [(2, 999, 441), (2, 1000, 512), (3, 1000, 480), (6, 1001, 1024), (1, 7, 100)] {
+        let packages = packaged(channels, size, callback_frames);
+        let case = format!("{} channels, --package-size {}, {} frame callbacks", channels, size, callback_frames);
+        assert!(packages.iter().all(|package| !package.is_empty() && package.len() % channels == 0), "a frame was split: {}", case);
+        assert_eq!(packages.concat(), ramp(channels), "{}", case);
+    }
+}
+
+#[test]
+fn packages_are_the_rounded_down_size_but_a_callbacks_last() {
+    // 999 samples of stereo round down to 499 frames: a 1024 frame callback fills two of those
+    // and leaves 26 frames over.
+    let packages = packaged(2, 999, 1024);
+    let sizes: Vec<_> = packages.iter().take(3).map(Vec::len).collect();
+    assert_eq!(sizes, [998, 998, 52]);
+}
+
+#[test]
+fn packages_are_timed_one_after_the_other() {
+    let at = Instant::now();
+    let period = Duration::from_millis(10);
+    let times: Vec<_> = capture_packages(&ramp(2)[..2500], 1000, at, period).map(|(_, at)| at).collect();
+    assert_eq!(times, [at, at + period, at + 2 * period]);
+}
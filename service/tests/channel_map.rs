@@ -0,0 +1,74 @@
+//! --channel-map: how maps are written, what they do to frames, and a sender whose channels
+//! don't fit the map being turned away.
+
+use tokio_stream::StreamExt;
+use tonic::Code;
+
+use sf_core::sound_flow::{AudioFormat, Codec, SampleFormat};
+use sf_core::ChannelMap;
+
+use common::{connect, format, send, CHANNELS, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+
+fn map(map: &str) -> ChannelMap {
+    map.parse().unwrap()
+}
+
+#[test]
+fn left_and_right_swap() {
+    let swap = map("0,1;1,0");
+    assert_eq!((swap.inputs(), swap.outputs()), (2, 2));
+    assert_eq!(swap.apply(&[0.1, 0.2, 0.3, 0.4]), [0.2, 0.1, 0.4, 0.3]);
+}
+
+#[test]
+fn mono_goes_to_the_left_only() {
+    let left = map("1;0");
+    assert_eq!((left.inputs(), left.outputs()), (1, 2));
+    assert_eq!(left.apply(&[0.5, -0.25]), [0.5, 0.0, -0.25, 0.0]);
+}
+
+#[test]
+fn weights_mix_the_inputs() {
+    let mixed = map("0.5, 0.5").apply(&[0.2, 0.4]);
+    assert_eq!(mixed.len(), 1);
+    assert!((mixed[0] - 0.3).abs() < 1e-6, "{}", mixed[0]);
+}
+
+#[test]
+fn malformed_maps_are_refused() {
+    for malformed in ["", "1,", "1,0;1", "a;b", "inf;0"] {
+        assert!(malformed.parse::<ChannelMap>().is_err(), "{:?} parsed", malformed);
+    }
+}
+
+#[tokio::test]
+async fn the_speaker_plays_the_channels_swapped() {
+    let mut client = connect(&["--channel-map", "0,1;1,0"]).await;
+    let mut monitor = client.monitor(()).await.unwrap().into_inner();
+    let frames: Vec<f32> = [0.25, -0.5].repeat(25 * PACKAGE_SIZE / CHANNELS); // left 0.25, right -0.5
+    tokio::spawn(send(client.clone(), frames, PACKAGE_SIZE));
+    let played = tokio::time::timeout(TIMEOUT, async {
+        while let Some(frame) = monitor.next().await {
+            let frame = frame.unwrap().flow;
+            if frame.iter().all(|&sample| sample != 0.0) {
+                return frame;
+            }
+        }
+        panic!("the monitor ended before the frames were played");
+    }).await.unwrap();
+    assert_eq!(&played[..4], [-0.5, 0.25, -0.5, 0.25]);
+}
+
+#[tokio::test]
+async fn a_sender_the_map_doesnt_fit_is_refused() {
+    let mut client = connect(&["--channel-map", "0,1;1,0"]).await;
+    let mono = AudioFormat { channels: 1, codec: Codec::Raw.into(), sample_format: SampleFormat::F32.into(), ..format() };
+    let status = client.negotiate_format(mono).await.expect_err("a mono sender was accepted");
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status.message().contains("--channel-map"), "{}", status.message());
+    client.negotiate_format(format()).await.unwrap();
+}
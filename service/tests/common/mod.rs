@@ -0,0 +1,67 @@
+//! A server on the virtual loopback device and a sender for it, shared by the integration tests.
+
+#![allow(dead_code)] // every test binary includes this but none uses all of it
+
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use tonic::transport::Channel;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{AudioFormat, Codec, Flow, SampleFormat};
+use sf_core::Config;
+
+pub const SAMPLE_RATE: u32 = 48000;
+pub const CHANNELS: usize = 2;
+pub const TIMEOUT: Duration = Duration::from_secs(10); // longest a test waits for audio to come back
+
+pub fn format() -> AudioFormat {
+    AudioFormat {
+        sample_rate: SAMPLE_RATE,
+        channels: CHANNELS as u32,
+        sample_format: SampleFormat::F32.into(),
+        codec: Codec::Raw.into(),
+    }
+}
+
+/// Serves on the virtual loopback device on an ephemeral port, with `args` on top of the
+/// defaults, and returns its URL.
+pub async fn serve(args: &[&str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // A deep jitter buffer, so a test runner busy with other tests doesn't cause underruns.
+    let defaults = ["sf_core", "--plaintext", "--no-vad", "--jitter-depth", "8"];
+    // No fades unless asked for, so what comes back can be compared with what was sent.
+    let fades = if args.contains(&"--fade-ms") { &[][..] } else { &["--fade-ms", "0"][..] };
+    let config = Config::parse_from(defaults.iter().chain(fades).chain(args));
+    tokio::spawn(async move { sf_core::serve_loopback(config, format(), listener).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// Like `serve`, and connects to it.
+pub async fn connect(args: &[&str]) -> SoundFlowClient<Channel> {
+    SoundFlowClient::connect(serve(args).await).await.unwrap()
+}
+
+/// Sends `samples` in packages of `package_size` in real time, one per package period, as a
+/// sender would.
+pub async fn send(client: SoundFlowClient<Channel>, samples: Vec<f32>, package_size: usize) {
+    let flows: Vec<_> = (1..).zip(samples.chunks(package_size))
+        .map(|(seq, samples)| Flow { flow: samples.to_vec(), seq, ..Default::default() })
+        .collect();
+    send_flows(client, flows, package_size).await;
+}
+
+/// Sends `flows` of `package_size` samples each in real time, one per package period.
+pub async fn send_flows(mut client: SoundFlowClient<Channel>, flows: Vec<Flow>, package_size: usize) {
+    let period = Duration::from_secs_f64((package_size / CHANNELS) as f64 / SAMPLE_RATE as f64);
+    let paced = async_stream::stream! {
+        let mut ticks = tokio::time::interval(period); // keeps to the schedule, where a throttle would drift
+        for flow in flows {
+            ticks.tick().await;
+            yield flow;
+        }
+    };
+    client.send_flow(paced).await.unwrap();
+}
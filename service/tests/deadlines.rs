@@ -0,0 +1,69 @@
+//! Deadlines on the device calls: how long a call gets from --rpc-timeout-ms and the client's
+//! grpc-timeout, and that a call stuck in the audio system fails in time without holding a worker.
+
+#![allow(clippy::result_large_err)] // the calls return tonic::Status, like the handlers
+
+use std::time::{Duration, Instant};
+
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request};
+
+use sf_core::sound_flow::Direction;
+use sf_core::{call_deadline, run_blocking};
+
+use common::connect;
+
+mod common;
+
+const DEFAULT: Duration = Duration::from_secs(5);
+
+fn requested(timeout: &str) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    metadata.insert("grpc-timeout", timeout.parse().unwrap());
+    metadata
+}
+
+#[test]
+fn the_sooner_of_the_two_deadlines_wins() {
+    assert_eq!(call_deadline(&MetadataMap::new(), DEFAULT), DEFAULT);
+    assert_eq!(call_deadline(&requested("100m"), DEFAULT), Duration::from_millis(100));
+    assert_eq!(call_deadline(&requested("250000u"), DEFAULT), Duration::from_millis(250));
+    assert_eq!(call_deadline(&requested("1M"), DEFAULT), DEFAULT);
+}
+
+#[test]
+fn a_malformed_grpc_timeout_is_ignored() {
+    for timeout in ["", "m", "100", "100x", "-1S", "123456789m"] {
+        assert_eq!(call_deadline(&requested(timeout), DEFAULT), DEFAULT, "{:?}", timeout);
+    }
+}
+
+// On a single worker thread, so a call that blocked it would hold up the timer as well.
+#[tokio::test(flavor = "current_thread")]
+async fn a_stuck_call_fails_with_deadline_exceeded() {
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(100);
+    let started = Instant::now();
+    let stuck = run_blocking(deadline, "setting the default device", || {
+        std::thread::sleep(Duration::from_secs(2));
+        Ok(())
+    }).await;
+    let status = stuck.expect_err("a stuck call succeeded");
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+    assert!(status.message().contains("setting the default device"), "{}", status.message());
+    assert!(started.elapsed() < Duration::from_secs(1), "gave up after {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn a_prompt_call_answers() {
+    let deadline = tokio::time::Instant::now() + DEFAULT;
+    assert_eq!(run_blocking(deadline, "listing the devices", || Ok(3)).await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn device_calls_answer_within_a_client_deadline() {
+    let mut client = connect(&["--rpc-timeout-ms", "1000"]).await;
+    let mut request = Request::new(Direction::default());
+    request.set_timeout(Duration::from_millis(500));
+    let devices = client.get_devices(request).await.unwrap().into_inner();
+    assert!(!devices.devices.is_empty());
+}
@@ -0,0 +1,97 @@
+//! Runs the noise suppressor over synthetic capture: steady noise should go, a voiced sound
+//! should stay, and what comes out should lag by the delay it reports.
+
+use std::f32::consts::TAU;
+
+use sf_core::NoiseSuppressor;
+
+const SAMPLE_RATE: usize = 48000;
+const CALLBACK: usize = 441; // frames per input callback, not a multiple of RNNoise's 480
+const VOICE_HZ: [f32; 4] = [150.0, 300.0, 450.0, 600.0]; // a pitch and its harmonics
+
+/// Uniform noise in [-level / 2, level / 2), the same every run.
+fn noise(len: usize, level: f32) -> Vec<f32> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            level * ((state >> 8) as f32 / (1 << 24) as f32 - 0.5)
+        })
+        .collect()
+}
+
+/// Something like a sung vowel, swelling four times a second.
+fn voice(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let swell = 0.5 * (1.0 + (TAU * 4.0 * t).sin());
+            0.05 * swell * VOICE_HZ.iter().map(|hz| (TAU * hz * t).sin()).sum::<f32>()
+        })
+        .collect()
+}
+
+fn suppress(samples: &[f32], channels: usize) -> Vec<f32> {
+    let mut suppressor = NoiseSuppressor::new(channels);
+    let mut out = samples.to_vec();
+    out.chunks_mut(CALLBACK * channels).for_each(|callback| suppressor.process(callback));
+    out
+}
+
+fn energy(samples: &[f32]) -> f32 {
+    samples.iter().map(|x| x * x).sum()
+}
+
+fn delay_frames() -> usize {
+    (NoiseSuppressor::delay().as_secs_f64() * SAMPLE_RATE as f64).round() as usize
+}
+
+/// Bursts of voice every 100 ms with steady noise throughout, and whether each sample is in a gap.
+fn speech_in_noise(len: usize) -> (Vec<f32>, Vec<bool>) {
+    let gap = |i: usize| (i / 4800) % 2 == 1;
+    let samples = voice(len).iter().zip(noise(len, 0.01)).enumerate()
+        .map(|(i, (voice, noise))| if gap(i) { noise } else { voice + noise })
+        .collect();
+    (samples, (0..len).map(gap).collect())
+}
+
+#[test]
+fn noise_between_words_is_suppressed() {
+    let len = 3 * SAMPLE_RATE;
+    let (noisy, gaps) = speech_in_noise(len);
+    let out = suppress(&noisy, 1);
+    assert_eq!(out.len(), noisy.len());
+    let delay = delay_frames();
+    // Away from the edges of the bursts, after RNNoise has had a second to learn the noise.
+    let quiet: Vec<usize> = (SAMPLE_RATE..len - delay)
+        .filter(|&i| gaps[i.saturating_sub(960)..(i + 960).min(len)].iter().all(|&gap| gap))
+        .collect();
+    let before: f32 = quiet.iter().map(|&i| noisy[i] * noisy[i]).sum();
+    let after: f32 = quiet.iter().map(|&i| out[i + delay] * out[i + delay]).sum();
+    let suppression = 10.0 * (before / after).log10();
+    assert!(suppression > 10.0, "the noise was only {:.1} dB quieter", suppression);
+}
+
+#[test]
+fn a_voice_is_kept_on_every_channel() {
+    let len = 3 * SAMPLE_RATE;
+    let mono = voice(len);
+    let stereo: Vec<f32> = mono.iter().flat_map(|&sample| [sample, sample]).collect();
+    let out = suppress(&stereo, 2);
+    let settled = 2 * SAMPLE_RATE;
+    for channel in 0..2 {
+        let heard: Vec<f32> = out.iter().skip(channel).step_by(2).copied().collect();
+        let change = 10.0 * (energy(&heard[settled..]) / energy(&mono[settled..])).log10();
+        assert!(change.abs() < 1.0, "channel {} changed level by {:.1} dB", channel, change);
+    }
+}
+
+#[test]
+fn output_lags_by_the_reported_delay() {
+    let len = 2 * SAMPLE_RATE;
+    let (bursts, _) = speech_in_noise(len); // bursts, so the lag is unambiguous
+    let out = suppress(&bursts, 1);
+    let correlation = |lag: usize| (SAMPLE_RATE..len).map(|i| bursts[i - lag] * out[i]).sum::<f32>();
+    let best = (0..=3 * 480).step_by(240).max_by(|&a, &b| correlation(a).total_cmp(&correlation(b))).unwrap();
+    assert_eq!(best, delay_frames());
+}
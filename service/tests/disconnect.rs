@@ -0,0 +1,66 @@
+//! Senders that vanish without ending their stream: the server tears them down, so their slot
+//! under --max-senders frees up and GetStats stops counting them.
+
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tonic::Code;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::Flow;
+
+use common::{serve, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+
+/// Forwards one connection to `url` and returns its own URL. Aborting the task cuts the
+/// connection off as a network failure would, without either side closing it.
+async fn relay(url: &str) -> (String, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = url.trim_start_matches("http://").to_string();
+    let relaying = tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::connect(server).await.unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+    });
+    (format!("http://{}", addr), relaying)
+}
+
+#[tokio::test]
+async fn a_dropped_sender_frees_its_slot() {
+    let url = serve(&["--max-senders", "1"]).await;
+    let client = SoundFlowClient::connect(url.clone()).await.unwrap();
+    let (relayed, relaying) = relay(&url).await;
+    let sending = {
+        let mut client = SoundFlowClient::connect(relayed).await.unwrap();
+        tokio::spawn(async move {
+            let flows = async_stream::stream! {
+                for seq in 1u64.. {
+                    yield Flow { flow: vec![0.0; PACKAGE_SIZE], seq, ..Default::default() };
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            client.send_flow(flows).await
+        })
+    };
+    let open = || async { client.clone().get_stats(()).await.unwrap().into_inner().senders };
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while open().await != 1 {
+        assert!(tokio::time::Instant::now() < deadline, "the sender never showed up in GetStats");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let refused = client.clone().send_flow(tokio_stream::iter([Flow::default()])).await.unwrap_err();
+    assert_eq!(refused.code(), Code::ResourceExhausted);
+
+    relaying.abort(); // the sender's connection drops mid-stream
+    sending.abort();
+    while open().await != 0 {
+        assert!(tokio::time::Instant::now() < deadline, "the dropped sender is still counted");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    client.clone().send_flow(tokio_stream::iter([Flow::default()])).await.expect("its slot wasn't freed");
+}
@@ -0,0 +1,65 @@
+//! Plays an hour of a sender whose clock runs off the speaker's through the DriftCompensator,
+//! and checks the buffer stays centred and the drift is measured.
+
+use std::time::Duration;
+
+use sf_core::DriftCompensator;
+
+const CHANNELS: usize = 2;
+const PACKAGE_SIZE: usize = 960; // 10 ms of stereo at 48 kHz
+const PACKAGE_DURATION: Duration = Duration::from_millis(10);
+const CENTRE: usize = 3; // packages
+
+/// What the compensator measured after an hour of a sender `ppm` faster than the speaker, and the
+/// furthest the buffer got from CENTRE packages over the last half of it, in samples.
+fn play(ppm: f64) -> (f32, usize) {
+    let mut drift = DriftCompensator::new(CHANNELS);
+    let mut sent = 0.0; // samples the sender produced that don't make a package yet
+    let mut buffered = CENTRE; // packages
+    let mut carry = 0; // samples left over from a stretched package
+    let mut furthest = 0;
+    let packages = 3600 * 100;
+    for played in 0..packages {
+        sent += PACKAGE_SIZE as f64 * (1.0 + ppm * 1e-6);
+        while sent >= PACKAGE_SIZE as f64 {
+            sent -= PACKAGE_SIZE as f64;
+            buffered += 1;
+        }
+        while carry < PACKAGE_SIZE {
+            let level = buffered * PACKAGE_SIZE + carry;
+            buffered -= 1;
+            carry += drift.correct(vec![0.0; PACKAGE_SIZE], level, CENTRE * PACKAGE_SIZE, PACKAGE_DURATION).len();
+        }
+        carry -= PACKAGE_SIZE;
+        if played > packages / 2 {
+            furthest = furthest.max((buffered * PACKAGE_SIZE + carry).abs_diff(CENTRE * PACKAGE_SIZE));
+        }
+    }
+    (drift.ppm as f32, furthest)
+}
+
+#[test]
+fn a_fast_sender_stays_centred() {
+    let (ppm, furthest) = play(100.0);
+    assert!((ppm - 100.0).abs() < 10.0, "measured {} ppm", ppm);
+    // Uncompensated, an hour at 100 ppm piles up 9 s of audio.
+    assert!(furthest <= 2 * PACKAGE_SIZE, "the buffer got {} samples off centre", furthest);
+}
+
+#[test]
+fn a_slow_sender_stays_centred() {
+    let (ppm, furthest) = play(-100.0);
+    assert!((ppm + 100.0).abs() < 10.0, "measured {} ppm", ppm);
+    assert!(furthest <= 2 * PACKAGE_SIZE, "the buffer got {} samples off centre", furthest);
+}
+
+#[test]
+fn matching_clocks_are_left_alone() {
+    let mut drift = DriftCompensator::new(CHANNELS);
+    for _ in 0..3600 * 100 {
+        let package = drift.correct(vec![0.0; PACKAGE_SIZE], CENTRE * PACKAGE_SIZE, CENTRE * PACKAGE_SIZE, PACKAGE_DURATION);
+        assert_eq!(package.len(), PACKAGE_SIZE);
+    }
+    assert_eq!(drift.ppm, 0.0);
+    assert_eq!(drift.corrections, 0);
+}
@@ -0,0 +1,118 @@
+//! Runs frames through the processors of the DSP chain on their own and chained, outside any
+//! audio stream.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use sf_core::sound_flow::AudioFormat;
+use sf_core::{Chain, FadeIn, Gain, GainProcessor, Passthrough, Processor};
+
+use common::{format, CHANNELS};
+
+mod common;
+
+const FRAME: usize = 960; // 10 ms of 48 kHz stereo
+
+/// Adds `offset` to every sample and reports `delay`, to see the order processors run in.
+struct Offset {
+    offset: f32,
+    delay: Duration,
+}
+
+impl Processor for Offset {
+    fn process(&mut self, frame: &mut Vec<f32>, _format: &AudioFormat) {
+        frame.iter_mut().for_each(|sample| *sample = *sample * 2.0 + self.offset);
+    }
+
+    fn delay(&self) -> Duration {
+        self.delay
+    }
+}
+
+/// Drops every frame.
+struct Drop;
+
+impl Processor for Drop {
+    fn process(&mut self, frame: &mut Vec<f32>, _format: &AudioFormat) {
+        frame.clear();
+    }
+}
+
+#[test]
+fn passthrough_leaves_frames_alone() {
+    let frame: Vec<f32> = (0..FRAME).map(|i| i as f32 / FRAME as f32).collect();
+    let mut processed = frame.clone();
+    Passthrough.process(&mut processed, &format());
+    assert_eq!(processed, frame);
+    assert_eq!(Passthrough.delay(), Duration::ZERO);
+}
+
+#[test]
+fn gain_ramps_to_a_new_volume() {
+    let volume = Gain::new(1.0);
+    let muted = Arc::new(AtomicBool::new(false));
+    let mut gain = GainProcessor::new(volume.clone(), muted.clone());
+    let mut frame = vec![1.0; FRAME];
+    gain.process(&mut frame, &format());
+    assert!(frame.iter().all(|&sample| sample == 1.0), "unity gain changed the samples");
+
+    volume.set(0.5);
+    let mut frame = vec![1.0; FRAME];
+    gain.process(&mut frame, &format());
+    let steps = frame.chunks(CHANNELS).map(|pair| pair[0]).collect::<Vec<_>>();
+    assert!(steps.windows(2).all(|pair| pair[0] >= pair[1] && pair[0] - pair[1] < 0.01), "the gain jumped instead of ramping");
+    assert!(frame.chunks(CHANNELS).all(|pair| pair[0] == pair[1]), "the channels were ramped apart");
+    assert!((frame[FRAME - 1] - 0.5).abs() < 1e-4, "the gain ended at {} instead of 0.5", frame[FRAME - 1]);
+
+    muted.store(true, Ordering::Relaxed);
+    for _ in 0..2 {
+        frame = vec![1.0; FRAME];
+        gain.process(&mut frame, &format());
+    }
+    assert!(frame.iter().all(|&sample| sample.abs() < 1e-4), "muting didn't reach silence");
+}
+
+#[test]
+fn a_chain_runs_its_processors_in_order() {
+    let mut chain = Chain::default();
+    chain.push(Offset { offset: 1.0, delay: Duration::from_millis(10) });
+    chain.push(Offset { offset: -3.0, delay: Duration::from_millis(5) });
+    let mut frame = vec![1.0; FRAME];
+    chain.process(&mut frame, &format());
+    // (1 × 2 + 1) × 2 - 3 one way round, (1 × 2 - 3) × 2 + 1 the other.
+    assert!(frame.iter().all(|&sample| sample == 3.0), "got {} instead of 3", frame[0]);
+    assert_eq!(chain.delay(), Duration::from_millis(15));
+}
+
+#[test]
+fn an_emptied_frame_stops_the_chain() {
+    let mut chain = Chain::default();
+    chain.push(Drop);
+    chain.push(Offset { offset: 1.0, delay: Duration::ZERO });
+    let mut frame = vec![1.0; FRAME];
+    chain.process(&mut frame, &format());
+    assert!(frame.is_empty(), "the frame came back with {} samples", frame.len());
+}
+
+#[test]
+fn playback_fades_in_from_silence() {
+    let mut fade = FadeIn::new(Duration::from_millis(10)); // 480 frames at 48 kHz
+    let mut frame = vec![1.0; FRAME];
+    fade.process(&mut frame, &format());
+    let gains = frame.chunks(CHANNELS).map(|pair| pair[0]).collect::<Vec<_>>();
+    assert!((gains[0] - 1.0 / 480.0).abs() < 1e-6, "the fade started at {}", gains[0]);
+    assert!(gains.windows(2).all(|pair| pair[1] > pair[0]), "the gain didn't rise steadily over the fade");
+    assert!(frame.chunks(CHANNELS).all(|pair| pair[0] == pair[1]), "the channels were faded apart");
+    assert!((gains[FRAME / CHANNELS - 1] - 1.0).abs() < 1e-4, "the fade ended at {} instead of 1", gains[FRAME / CHANNELS - 1]);
+
+    let mut frame = vec![1.0; FRAME];
+    fade.process(&mut frame, &format());
+    assert!(frame.iter().all(|&sample| sample == 1.0), "audio after the fade was changed");
+
+    let mut silence = vec![0.0; FRAME];
+    fade.process(&mut silence, &format());
+    let mut frame = vec![1.0; FRAME];
+    fade.process(&mut frame, &format());
+    assert!(frame[0] < 0.01, "audio after silence started at {} instead of fading in again", frame[0]);
+}
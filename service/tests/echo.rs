@@ -0,0 +1,89 @@
+//! Feeds the echo canceller a synthetic microphone signal, a quiet near-end tone plus the far
+//! end's noise through a made-up room, and checks that the echo goes while the tone stays.
+
+use std::f32::consts::TAU;
+
+use sf_core::EchoCanceller;
+
+const SAMPLE_RATE: usize = 48000;
+const CALLBACK: usize = 480; // frames per input callback, 10 ms
+const SECONDS: usize = 4;
+const TAPS: usize = 128;
+const ROOM: [(usize, f32); 3] = [(10, 0.6), (40, 0.3), (90, -0.1)]; // echo path: delay in samples, gain
+const TONE_HZ: f32 = 300.0;
+const TONE_LEVEL: f32 = 0.05;
+
+/// Uniform noise in [-0.5, 0.5), the same every run.
+fn noise(len: usize) -> Vec<f32> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        })
+        .collect()
+}
+
+fn echo_of(played: &[f32]) -> Vec<f32> {
+    (0..played.len())
+        .map(|i| ROOM.iter().filter(|&&(delay, _)| i >= delay).map(|&(delay, gain)| gain * played[i - delay]).sum())
+        .collect()
+}
+
+fn tone(len: usize) -> Vec<f32> {
+    (0..len).map(|i| TONE_LEVEL * (TAU * TONE_HZ * i as f32 / SAMPLE_RATE as f32).sin()).collect()
+}
+
+fn power(samples: &[f32]) -> f32 {
+    samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32
+}
+
+fn db(ratio: f32) -> f32 {
+    10.0 * ratio.log10()
+}
+
+/// Runs the canceller over `captured` callback by callback, like the input stream does.
+fn cancel(captured: &[f32], played: &[f32]) -> Vec<f32> {
+    let mut canceller = EchoCanceller::new(TAPS, 1);
+    let mut out = captured.to_vec();
+    for (capture, reference) in out.chunks_mut(CALLBACK).zip(played.chunks(CALLBACK)) {
+        canceller.process(capture, reference);
+    }
+    out
+}
+
+#[test]
+fn echo_is_suppressed() {
+    let len = SECONDS * SAMPLE_RATE;
+    let played = noise(len);
+    let echo = echo_of(&played);
+    let out = cancel(&echo, &played);
+    let settled = len / 2; // the second half, after the filter has converged
+    let suppression = db(power(&echo[settled..]) / power(&out[settled..]));
+    assert!(suppression > 40.0, "the echo was only {:.1} dB quieter", suppression);
+}
+
+#[test]
+fn near_end_speech_is_kept() {
+    let len = SECONDS * SAMPLE_RATE;
+    let played = noise(len);
+    let near = tone(len);
+    let captured: Vec<f32> = echo_of(&played).iter().zip(&near).map(|(echo, near)| echo + near).collect();
+    let out = cancel(&captured, &played);
+    let settled = len / 2;
+    let residual: Vec<f32> = out[settled..].iter().zip(&near[settled..]).map(|(out, near)| out - near).collect();
+    // How much of the tone is in the output, the rest being echo left over.
+    let kept = out[settled..].iter().zip(&near[settled..]).map(|(out, near)| out * near).sum::<f32>()
+        / near[settled..].iter().map(|near| near * near).sum::<f32>();
+    assert!((kept - 1.0).abs() < 0.05, "the near end came out at {:.2} of its level", kept);
+    let echo_left = db(power(&residual) / power(&echo_of(&played)[settled..]));
+    assert!(echo_left < -20.0, "only {:.1} dB of echo was removed with the near end talking", -echo_left);
+}
+
+#[test]
+fn silence_from_the_speaker_leaves_the_capture_alone() {
+    let len = SAMPLE_RATE;
+    let near = tone(len);
+    let out = cancel(&near, &vec![0.0; len]);
+    assert_eq!(near, out);
+}
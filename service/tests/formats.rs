@@ -0,0 +1,105 @@
+//! Senders whose format isn't the speaker's: what they negotiate is resampled and remapped on the
+//! way in, so a 44.1 kHz tone comes back from the 48 kHz loopback at the pitch and level it was
+//! sent at.
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{AudioFormat, Codec, Flow, FlowRequest, SampleFormat};
+
+use common::{connect, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+const SENDER_RATE: u32 = 44100;
+const TONE_HZ: f32 = 440.0;
+const TONE_LEVEL: f32 = 0.5;
+const SECONDS: f32 = 0.5;
+const HEARD_SECONDS: f32 = 0.3; // of the tone checked, leaving its start and end out
+
+fn sender(channels: u32) -> AudioFormat {
+    AudioFormat { sample_rate: SENDER_RATE, channels, sample_format: SampleFormat::F32.into(), codec: Codec::Raw.into() }
+}
+
+/// Sends SECONDS of the tone in `channels` at SENDER_RATE, 10 ms a frame in real time.
+async fn send_tone(mut client: SoundFlowClient<Channel>, channels: usize) {
+    let frame_len = SENDER_RATE as usize / 100;
+    let frames = (SECONDS * 100.0) as usize;
+    let paced = async_stream::stream! {
+        let mut ticks = tokio::time::interval(Duration::from_millis(10));
+        for seq in 1..=frames {
+            ticks.tick().await;
+            let start = (seq - 1) * frame_len;
+            let flow = (start..start + frame_len)
+                .flat_map(|i| vec![TONE_LEVEL * (TAU * TONE_HZ * i as f32 / SENDER_RATE as f32).sin(); channels])
+                .collect();
+            yield Flow { flow, seq: seq as u64, ..Default::default() };
+        }
+    };
+    client.send_flow(paced).await.unwrap();
+}
+
+/// Listens until HEARD_SECONDS of capture have arrived, from a little after the tone starts.
+async fn hear(client: &mut SoundFlowClient<Channel>) -> Vec<f32> {
+    let mut flows = client.get_flow(FlowRequest::default()).await.unwrap().into_inner();
+    let len = (HEARD_SECONDS * SAMPLE_RATE as f32) as usize * CHANNELS;
+    let skip = SAMPLE_RATE as usize / 20 * CHANNELS; // 50 ms, past the resampler's ramp up
+    let mut heard = Vec::new();
+    while let Some(flow) = flows.next().await {
+        let started = !heard.is_empty();
+        heard.extend(flow.unwrap().flow.into_iter().skip_while(|sample: &f32| !started && sample.abs() < 1e-3));
+        if heard.len() >= skip + len {
+            return heard[skip..skip + len].to_vec();
+        }
+    }
+    panic!("the flow ended after {} samples", heard.len());
+}
+
+/// Sends the tone in `channels` and returns what came back.
+async fn round_trip(channels: u32) -> Vec<f32> {
+    let mut client = connect(&[]).await;
+    client.negotiate_format(sender(channels)).await.unwrap();
+    let listening = {
+        let mut client = client.clone();
+        tokio::spawn(async move { hear(&mut client).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await; // let the listener subscribe first
+    send_tone(client, channels as usize).await;
+    tokio::time::timeout(TIMEOUT, listening).await.expect("timed out waiting for the tone").unwrap()
+}
+
+/// The frequency of `channel` of the interleaved 48 kHz `samples`, from its zero crossings.
+fn frequency(samples: &[f32], channel: usize) -> f32 {
+    let channel: Vec<f32> = samples.iter().skip(channel).step_by(CHANNELS).copied().collect();
+    let crossings = channel.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+    crossings as f32 / 2.0 / (channel.len() as f32 / SAMPLE_RATE as f32)
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+}
+
+#[tokio::test]
+async fn a_44_1_khz_sender_is_resampled_to_the_speaker() {
+    let heard = round_trip(2).await;
+    for channel in 0..CHANNELS {
+        let hz = frequency(&heard, channel);
+        assert!((hz - TONE_HZ).abs() < 5.0, "channel {} came back at {} Hz", channel, hz);
+    }
+    let level = peak(&heard);
+    assert!((level - TONE_LEVEL).abs() < 0.05, "the tone came back at a peak of {}", level);
+}
+
+#[tokio::test]
+async fn a_44_1_khz_mono_sender_is_resampled_and_upmixed() {
+    let heard = round_trip(1).await;
+    let hz = frequency(&heard, 0);
+    assert!((hz - TONE_HZ).abs() < 5.0, "the tone came back at {} Hz", hz);
+    for frame in heard.chunks_exact(CHANNELS) {
+        assert_eq!(frame[0], frame[1], "mono wasn't the same on both sides");
+    }
+}
@@ -0,0 +1,64 @@
+//! Frames a sender shouldn't send: empty ones are skipped and counted, and one past
+//! --max-frame-samples ends the sender's flow with INVALID_ARGUMENT, leaving what came after it
+//! unread.
+
+use std::time::Duration;
+
+use tonic::transport::Channel;
+use tonic::Code;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{Flow, Stats};
+
+use common::{connect, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+
+fn frame(seq: u64, samples: usize) -> Flow {
+    Flow { flow: vec![0.25; samples], seq, ..Default::default() }
+}
+
+/// Polls GetStats until `done` holds, which it must within TIMEOUT.
+async fn stats_once(client: &mut SoundFlowClient<Channel>, done: impl Fn(&Stats) -> bool) -> Stats {
+    let polled = async {
+        loop {
+            let stats = client.get_stats(()).await.unwrap().into_inner();
+            if done(&stats) {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(TIMEOUT, polled).await.expect("the stats never got there")
+}
+
+#[tokio::test]
+async fn empty_frames_are_skipped() {
+    let mut client = connect(&[]).await;
+    let flows = vec![frame(1, PACKAGE_SIZE), frame(2, 0), frame(3, 0), frame(4, PACKAGE_SIZE)];
+    client.send_flow(tokio_stream::iter(flows)).await.unwrap();
+    let stats = stats_once(&mut client, |stats| stats.frames_received == 4 && stats.senders == 0).await;
+    assert_eq!((stats.frames_empty, stats.frames_oversized, stats.frames_lost), (2, 0, 0));
+}
+
+#[tokio::test]
+async fn an_oversized_frame_ends_the_flow() {
+    let mut client = connect(&["--max-frame-samples", "2000"]).await;
+    let flows = vec![frame(1, PACKAGE_SIZE), frame(2, 2000), frame(3, 2001), frame(4, PACKAGE_SIZE)];
+    let refused = client.send_flow(tokio_stream::iter(flows)).await.unwrap_err();
+    assert_eq!(refused.code(), Code::InvalidArgument, "{}", refused.message());
+    let stats = stats_once(&mut client, |stats| stats.frames_oversized > 0 && stats.senders == 0).await;
+    assert_eq!(stats.frames_oversized, 1);
+    assert_eq!(stats.frames_received, 3, "read on past the oversized frame");
+}
+
+#[tokio::test]
+async fn the_limit_follows_the_package_size() {
+    let mut client = connect(&["--package-size", "100"]).await;
+    let refused = client.send_flow(tokio_stream::iter([frame(1, 1000), frame(2, 1001)])).await.unwrap_err();
+    assert_eq!(refused.code(), Code::InvalidArgument);
+    let stats = stats_once(&mut client, |stats| stats.senders == 0 && stats.frames_received == 2).await;
+    assert_eq!(stats.frames_oversized, 1, "10 packages and one sample more got through");
+}
@@ -0,0 +1,64 @@
+//! Checks that --adaptive-package-size grows the frames a Duplex peer is sent once it reports
+//! underruns, and announces the new size.
+
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+
+use sf_core::sound_flow::{BufferHealth, Flow};
+
+use common::{connect, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+const CALM_FRAMES: u64 = 20; // sent reporting no underruns before the peer starts to struggle
+
+/// Silence in real time, reporting one underrun per frame after the first CALM_FRAMES.
+fn struggling_peer() -> impl tokio_stream::Stream<Item = Flow> {
+    let period = Duration::from_secs_f64((PACKAGE_SIZE / CHANNELS) as f64 / SAMPLE_RATE as f64);
+    async_stream::stream! {
+        let mut ticks = tokio::time::interval(period);
+        for seq in 1u64.. {
+            ticks.tick().await;
+            let health = BufferHealth { depth: 3, underruns: seq.saturating_sub(CALM_FRAMES) };
+            yield Flow { flow: vec![0.0; PACKAGE_SIZE], seq, health: Some(health), ..Default::default() };
+        }
+    }
+}
+
+/// The package sizes the first `frames` frames of a Duplex call with `args` announce, and
+/// whether they all reported the server's buffer.
+async fn announced_sizes(args: &[&str], frames: usize) -> (Vec<u32>, bool) {
+    let mut client = connect(args).await;
+    let mut flows = client.duplex(struggling_peer()).await.unwrap().into_inner();
+    let mut announced = Vec::new();
+    let mut healthy = true;
+    tokio::time::timeout(TIMEOUT, async {
+        for _ in 0..frames {
+            let flow = flows.next().await.unwrap().unwrap();
+            if flow.package_size != 0 {
+                announced.push(flow.package_size);
+                assert_eq!(flow.flow.len(), flow.package_size as usize, "a frame doesn't have the size it announces");
+            }
+            healthy &= flow.health.is_some();
+        }
+    }).await.expect("timed out waiting for frames");
+    (announced, healthy)
+}
+
+#[tokio::test]
+async fn frames_grow_when_the_peer_underruns() {
+    let (announced, healthy) = announced_sizes(&["--adaptive-package-size", "--max-package-size", "4000"], 40).await;
+    assert_eq!(announced.first(), Some(&(PACKAGE_SIZE as u32)));
+    assert!(announced.contains(&(2 * PACKAGE_SIZE as u32)), "frames never grew: {:?}", announced);
+    assert!(announced.iter().all(|&size| size as usize <= 4 * PACKAGE_SIZE), "frames grew past the maximum: {:?}", announced);
+    assert!(healthy, "a Duplex frame didn't report the server's buffer");
+}
+
+#[tokio::test]
+async fn frames_keep_their_size_by_default() {
+    let (announced, healthy) = announced_sizes(&[], 40).await;
+    assert!(announced.is_empty(), "fixed frames announced sizes: {:?}", announced);
+    assert!(healthy, "a Duplex frame didn't report the server's buffer");
+}
@@ -0,0 +1,53 @@
+//! Streams a 2-channel ramp through the virtual loopback device at a --package-size that isn't a
+//! whole number of frames, and checks that frames stay whole and the channels stay apart.
+
+use tokio_stream::StreamExt;
+
+use sf_core::sound_flow::FlowRequest;
+
+use common::{connect, send, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 999; // odd, so packages of exactly this many samples would split a stereo frame
+const FRAMES: usize = 24000; // half a second
+
+/// The left channel ramps up from just above silence and the right one mirrors it below zero,
+/// so every frame can be told apart and checked for its pairing.
+fn ramp() -> Vec<f32> {
+    (1..=FRAMES)
+        .flat_map(|frame| {
+            let left = 0.5 * frame as f32 / FRAMES as f32;
+            [left, -left]
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn stereo_frames_stay_whole_and_in_order() {
+    let client = connect(&["--package-size", &PACKAGE_SIZE.to_string(), "--no-end-fade"]).await;
+    let response = client.clone().get_flow(FlowRequest::default()).await.unwrap();
+    let metadata = response.metadata();
+    assert_eq!(metadata.get("sf-sample-rate").unwrap(), SAMPLE_RATE.to_string().as_str());
+    assert_eq!(metadata.get("sf-channels").unwrap(), CHANNELS.to_string().as_str());
+    let mut flows = response.into_inner();
+
+    let package_size = PACKAGE_SIZE / CHANNELS * CHANNELS; // what the server plays, so what to send
+    let ramp = ramp();
+    let sending = tokio::spawn(send(client, ramp.clone(), package_size));
+    let mut heard = Vec::new();
+    tokio::time::timeout(TIMEOUT, async {
+        while heard.len() < ramp.len() {
+            let flow = flows.next().await.unwrap().unwrap().flow;
+            assert_eq!(flow.len() % CHANNELS, 0, "a {} sample frame splits a stereo frame", flow.len());
+            let started = !heard.is_empty();
+            heard.extend(flow.into_iter().skip_while(|&sample| !started && sample == 0.0));
+        }
+    }).await.expect("timed out waiting for the ramp");
+    sending.await.unwrap();
+
+    heard.truncate(ramp.len());
+    for (frame, (heard, sent)) in heard.chunks(CHANNELS).zip(ramp.chunks(CHANNELS)).enumerate() {
+        assert_eq!(heard, sent, "frame {} came back wrong", frame);
+    }
+}
@@ -0,0 +1,50 @@
+//! Messages from clients built before DeviceDirection, which sent a `bool direction` in Direction
+//! and DeviceId where `true` meant capture, still decode to what they asked for.
+
+use prost::Message;
+
+use sf_core::sound_flow::{DeviceDirection, DeviceId, Direction};
+
+/// Direction as it was, `direction = true` for capture devices.
+#[derive(Clone, PartialEq, Message)]
+struct LegacyDirection {
+    #[prost(bool, tag = "1")]
+    direction: bool,
+}
+
+/// DeviceId as it was, `direction = Some(true)` to set the default source.
+#[derive(Clone, PartialEq, Message)]
+struct LegacyDeviceId {
+    #[prost(uint32, tag = "1")]
+    id: u32,
+    #[prost(bool, optional, tag = "2")]
+    direction: Option<bool>,
+}
+
+fn device_id(direction: Option<bool>) -> DeviceId {
+    DeviceId::decode(LegacyDeviceId { id: 7, direction }.encode_to_vec().as_slice()).unwrap()
+}
+
+#[test]
+fn a_legacy_true_direction_is_capture() {
+    let legacy = LegacyDirection { direction: true }.encode_to_vec();
+    assert_eq!(Direction::decode(legacy.as_slice()).unwrap().direction(), DeviceDirection::Capture);
+}
+
+#[test]
+fn a_legacy_false_direction_lists_all() {
+    // proto3 leaves `false` off the wire, just as it does ALL.
+    let legacy = LegacyDirection { direction: false }.encode_to_vec();
+    assert!(legacy.is_empty());
+    assert_eq!(Direction::decode(legacy.as_slice()).unwrap().direction(), DeviceDirection::All);
+}
+
+#[test]
+fn a_legacy_device_id_selects_the_same_kind_of_device() {
+    let source = device_id(Some(true));
+    assert_eq!((source.id, source.direction()), (7, DeviceDirection::Capture));
+    // set_device takes anything but CAPTURE for a sink, as the old default of false did.
+    for sink in [device_id(Some(false)), device_id(None)] {
+        assert_eq!((sink.id, sink.direction()), (7, DeviceDirection::All));
+    }
+}
@@ -0,0 +1,38 @@
+//! Binds listeners the way the server does, and checks the IPv6 wildcard takes IPv4
+//! connections too, and what --ipv4-only makes of IPv6 addresses.
+
+use std::net::{SocketAddr, TcpStream};
+
+use sf_core::{bind_listener, ipv4_addr};
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn the_ipv6_wildcard_accepts_ipv4() {
+    let listener = match bind_listener(addr("[::]:0")) {
+        Ok(listener) => listener,
+        Err(e) => return eprintln!("skipped, this host has no IPv6: {:#}", e),
+    };
+    let port = listener.local_addr().unwrap().port();
+    listener.set_nonblocking(false).unwrap();
+    let _client = TcpStream::connect(("127.0.0.1", port)).expect("an IPv4 client couldn't connect");
+    let (_, peer) = listener.accept().unwrap();
+    assert_eq!(peer.ip().to_canonical().to_string(), "127.0.0.1");
+}
+
+#[test]
+fn ipv4_listeners_bind() {
+    let listener = bind_listener(addr("127.0.0.1:0")).unwrap();
+    assert!(listener.local_addr().unwrap().is_ipv4());
+}
+
+#[test]
+fn ipv4_only_maps_ipv6_addresses() {
+    assert_eq!(ipv4_addr(addr("[::]:50051")).unwrap(), addr("0.0.0.0:50051"));
+    assert_eq!(ipv4_addr(addr("[::1]:50051")).unwrap(), addr("127.0.0.1:50051"));
+    assert_eq!(ipv4_addr(addr("[::ffff:192.168.1.2]:80")).unwrap(), addr("192.168.1.2:80"));
+    assert_eq!(ipv4_addr(addr("10.0.0.1:80")).unwrap(), addr("10.0.0.1:80"));
+    assert!(ipv4_addr(addr("[2001:db8::1]:80")).is_err());
+}
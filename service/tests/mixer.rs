@@ -0,0 +1,52 @@
+//! Mixing concurrent senders: two sines come out of the mixer as their sum, and a sum too loud
+//! to play is turned down by the limiter instead of clipping.
+
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+
+use sf_core::{Mixer, Packet};
+
+const SAMPLE_RATE: f32 = 48000.0;
+const PACKAGE_SIZE: usize = 960; // 10 ms of mono
+const PACKAGES: u64 = 10;
+
+/// `PACKAGES` packages of a sine at `frequency`, the whole of it in one.
+fn sine(frequency: f32, amplitude: f32) -> Vec<f32> {
+    (0..PACKAGE_SIZE * PACKAGES as usize).map(|i| amplitude * (TAU * frequency * i as f32 / SAMPLE_RATE).sin()).collect()
+}
+
+/// Pushes every package of each of `streams` at once, then pops what the mixer makes of them.
+fn mix(streams: &[Vec<f32>]) -> Vec<f32> {
+    let mut mixer = Mixer::new(1, 1, 2 * PACKAGES as usize, true, false, 0);
+    for (stream, samples) in (1..).zip(streams) {
+        for (seq, package) in (1..).zip(samples.chunks(PACKAGE_SIZE)) {
+            mixer.push(Packet { stream, seq, samples: package.to_vec(), captured: None, due: None, end: false });
+        }
+    }
+    let period = Duration::from_secs_f32(PACKAGE_SIZE as f32 / SAMPLE_RATE);
+    let mut slot = Instant::now();
+    (0..PACKAGES).flat_map(|_| {
+        slot += period;
+        mixer.pop(PACKAGE_SIZE, &(slot..slot + period)).expect("nothing to play")
+    }).collect()
+}
+
+#[test]
+fn two_sines_mix_to_their_sum() {
+    let (low, high) = (sine(440.0, 0.3), sine(660.0, 0.4));
+    let mixed = mix(&[low.clone(), high.clone()]);
+    assert_eq!(mixed.len(), low.len());
+    for (i, sample) in mixed.iter().enumerate() {
+        assert!((sample - (low[i] + high[i])).abs() < 1e-6, "sample {} is {}, not {}", i, sample, low[i] + high[i]);
+    }
+}
+
+#[test]
+fn a_loud_sum_is_limited_to_full_scale() {
+    // Two loud sines in phase, which sum to peaks of 1.8.
+    let loud = sine(440.0, 0.9);
+    let mixed = mix(&[loud.clone(), loud]);
+    let peak = mixed.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!(peak <= 1.0, "the mix peaked at {}", peak);
+    assert!(peak > 0.9, "the mix was turned down to {}, more than it needed", peak);
+}
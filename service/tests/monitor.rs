@@ -0,0 +1,51 @@
+//! The Monitor tap on the capture: every frame, silence included, whoever else is listening, and
+//! without taking a listener's place.
+
+use std::f32::consts::TAU;
+
+use tokio_stream::StreamExt;
+
+use sf_core::sound_flow::FlowRequest;
+
+use common::{connect, send, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+#[tokio::test]
+async fn silence_is_monitored_with_nobody_sending() {
+    let mut client = connect(&["--package-size", "480"]).await;
+    let mut monitor = client.monitor(()).await.unwrap().into_inner();
+    for expected in 1..=5 {
+        let frame = tokio::time::timeout(TIMEOUT, monitor.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(frame.seq, expected);
+        assert_eq!(frame.flow.len(), 480);
+        assert!(frame.flow.iter().all(|&sample| sample == 0.0));
+    }
+}
+
+#[tokio::test]
+async fn the_tone_sent_is_monitored() {
+    let mut client = connect(&["--package-size", "480"]).await;
+    let mut monitor = client.monitor(()).await.unwrap().into_inner();
+    // Half a second of 440 Hz.
+    let tone = (0..SAMPLE_RATE as usize / 2 * CHANNELS).map(|i| 0.5 * (TAU * 440.0 * (i / CHANNELS) as f32 / SAMPLE_RATE as f32).sin()).collect();
+    tokio::spawn(send(client.clone(), tone, 480));
+    let heard = tokio::time::timeout(TIMEOUT, async {
+        while let Some(frame) = monitor.next().await {
+            if frame.unwrap().flow.iter().any(|&sample| sample.abs() > 0.1) {
+                return true;
+            }
+        }
+        false
+    }).await.unwrap();
+    assert!(heard, "the monitor ended before the tone came back");
+}
+
+#[tokio::test]
+async fn monitors_leave_the_listeners_their_places() {
+    let mut client = connect(&["--max-listeners", "1"]).await;
+    let _monitors = [client.monitor(()).await.unwrap(), client.monitor(()).await.unwrap()];
+    let _listener = client.get_flow(FlowRequest::default()).await.unwrap();
+    let stats = client.get_stats(()).await.unwrap().into_inner();
+    assert_eq!(stats.listeners, 1);
+}
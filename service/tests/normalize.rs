@@ -0,0 +1,77 @@
+//! Runs capture normalization over synthetic microphones: a quiet one should be brought up to the
+//! target, a sudden loud one held below the limiter's ceiling, and silence left alone.
+
+use std::f32::consts::TAU;
+
+use sf_core::sound_flow::AudioFormat;
+use sf_core::{Normalizer, Processor};
+
+const SAMPLE_RATE: usize = 48000;
+const CALLBACK: usize = 480; // frames per input callback
+const TARGET_DBFS: f32 = -20.0;
+const CEILING: f32 = 0.891; // -1 dBFS
+
+fn mono() -> AudioFormat {
+    AudioFormat { sample_rate: SAMPLE_RATE as u32, channels: 1, ..Default::default() }
+}
+
+/// `seconds` of a 440 Hz sine whose RMS is `rms_dbfs`.
+fn tone(seconds: usize, rms_dbfs: f32) -> Vec<f32> {
+    let amplitude = 10f32.powf(rms_dbfs / 20.0) * 2f32.sqrt();
+    (0..seconds * SAMPLE_RATE).map(|i| amplitude * (TAU * 440.0 * i as f32 / SAMPLE_RATE as f32).sin()).collect()
+}
+
+fn normalize(normalizer: &mut Normalizer, samples: &[f32]) -> Vec<f32> {
+    samples.chunks(CALLBACK).flat_map(|callback| {
+        let mut callback = callback.to_vec();
+        normalizer.process(&mut callback, &mono());
+        callback
+    }).collect()
+}
+
+fn rms_dbfs(samples: &[f32]) -> f32 {
+    10.0 * (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).log10()
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+}
+
+#[test]
+fn a_quiet_microphone_is_boosted_to_the_target_without_clipping() {
+    let mut normalizer = Normalizer::new(TARGET_DBFS, 30.0);
+    let out = normalize(&mut normalizer, &tone(20, -40.0));
+    let last_second = &out[out.len() - SAMPLE_RATE..];
+    assert!((rms_dbfs(last_second) - TARGET_DBFS).abs() < 1.0, "ended at {} dBFS", rms_dbfs(last_second));
+    assert!((normalizer.gain_db() - 20.0).abs() < 1.0, "applied {} dB", normalizer.gain_db());
+    assert!(peak(&out) <= CEILING, "peaked at {}", peak(&out));
+    // Slowly: a second in, it is still well short of the target.
+    assert!(rms_dbfs(&out[..SAMPLE_RATE]) < TARGET_DBFS - 10.0, "rose to {} dBFS in a second", rms_dbfs(&out[..SAMPLE_RATE]));
+}
+
+#[test]
+fn the_boost_stops_at_the_most_allowed() {
+    let mut normalizer = Normalizer::new(TARGET_DBFS, 10.0);
+    normalize(&mut normalizer, &tone(20, -40.0));
+    assert!((normalizer.gain_db() - 10.0).abs() < 0.5, "applied {} dB", normalizer.gain_db());
+}
+
+#[test]
+fn a_sudden_loud_microphone_stays_below_the_ceiling() {
+    let mut normalizer = Normalizer::new(TARGET_DBFS, 30.0);
+    normalize(&mut normalizer, &tone(20, -40.0));
+    // Full scale into 20 dB of gain: only the limiter stands in the way.
+    let out = normalize(&mut normalizer, &tone(3, -3.0));
+    assert!(peak(&out) <= CEILING, "peaked at {}", peak(&out));
+    assert!(normalizer.gain_db() < -10.0, "the gain is still {} dB", normalizer.gain_db());
+}
+
+#[test]
+fn silence_leaves_the_gain_alone() {
+    let mut normalizer = Normalizer::new(TARGET_DBFS, 30.0);
+    normalize(&mut normalizer, &tone(20, -40.0));
+    let gain = normalizer.gain_db();
+    let out = normalize(&mut normalizer, &vec![0.0; 10 * SAMPLE_RATE]);
+    assert_eq!(normalizer.gain_db(), gain);
+    assert!(out.iter().all(|&sample| sample == 0.0));
+}
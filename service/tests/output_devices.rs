@@ -0,0 +1,75 @@
+//! Playing on other sinks besides the default one: SetOutputDevices on the virtual device, whose
+//! second sink its audio side knows by another name than GetDevices lists, and the copies the
+//! speaker makes for them.
+
+use tonic::Code;
+
+use sf_core::sound_flow::{AudioFormat, DeviceDirection, Direction, OutputDevices};
+use sf_core::{Mirror, Mirrors, Processor};
+
+use common::{connect, format};
+
+mod common;
+
+const FRAME: usize = 960; // samples per speaker frame, 10 ms of 48 kHz stereo
+
+fn sink(sample_rate: u32, channels: u32) -> AudioFormat {
+    AudioFormat { sample_rate, channels, ..format() }
+}
+
+#[tokio::test]
+async fn the_default_sink_is_left_out() {
+    let mut client = connect(&[]).await;
+    client.set_output_devices(OutputDevices { ids: vec![0] }).await.unwrap();
+    client.set_output_devices(OutputDevices { ids: vec![] }).await.unwrap();
+}
+
+#[tokio::test]
+async fn sinks_are_opened_as_the_controller_knows_them() {
+    let mut client = connect(&[]).await;
+    let request = Direction { direction: DeviceDirection::Playback.into() };
+    let devices = client.get_devices(request).await.unwrap().into_inner().devices;
+    let second = devices.iter().find(|device| device.id == 1).expect("the virtual device has no second sink");
+    assert_eq!(second.name, "Virtual second sink");
+    client.set_output_devices(OutputDevices { ids: vec![second.id] }).await.unwrap();
+    client.set_output_devices(OutputDevices { ids: vec![] }).await.unwrap();
+}
+
+#[tokio::test]
+async fn unknown_sinks_are_not_found() {
+    let mut client = connect(&[]).await;
+    let status = client.set_output_devices(OutputDevices { ids: vec![0, 7] }).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound, "{:?}", status);
+}
+
+#[test]
+fn copies_are_converted_to_the_sinks_format() {
+    let mut mirrors = Mirrors::default();
+    let (mirror, mut mirrored) = Mirror::new(sink(44100, 1), 2 * 44100);
+    mirrors.set(vec![mirror]);
+    let speaker = sink(48000, 2);
+    for _ in 0..100 {
+        mirrors.process(&mut vec![0.5; FRAME], &speaker);
+    }
+    // A second at 48 kHz is a second at 44.1 kHz, less what the resampler holds back.
+    let mut played = vec![0.0; 44100];
+    played.chunks_mut(441).for_each(|callback| mirrored.fill(callback));
+    let heard = played.iter().rposition(|&sample| sample != 0.0).map_or(0, |last| last + 1);
+    assert!((40000..=44100).contains(&heard), "{} samples of the second came through", heard);
+    assert!(played[20000..heard].iter().all(|&sample| (sample - 0.5).abs() < 0.01), "the copy isn't the level played");
+}
+
+#[test]
+fn a_sink_waits_for_twice_a_callback() {
+    let mut mirrors = Mirrors::default();
+    let (mirror, mut mirrored) = Mirror::new(sink(48000, 2), 100 * FRAME);
+    mirrors.set(vec![mirror]);
+    let speaker = sink(48000, 2);
+    let mut data = vec![1.0; FRAME];
+    mirrors.process(&mut vec![0.5; FRAME], &speaker);
+    mirrored.fill(&mut data);
+    assert!(data.iter().all(|&sample| sample == 0.0), "played with a callback's worth queued");
+    mirrors.process(&mut vec![0.5; FRAME], &speaker);
+    mirrored.fill(&mut data);
+    assert!(data.iter().all(|&sample| sample == 0.5), "didn't play with twice a callback's worth queued");
+}
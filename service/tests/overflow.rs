@@ -0,0 +1,52 @@
+//! A sender that bursts far past its playback lane: whatever --overflow drops on the way, the
+//! end of its stream still reaches the mix, so what is left plays out instead of waiting there.
+
+use tokio_stream::StreamExt;
+
+use sf_core::sound_flow::{Flow, FlowRequest};
+use sf_core::{Packet, PlaybackRing};
+
+use common::{connect, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+const CAPACITY: usize = 4; // packages a lane holds, well short of the jitter depth
+const LEVEL: f32 = 0.25; // of every sample sent, to tell them from the silence around them
+
+async fn burst_plays_out(policy: &str) {
+    let mut client = connect(&["--ring-capacity", &CAPACITY.to_string(), "--overflow", policy]).await;
+    let mut flows = client.get_flow(FlowRequest::default()).await.unwrap().into_inner();
+    let burst: Vec<Flow> = (1..=3 * CAPACITY as u64).map(|seq| Flow { flow: vec![LEVEL; PACKAGE_SIZE], seq, ..Default::default() }).collect();
+    client.send_flow(tokio_stream::iter(burst)).await.unwrap();
+    // The lane keeps CAPACITY packages, which only play out without the jitter depth's worth
+    // once the end marker made it to the mix.
+    let heard = async {
+        let mut heard = 0;
+        while heard < CAPACITY * PACKAGE_SIZE {
+            heard += flows.next().await.unwrap().unwrap().flow.iter().filter(|&&sample| sample == LEVEL).count();
+        }
+    };
+    tokio::time::timeout(TIMEOUT, heard).await.expect("what the lane kept never played");
+    let stats = client.get_stats(()).await.unwrap().into_inner();
+    assert!(stats.output_overruns > 0, "the burst never overran the lane");
+}
+
+#[tokio::test]
+async fn the_end_gets_through_when_the_oldest_are_dropped() {
+    burst_plays_out("drop-oldest").await;
+}
+
+#[tokio::test]
+async fn the_end_gets_through_when_the_newest_are_dropped() {
+    burst_plays_out("drop-newest").await;
+}
+
+#[tokio::test]
+async fn an_end_with_no_room_is_reported_dropped() {
+    let (ring, _drain) = PlaybackRing::new(1);
+    let mut lane = ring.open();
+    assert!(!lane.finish(Packet::end(1, 1)).await, "the end didn't fit an empty lane");
+    // Nothing drains the lane, so the next end waits for room in vain.
+    assert!(lane.finish(Packet::end(1, 2)).await, "an end the lane had no room for was reported queued");
+}
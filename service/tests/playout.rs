@@ -0,0 +1,101 @@
+//! Sends a tone stamped with capture times from another clock, with jitter and a stall on the
+//! way, to a server with a playout delay, and checks it comes back that long after it was sent,
+//! whatever the jitter, dropping only what arrives too late.
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{Flow, FlowRequest};
+
+use common::{connect, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+const PACKAGES: usize = 60;
+const FOREIGN_NS: u64 = 1 << 60; // where the other clock stands at the first frame, far from ours
+const TONE_HZ: f32 = 440.0;
+
+fn period() -> Duration {
+    Duration::from_secs_f64((PACKAGE_SIZE / CHANNELS) as f64 / SAMPLE_RATE as f64)
+}
+
+fn tone() -> Vec<f32> {
+    (0..PACKAGES * PACKAGE_SIZE / CHANNELS)
+        .flat_map(|frame| [0.5 * (TAU * TONE_HZ * frame as f32 / SAMPLE_RATE as f32).cos(); CHANNELS])
+        .collect()
+}
+
+/// Sends the tone, stamped as captured a package period apart, package `i` at `sent(i)` after
+/// the first, and returns when the first went.
+async fn send(mut client: SoundFlowClient<Channel>, tone: Vec<f32>, sent: fn(u32) -> Duration) -> Instant {
+    let start = Instant::now();
+    let period = period().as_nanos() as u64;
+    let flows = async_stream::stream! {
+        for (i, samples) in (0..).zip(tone.chunks(PACKAGE_SIZE)) {
+            tokio::time::sleep_until(start + sent(i)).await;
+            yield Flow { flow: samples.to_vec(), seq: i as u64 + 1, captured_ns: FOREIGN_NS + i as u64 * period, ..Default::default() };
+        }
+    };
+    tokio::spawn(async move { client.send_flow(flows).await.unwrap() });
+    start
+}
+
+/// Listens until `len` samples have arrived from the first one that isn't silent on, and
+/// returns those along with when that one arrived.
+async fn receive(mut client: SoundFlowClient<Channel>, len: usize) -> (Vec<f32>, Instant) {
+    let mut flows = client.get_flow(FlowRequest::default()).await.unwrap().into_inner();
+    let mut heard = Vec::new();
+    let mut first = None;
+    while let Some(flow) = flows.next().await {
+        let samples = flow.unwrap().flow;
+        if first.is_none() && samples.iter().any(|&sample| sample != 0.0) {
+            first = Some(Instant::now());
+        }
+        let started = !heard.is_empty();
+        heard.extend(samples.into_iter().skip_while(|&sample| !started && sample == 0.0));
+        if heard.len() >= len {
+            heard.truncate(len);
+            return (heard, first.unwrap());
+        }
+    }
+    panic!("the flow ended after {} of {} samples", heard.len(), len);
+}
+
+/// Plays the tone through a server with a `delay_ms` playout delay, and returns what came back,
+/// how long after it was sent, and how many packages the server dropped as late.
+async fn play_out(delay_ms: &str, sent: fn(u32) -> Duration) -> (Vec<f32>, Duration, u64) {
+    let client = connect(&["--no-end-fade", "--playout-delay-ms", delay_ms, "--jitter-max", "32"]).await;
+    let tone = tone();
+    let listening = tokio::spawn(receive(client.clone(), tone.len()));
+    tokio::time::sleep(Duration::from_millis(100)).await; // let the listener subscribe first
+    let start = send(client.clone(), tone, sent).await;
+    let (heard, first) = tokio::time::timeout(TIMEOUT, listening).await.expect("timed out waiting for the tone").unwrap();
+    let late = client.clone().get_stats(()).await.unwrap().into_inner().frames_late;
+    (heard, first - start, late)
+}
+
+#[tokio::test]
+async fn jitter_doesnt_change_the_latency() {
+    // Every fourth package is held up by 30 ms, those queued behind it go with it.
+    let (heard, latency, late) = play_out("150", |i| period() * i + if i % 4 == 1 { Duration::from_millis(30) } else { Duration::ZERO }).await;
+    assert!(latency >= Duration::from_millis(140), "the tone came back after only {:?}", latency);
+    assert!(latency < Duration::from_millis(250), "the tone took {:?} to come back", latency);
+    assert_eq!(late, 0);
+    assert_eq!(heard, tone(), "the tone didn't come back unchanged");
+}
+
+#[tokio::test]
+async fn packages_past_their_playout_time_are_dropped() {
+    // A 100 ms stall ahead of package 20 makes the first few after it later than the 60 ms delay.
+    let (heard, _, late) = play_out("60", |i| if i < 20 { period() * i } else { (period() * i).max(period() * 20 + Duration::from_millis(100)) }).await;
+    assert!(late >= 2, "only {} package(s) were dropped as late", late);
+    // Dropping them kept the rest on time: the end plays in the slots it would have without a stall.
+    let tail = heard.len() - 10 * PACKAGE_SIZE;
+    assert_eq!(heard[tail..], tone()[tail..], "the end of the tone didn't come back in place");
+}
@@ -0,0 +1,49 @@
+//! --preset: what each one sets, and options given on their own winning over it, whether on
+//! the command line or in the --config file.
+
+use std::time::Duration;
+
+use sf_core::sound_flow::AudioFormat;
+use sf_core::{Compression, Config};
+
+fn load(args: &[&str]) -> Config {
+    Config::load_from(["sf_core"].iter().chain(args)).unwrap()
+}
+
+fn stereo() -> AudioFormat {
+    AudioFormat { sample_rate: 48000, channels: 2, ..Default::default() }
+}
+
+#[test]
+fn low_latency_shrinks_every_buffer() {
+    let config = load(&["--preset", "low-latency"]);
+    assert_eq!((config.package_size, config.buffer_frames, config.ring_capacity), (480, Some(128), 32));
+    assert_eq!((config.jitter_depth, config.jitter_min, config.jitter_max), (2, 1, 8));
+    assert_eq!(config.send_compression, Compression::None);
+    // Three 5 ms packages and two 128 frame hardware buffers.
+    assert_eq!(config.estimated_latency(&stereo()), Duration::from_secs_f64((3 * 240 + 256) as f64 / 48000.0));
+}
+
+#[test]
+fn balanced_is_the_defaults() {
+    let balanced = load(&["--preset", "balanced"]);
+    let defaults = load(&[]);
+    assert_eq!(format!("{:?}", Config { preset: None, ..balanced }), format!("{:?}", defaults));
+    // Four packages of 1000 samples, 500 frames each, and whatever the devices buffer.
+    assert_eq!(defaults.estimated_latency(&stereo()), Duration::from_secs_f64((4 * 500) as f64 / 48000.0));
+}
+
+#[test]
+fn options_given_win_over_the_preset() {
+    let config = load(&["--preset", "high-quality", "--jitter-depth", "8"]);
+    assert_eq!((config.package_size, config.jitter_depth, config.jitter_max), (1920, 8, 25));
+}
+
+#[test]
+fn the_config_file_wins_over_the_preset_too() {
+    let path = std::env::temp_dir().join(format!("sf_core-{}-preset.toml", std::process::id()));
+    std::fs::write(&path, "preset = \"low-latency\"\npackage-size = 960\n").unwrap();
+    let config = load(&["--config", path.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!((config.package_size, config.jitter_depth), (960, 2));
+}
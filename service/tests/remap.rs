@@ -0,0 +1,39 @@
+//! Up- and downmixing a stream to the speaker's channel count: fewer channels are repeated across
+//! the outputs, and extra ones are averaged into the output they wrap onto.
+
+use sf_core::remap;
+
+#[test]
+fn mono_is_duplicated_into_both_sides() {
+    assert_eq!(remap(&[0.1, -0.2, 0.3], 1, 2), [0.1, 0.1, -0.2, -0.2, 0.3, 0.3]);
+}
+
+#[test]
+fn stereo_downmixes_to_the_average() {
+    assert_eq!(remap(&[0.2, 0.4, -1.0, 1.0, 0.5, 0.5], 2, 1), [0.3, 0.0, 0.5]);
+}
+
+#[test]
+fn stereo_repeats_across_more_channels() {
+    assert_eq!(remap(&[0.1, 0.2], 2, 5), [0.1, 0.2, 0.1, 0.2, 0.1]);
+}
+
+#[test]
+fn six_channels_fold_onto_stereo() {
+    // Channels 0, 2 and 4 wrap onto the left, 1, 3 and 5 onto the right.
+    let frame = [0.3, 0.6, 0.0, 0.0, 0.6, -0.3];
+    let stereo = remap(&frame, 6, 2);
+    assert!((stereo[0] - 0.3).abs() < 1e-6 && (stereo[1] - 0.1).abs() < 1e-6, "{:?}", stereo);
+}
+
+#[test]
+fn four_channels_fold_unevenly_onto_three() {
+    // Only channel 3 wraps, onto the first output; the other two outputs pass through.
+    assert_eq!(remap(&[0.2, 0.5, -0.5, 0.4, 1.0, 0.0, 0.0, -1.0], 4, 3), [0.3, 0.5, -0.5, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn the_same_count_passes_through() {
+    let samples = [0.1, 0.2, 0.3, 0.4];
+    assert_eq!(remap(&samples, 2, 2), samples);
+}
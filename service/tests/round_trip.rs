@@ -0,0 +1,152 @@
+//! Streams a sine wave through a server running on the virtual loopback device, with send_flow,
+//! and checks that get_flow hands the same samples back.
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{Codec, Encoding, Flow, FlowRequest, SampleFormat};
+
+use common::{connect, send, send_flows, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+const PACKAGES: usize = 50; // about half a second of tone
+const TONE_HZ: f32 = 440.0;
+const TONE_LEVEL: f32 = 0.5; // well below the mixer's limiter
+const FADE_FRAMES: usize = 480; // --fade-ms 10 at 48 kHz
+
+/// A cosine rather than a sine, so the tone's first sample isn't silent and is easy to find.
+fn tone() -> Vec<f32> {
+    (0..PACKAGES * PACKAGE_SIZE / CHANNELS)
+        .flat_map(|frame| {
+            let sample = TONE_LEVEL * (TAU * TONE_HZ * frame as f32 / SAMPLE_RATE as f32).cos();
+            [sample; CHANNELS]
+        })
+        .collect()
+}
+
+/// Listens in `sample_format` until `len` samples have arrived from the first one that isn't
+/// silent on, and returns those.
+async fn receive(client: &mut SoundFlowClient<Channel>, sample_format: SampleFormat, len: usize) -> Vec<f32> {
+    let request = FlowRequest { codec: Codec::Raw.into(), sample_format: sample_format.into() };
+    let mut flows = client.get_flow(request).await.unwrap().into_inner();
+    let mut heard = Vec::new();
+    while let Some(flow) = flows.next().await {
+        let flow = flow.unwrap();
+        let expected = match sample_format {
+            SampleFormat::F32 => Encoding::F32,
+            SampleFormat::I16 => Encoding::I16,
+            SampleFormat::U16 => Encoding::U16,
+        };
+        if !flow.payload.is_empty() {
+            assert_eq!(flow.encoding(), expected, "the payload wasn't tagged with its sample format");
+        }
+        let samples = match sample_format {
+            SampleFormat::F32 => flow.flow,
+            SampleFormat::I16 => flow.payload.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0).collect(),
+            SampleFormat::U16 => flow.payload.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0 - 1.0).collect(),
+        };
+        let started = !heard.is_empty();
+        heard.extend(samples.into_iter().skip_while(|&sample| !started && sample == 0.0));
+        if heard.len() >= len {
+            heard.truncate(len);
+            return heard;
+        }
+    }
+    panic!("the flow ended after {} of {} samples", heard.len(), len);
+}
+
+/// Sends the tone in frames of `frame_size` and returns it along with what came back, `extra`
+/// samples past its end included.
+async fn round_trip(args: &[&str], frame_size: usize, sample_format: SampleFormat, extra: usize) -> (Vec<f32>, Vec<f32>) {
+    let client = connect(args).await;
+    let tone = tone();
+    let listening = {
+        let mut client = client.clone();
+        let len = tone.len() + extra;
+        tokio::spawn(async move { receive(&mut client, sample_format, len).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await; // let the listener subscribe first
+    send(client.clone(), tone.clone(), frame_size).await;
+    let heard = tokio::time::timeout(TIMEOUT, listening).await.expect("timed out waiting for the tone").unwrap();
+    (tone, heard)
+}
+
+fn assert_close(sent: &[f32], heard: &[f32], tolerance: f32) {
+    assert_eq!(sent.len(), heard.len());
+    for (i, (sent, heard)) in sent.iter().zip(heard).enumerate() {
+        assert!((sent - heard).abs() <= tolerance, "sample {} was sent as {} but came back as {}", i, sent, heard);
+    }
+}
+
+#[tokio::test]
+async fn raw_f32_comes_back_unchanged() {
+    let (sent, heard) = round_trip(&["--no-end-fade"], PACKAGE_SIZE, SampleFormat::F32, 0).await;
+    assert_close(&sent, &heard, 1e-6);
+}
+
+#[tokio::test]
+async fn i16_comes_back_within_a_step() {
+    let (sent, heard) = round_trip(&["--no-end-fade"], PACKAGE_SIZE, SampleFormat::I16, 0).await;
+    assert_close(&sent, &heard, 1.0 / 32768.0);
+}
+
+#[tokio::test]
+async fn u16_comes_back_within_a_step() {
+    let (sent, heard) = round_trip(&["--no-end-fade"], PACKAGE_SIZE, SampleFormat::U16, 0).await;
+    assert_close(&sent, &heard, 1.0 / 32768.0);
+}
+
+#[tokio::test]
+async fn the_start_fades_in_and_the_end_fades_out_into_silence() {
+    let (sent, heard) = round_trip(&["--fade-ms", "10"], PACKAGE_SIZE, SampleFormat::F32, PACKAGE_SIZE).await;
+    let (played, after) = heard.split_at(sent.len());
+    let fade = FADE_FRAMES * CHANNELS;
+    let body = sent.len() - fade;
+    let faded_in: Vec<f32> = sent[..fade].iter().enumerate()
+        .map(|(i, sample)| sample * (i / CHANNELS + 1) as f32 / FADE_FRAMES as f32)
+        .collect();
+    assert_close(&faded_in, &played[..fade], 1e-4);
+    assert_close(&sent[fade..body], &played[fade..body], 1e-6);
+    let faded_out: Vec<f32> = sent[body..].iter().enumerate()
+        .map(|(i, sample)| sample * (1.0 - i as f32 / fade as f32))
+        .collect();
+    assert_close(&faded_out, &played[body..], 1e-6);
+    // Concealment would repeat the last package here, the end marker stops it.
+    assert!(after.iter().all(|&sample| sample == 0.0), "the stream didn't end in silence");
+}
+
+#[tokio::test]
+async fn frames_bigger_than_the_devices_are_regrouped() {
+    let (sent, heard) = round_trip(&["--no-end-fade"], 3 * PACKAGE_SIZE, SampleFormat::F32, 0).await;
+    assert_close(&sent, &heard, 1e-6);
+}
+
+#[tokio::test]
+async fn tagged_payloads_need_no_negotiation() {
+    let client = connect(&["--no-end-fade"]).await;
+    let tone = tone();
+    let listening = {
+        let mut client = client.clone();
+        let len = tone.len();
+        tokio::spawn(async move { receive(&mut client, SampleFormat::F32, len).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await; // let the listener subscribe first
+    // Every other frame as f32 bytes, the rest as i16, on a stream negotiated for neither.
+    let flows = (1..).zip(tone.chunks(PACKAGE_SIZE)).map(|(seq, samples)| {
+        let (encoding, payload) = if seq % 2 == 1 {
+            (Encoding::F32, samples.iter().flat_map(|sample| sample.to_le_bytes()).collect())
+        } else {
+            (Encoding::I16, samples.iter().flat_map(|sample| ((sample * 32768.0).round() as i16).to_le_bytes()).collect())
+        };
+        Flow { payload, encoding: encoding.into(), seq, ..Default::default() }
+    }).collect();
+    send_flows(client.clone(), flows, PACKAGE_SIZE).await;
+    let heard = tokio::time::timeout(TIMEOUT, listening).await.expect("timed out waiting for the tone").unwrap();
+    assert_close(&tone, &heard, 1.0 / 32768.0);
+}
@@ -0,0 +1,94 @@
+//! Every sample format a device may run in, converted to the f32 sf_core works in and back, and
+//! the 16-bit payloads listeners and senders may ask for.
+
+use sf_core::sound_flow::SampleFormat;
+use sf_core::{from_payload, to_payload, DeviceSample};
+
+/// Every value of `T` in `values` comes back as itself from f32.
+fn exact<T: DeviceSample + PartialEq + std::fmt::Debug>(values: impl IntoIterator<Item = T>) {
+    for value in values {
+        assert_eq!(T::from_f32(value.to_f32()), value);
+    }
+}
+
+/// `steps` samples evenly over -1.0..=1.0 land within half a step of where they were after a
+/// round trip through `T`, which is `step` wide, but for 1.0 that `T` can only get a step short of.
+fn near<T: DeviceSample>(step: f32) {
+    let steps = 1000;
+    for i in 0..=steps {
+        let sample = -1.0 + 2.0 * i as f32 / steps as f32;
+        let back = T::from_f32(sample).to_f32();
+        assert!((back - sample.min(1.0 - step)).abs() <= step / 2.0 + f32::EPSILON, "{} came back as {}", sample, back);
+    }
+}
+
+/// Past full scale is clamped to the loudest `T` holds, and NaN is silence.
+fn clamped<T: DeviceSample + PartialEq + std::fmt::Debug>(min: T, max: T, silence: T) {
+    assert_eq!(T::from_f32(1.5), max);
+    assert_eq!(T::from_f32(-1.5), min);
+    assert_eq!(T::from_f32(f32::NAN), silence);
+    assert_eq!(silence.to_f32(), 0.0);
+}
+
+#[test]
+fn integers_come_back_exactly() {
+    exact(i8::MIN..=i8::MAX);
+    exact(u8::MIN..=u8::MAX);
+    exact(i16::MIN..=i16::MAX);
+    exact(u16::MIN..=u16::MAX);
+    exact([i32::MIN, -1 << 8, 0, 1 << 8, i32::MAX & !0xff]);
+    exact([u32::MIN, 0x8000_0000, 0xffff_ff00]);
+    exact([i64::MIN, 0, 1 << 40]);
+    exact([u64::MIN, 1 << 63]);
+}
+
+#[test]
+fn twenty_four_bit_samples_in_i32_come_back_exactly() {
+    // What a 24-bit interface hands over: the sample in the top 24 bits of an i32.
+    exact((-(1 << 23)..1 << 23).step_by(97).map(|sample: i32| sample << 8));
+    exact([-(1 << 23) << 8, ((1 << 23) - 1) << 8]);
+}
+
+#[test]
+fn floats_land_within_half_a_step() {
+    near::<i8>(1.0 / 128.0);
+    near::<u8>(1.0 / 128.0);
+    near::<i16>(1.0 / 32768.0);
+    near::<u16>(1.0 / 32768.0);
+    near::<i32>(1.0 / 2f32.powi(31));
+    near::<u32>(1.0 / 2f32.powi(31));
+    near::<i64>(1.0 / 2f32.powi(63));
+    near::<u64>(1.0 / 2f32.powi(63));
+    near::<f32>(0.0);
+    near::<f64>(0.0);
+}
+
+#[test]
+fn past_full_scale_is_clamped() {
+    clamped(i8::MIN, i8::MAX, 0);
+    clamped(u8::MIN, u8::MAX, 0x80);
+    clamped(i16::MIN, i16::MAX, 0);
+    clamped(u16::MIN, u16::MAX, 0x8000);
+    clamped(i32::MIN, i32::MAX, 0);
+    clamped(u32::MIN, u32::MAX, 0x8000_0000);
+    clamped(i64::MIN, i64::MAX, 0);
+    clamped(u64::MIN, u64::MAX, 1 << 63);
+}
+
+#[test]
+fn payloads_round_trip_in_every_format() {
+    let samples: Vec<f32> = (0..100).map(|i| (i as f32 / 50.0 - 1.0) * 0.9).collect();
+    assert_eq!(from_payload(&to_payload(&samples, SampleFormat::F32), SampleFormat::F32).unwrap(), samples);
+    for format in [SampleFormat::I16, SampleFormat::U16] {
+        let payload = to_payload(&samples, format);
+        assert_eq!(payload.len(), 2 * samples.len());
+        let back = from_payload(&payload, format).unwrap();
+        assert!(back.iter().zip(&samples).all(|(back, sample)| (back - sample).abs() <= 0.5 / 32768.0), "{:?} strayed", format);
+    }
+}
+
+#[test]
+fn payloads_of_partial_samples_are_refused() {
+    assert!(from_payload(&[0; 3], SampleFormat::I16).is_err());
+    assert!(from_payload(&[0; 6], SampleFormat::F32).is_err());
+}
@@ -0,0 +1,40 @@
+//! Checks what GetServerInfo reports, with and without --advertise and --auth-token.
+
+use sf_core::sound_flow::{Codec, SampleFormat};
+
+use common::connect;
+
+mod common;
+
+#[tokio::test]
+async fn reports_version_codecs_and_compression() {
+    let mut client = connect(&["--accept-compression", "zstd"]).await;
+    let info = client.get_server_info(()).await.unwrap().into_inner();
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.advertised_address, "[::1]:50051", "the --listen default, since nothing else was advertised");
+    assert!(info.codecs().any(|codec| codec == Codec::Opus));
+    assert!(info.sample_formats().any(|format| format == SampleFormat::I16));
+    assert_eq!(info.accept_compression, ["zstd"]);
+    assert_eq!(info.protocol, 1);
+    assert_eq!(info.max_listeners, 16);
+    assert!(!info.auth_required && !info.tls);
+}
+
+#[tokio::test]
+async fn needs_the_token_like_every_call() {
+    let mut client = connect(&["--auth-token", "secret", "--max-listeners", "3"]).await;
+    let refused = client.get_server_info(()).await.unwrap_err();
+    assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+    let mut request = tonic::Request::new(());
+    request.metadata_mut().insert("authorization", "Bearer secret".parse().unwrap());
+    let info = client.get_server_info(request).await.unwrap().into_inner();
+    assert!(info.auth_required);
+    assert_eq!(info.max_listeners, 3);
+}
+
+#[tokio::test]
+async fn reports_the_advertised_address() {
+    let mut client = connect(&["--advertise", "sound.example.com:443"]).await;
+    let info = client.get_server_info(()).await.unwrap().into_inner();
+    assert_eq!(info.advertised_address, "sound.example.com:443");
+}
@@ -0,0 +1,61 @@
+//! SetCodec on a GetFlow listener of the virtual loopback device, which keeps broadcasting
+//! silence with --no-vad: the frames switch codec at the seq the call returns, on the same stream.
+
+use tokio_stream::StreamExt;
+use tonic::Code;
+use tonic::Streaming;
+use tonic::transport::Channel;
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::sound_flow::{Codec, CodecSwitch, Encoding, Flow, FlowRequest, SampleFormat};
+
+use common::{connect, TIMEOUT};
+
+mod common;
+
+const LISTENER_HEADER: &str = "sf-listener";
+
+/// How `frame` is encoded, with F32 frames in `Flow.flow` counted as F32 too.
+fn encoding(frame: &Flow) -> Encoding {
+    if frame.payload.is_empty() { Encoding::F32 } else { frame.encoding() }
+}
+
+async fn switch(client: &mut SoundFlowClient<Channel>, listener: u64, codec: Codec, sample_format: SampleFormat) -> u64 {
+    let request = CodecSwitch { listener, codec: codec.into(), sample_format: sample_format.into() };
+    tokio::time::timeout(TIMEOUT, client.set_codec(request)).await.expect("the switch never landed").unwrap().into_inner().seq
+}
+
+/// Reads frames up to `seq`, which must still be `before`, and returns the one at `seq` or
+/// after, which must be `after`.
+async fn switched_at(flows: &mut Streaming<Flow>, seq: u64, before: Encoding, after: Encoding) -> Flow {
+    loop {
+        let frame = tokio::time::timeout(TIMEOUT, flows.next()).await.unwrap().unwrap().unwrap();
+        if frame.seq >= seq {
+            assert_eq!(encoding(&frame), after, "seq {} hasn't switched", frame.seq);
+            return frame;
+        }
+        assert_eq!(encoding(&frame), before, "seq {} switched early", frame.seq);
+    }
+}
+
+#[tokio::test]
+async fn frames_switch_codec_at_the_seq_returned() {
+    let mut client = connect(&[]).await;
+    let response = client.get_flow(FlowRequest::default()).await.unwrap();
+    let listener = response.metadata().get(LISTENER_HEADER).expect("no listener id").to_str().unwrap().parse().unwrap();
+    let mut flows = response.into_inner();
+    switched_at(&mut flows, 1, Encoding::F32, Encoding::F32).await;
+    let to_opus = switch(&mut client, listener, Codec::Opus, SampleFormat::F32).await;
+    switched_at(&mut flows, to_opus, Encoding::F32, Encoding::Opus).await;
+    let to_i16 = switch(&mut client, listener, Codec::Raw, SampleFormat::I16).await;
+    let frame = switched_at(&mut flows, to_i16, Encoding::Opus, Encoding::I16).await;
+    assert_eq!(frame.payload.len(), 2 * 1000, "not a whole package of i16");
+}
+
+#[tokio::test]
+async fn unknown_listeners_are_not_found() {
+    let mut client = connect(&[]).await;
+    let request = CodecSwitch { listener: 42, codec: Codec::Opus.into(), ..Default::default() };
+    let status = client.set_codec(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound, "{:?}", status);
+}
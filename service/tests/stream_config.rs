@@ -0,0 +1,26 @@
+//! Checks that GetStreamConfig reports the device formats, and the negotiated one once there is.
+
+use sf_core::sound_flow::{AudioFormat, Codec, SampleFormat};
+
+use common::{connect, format};
+
+mod common;
+
+#[tokio::test]
+async fn reports_the_device_formats() {
+    let mut client = connect(&[]).await;
+    let config = client.get_stream_config(()).await.unwrap().into_inner();
+    assert_eq!(config.capture, Some(format()));
+    assert_eq!(config.playback, Some(format()));
+    assert_eq!(config.negotiated, None);
+}
+
+#[tokio::test]
+async fn reports_the_negotiated_format() {
+    let mut client = connect(&[]).await;
+    let mono = AudioFormat { sample_rate: 16000, channels: 1, sample_format: SampleFormat::I16.into(), codec: Codec::Raw.into() };
+    client.negotiate_format(mono.clone()).await.unwrap();
+    let config = client.get_stream_config(()).await.unwrap().into_inner();
+    assert_eq!(config.negotiated, Some(mono));
+    assert_eq!(config.playback, Some(format()), "the devices don't change for a sender");
+}
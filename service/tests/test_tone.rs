@@ -0,0 +1,57 @@
+//! Plays test tones on the virtual loopback device, which captures them right back, and checks
+//! the tone arrives at its frequency and level, and that impossible tones are refused.
+
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+use tonic::Code;
+
+use sf_core::sound_flow::{FlowRequest, TestTone};
+
+use common::{connect, CHANNELS, SAMPLE_RATE, TIMEOUT};
+
+mod common;
+
+#[tokio::test]
+async fn a_test_tone_comes_back_at_its_frequency() {
+    let client = connect(&[]).await;
+    let mut flows = client.clone().get_flow(FlowRequest::default()).await.unwrap().into_inner();
+    tokio::time::sleep(Duration::from_millis(100)).await; // let the listener subscribe first
+    let tone = TestTone { frequency_hz: 1000.0, amplitude: 0.25, duration_ms: 500 };
+    let playing = tokio::spawn({
+        let mut client = client.clone();
+        async move { client.play_test_tone(tone).await }
+    });
+    let len = SAMPLE_RATE as usize * CHANNELS / 2;
+    let mut heard = Vec::new();
+    tokio::time::timeout(TIMEOUT, async {
+        while heard.len() < len {
+            let samples = flows.next().await.unwrap().unwrap().flow;
+            let started = !heard.is_empty();
+            heard.extend(samples.into_iter().skip_while(|&sample| !started && sample == 0.0));
+        }
+    }).await.expect("timed out waiting for the tone");
+    playing.await.unwrap().expect("the tone failed");
+
+    let left: Vec<f32> = heard[..len].chunks(CHANNELS).map(|frame| frame[0]).collect();
+    let peak = left.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!((peak - 0.25).abs() < 0.01, "the tone peaked at {} instead of 0.25", peak);
+    let crossings = left.windows(2).filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0)).count();
+    assert!((995..=1005).contains(&crossings), "{} zero crossings in 500 ms of a 1 kHz tone", crossings);
+}
+
+#[tokio::test]
+async fn impossible_tones_are_refused() {
+    let client = connect(&[]).await;
+    let refused = [
+        TestTone { frequency_hz: 10.0, amplitude: 0.5, duration_ms: 100 },
+        TestTone { frequency_hz: f32::NAN, amplitude: 0.5, duration_ms: 100 },
+        TestTone { frequency_hz: 440.0, amplitude: 1.5, duration_ms: 100 },
+        TestTone { frequency_hz: 440.0, amplitude: 0.5, duration_ms: 0 },
+        TestTone { frequency_hz: 440.0, amplitude: 0.5, duration_ms: 60_000 },
+    ];
+    for tone in refused {
+        let status = client.clone().play_test_tone(tone.clone()).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument, "{:?} wasn't refused as invalid", tone);
+    }
+}
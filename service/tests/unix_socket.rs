@@ -0,0 +1,81 @@
+//! Serving on a Unix domain socket with --socket: calls over it, the permissions it gets, and
+//! what happens to a socket file someone else left there.
+
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+
+use sf_core::sound_flow::sound_flow_client::SoundFlowClient;
+use sf_core::{bind_socket, Config};
+
+use common::{format, TIMEOUT};
+
+mod common;
+
+/// A socket path of its own for every test, so they can run side by side.
+fn socket(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("sf_core-{}-{}.sock", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Serves on the virtual loopback device at `path`, with permissions `mode`.
+fn serve(path: &Path, mode: u32) {
+    let arg = path.to_str().unwrap();
+    let config = Config::parse_from(["sf_core", "--socket", arg, "--no-vad", "--fade-ms", "0"]);
+    let listener = bind_socket(path, mode).unwrap();
+    tokio::spawn(async move { sf_core::serve_loopback(config, format(), listener).await.unwrap() });
+}
+
+async fn connect(path: &Path) -> SoundFlowClient<Channel> {
+    let path = path.to_path_buf();
+    let connector = tower::service_fn(move |_: Uri| UnixStream::connect(path.clone()));
+    let channel = Endpoint::from_static("http://[::]:50051").connect_with_connector(connector).await.unwrap();
+    SoundFlowClient::new(channel)
+}
+
+#[tokio::test]
+async fn calls_answer_over_the_socket() {
+    let path = socket("calls");
+    serve(&path, 0o600);
+    let mut client = connect(&path).await;
+    let info = tokio::time::timeout(TIMEOUT, client.get_server_info(())).await.unwrap().unwrap().into_inner();
+    assert_eq!(info.advertised_address, format!("unix:{}", path.display()));
+    assert!(!info.tls, "the socket is served without TLS, --plaintext or not");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn the_socket_gets_the_mode_asked_for() {
+    for (name, mode) in [("owner", 0o600), ("group", 0o660)] {
+        let path = socket(name);
+        serve(&path, mode);
+        let permissions = std::fs::metadata(&path).unwrap().permissions();
+        assert_eq!(permissions.mode() & 0o777, mode, "{}", name);
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+#[tokio::test]
+async fn a_live_socket_is_left_alone() {
+    let path = socket("live");
+    serve(&path, 0o600);
+    let error = bind_socket(&path, 0o600).err().expect("bound a socket another server answers on");
+    assert!(error.to_string().contains("already listening"), "{:#}", error);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn a_stale_socket_is_replaced() {
+    let path = socket("stale");
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap()); // the file outlives its listener
+    serve(&path, 0o600);
+    let mut client = connect(&path).await;
+    tokio::time::timeout(TIMEOUT, client.get_server_info(())).await.unwrap().unwrap();
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,56 @@
+//! Watches the ring fill levels while nothing and then a burst of frames goes through a server
+//! on the virtual loopback device.
+
+use std::time::{Duration, Instant};
+
+use tokio_stream::StreamExt;
+use tonic::Code;
+
+use sf_core::sound_flow::{BufferWatch, Flow};
+
+use common::{connect, TIMEOUT};
+
+mod common;
+
+const PACKAGE_SIZE: usize = 1000; // the server's default --package-size
+
+#[tokio::test]
+async fn levels_come_at_the_interval_asked_for() {
+    let mut client = connect(&[]).await;
+    let started = Instant::now();
+    let mut levels = client.watch_buffers(BufferWatch { interval_ms: 50 }).await.unwrap().into_inner();
+    for _ in 0..5 {
+        let level = tokio::time::timeout(TIMEOUT, levels.next()).await.expect("timed out waiting for levels").unwrap().unwrap();
+        assert!((0.0..=100.0).contains(&level.capture_fill_percent), "capture ring {}% full", level.capture_fill_percent);
+        assert!((0.0..=100.0).contains(&level.playback_fill_percent), "playback lane {}% full", level.playback_fill_percent);
+    }
+    // The first comes straight away, the others an interval apart.
+    assert!(started.elapsed() >= Duration::from_millis(190), "5 levels came in {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn intervals_out_of_range_are_refused() {
+    let mut client = connect(&[]).await;
+    for interval_ms in [5, 20_000] {
+        let status = client.watch_buffers(BufferWatch { interval_ms }).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument, "interval_ms {} was {:?}", interval_ms, status);
+    }
+}
+
+#[tokio::test]
+async fn a_burst_fills_the_playback_lane() {
+    // A sender that waits for room keeps its lane full drain after drain.
+    let client = connect(&["--ring-capacity", "4", "--overflow", "block"]).await;
+    let mut levels = client.clone().watch_buffers(BufferWatch { interval_ms: 10 }).await.unwrap().into_inner();
+    let watching = tokio::spawn(async move {
+        while let Some(level) = levels.next().await {
+            if level.unwrap().playback_fill_percent >= 50.0 {
+                return;
+            }
+        }
+    });
+    // Far faster than real time, so the lane fills up between two drains.
+    let burst: Vec<Flow> = (1..=40).map(|seq| Flow { flow: vec![0.1; PACKAGE_SIZE], seq, ..Default::default() }).collect();
+    client.clone().send_flow(tokio_stream::iter(burst)).await.unwrap();
+    tokio::time::timeout(TIMEOUT, watching).await.expect("the burst never showed in the playback lane").unwrap();
+}
@@ -0,0 +1,63 @@
+//! Calls from a browser over grpc-web, made by hand since they are plain HTTP/1.1, against a
+//! server with --web-origin.
+
+#![cfg(feature = "web")]
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use common::serve;
+
+mod common;
+
+const ORIGIN: &str = "http://localhost:8080";
+const PATH: &str = "/sound_flow.SoundFlow/GetServerInfo";
+
+/// Sends `request` with `headers` and `body` to the server at `url`, and returns the whole
+/// response, or what came of it before the server hung up.
+async fn exchange(url: &str, request: &str, headers: &[&str], body: &[u8]) -> String {
+    let addr = url.trim_start_matches("http://");
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut head = format!("{} {} HTTP/1.1\r\nhost: {}\r\nconnection: close\r\ncontent-length: {}\r\n", request, PATH, addr, body.len());
+    headers.iter().for_each(|header| head += &format!("{}\r\n", header));
+    head += "\r\n";
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await; // a server only speaking HTTP/2 hangs up
+    String::from_utf8_lossy(&response).to_lowercase()
+}
+
+async fn preflight(url: &str, origin: &str) -> String {
+    let origin = format!("origin: {}", origin);
+    exchange(url, "OPTIONS", &[&origin, "access-control-request-method: POST", "access-control-request-headers: x-grpc-web,content-type,authorization"], &[]).await
+}
+
+#[tokio::test]
+async fn the_allowed_origin_passes_the_preflight() {
+    let url = serve(&["--web-origin", ORIGIN]).await;
+    let response = preflight(&url, ORIGIN).await;
+    assert!(response.contains(&format!("access-control-allow-origin: {}", ORIGIN)), "{}", response);
+    assert!(response.contains("authorization"), "the token can't be sent: {}", response);
+    let response = preflight(&url, "http://elsewhere.example").await;
+    assert!(!response.contains("access-control-allow-origin"), "{}", response);
+}
+
+#[tokio::test]
+async fn unary_calls_answer_over_grpc_web() {
+    let url = serve(&["--web-origin", ORIGIN]).await;
+    let origin = format!("origin: {}", ORIGIN);
+    // An empty message: uncompressed, zero bytes long.
+    let response = exchange(&url, "POST", &[&origin, "content-type: application/grpc-web+proto", "x-grpc-web: 1"], &[0, 0, 0, 0, 0]).await;
+    assert!(response.starts_with("http/1.1 200"), "{}", response);
+    assert!(response.contains("content-type: application/grpc-web+proto"), "{}", response);
+    assert!(response.contains("grpc-status:0"), "the call failed: {}", response);
+}
+
+#[tokio::test]
+async fn grpc_web_is_off_without_an_origin() {
+    let url = serve(&[]).await;
+    let origin = format!("origin: {}", ORIGIN);
+    let response = exchange(&url, "POST", &[&origin, "content-type: application/grpc-web+proto", "x-grpc-web: 1"], &[0, 0, 0, 0, 0]).await;
+    assert!(!response.contains("grpc-status:0"), "{}", response);
+}